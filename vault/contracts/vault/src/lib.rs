@@ -1,5 +1,200 @@
 #![no_std] // No standard library for embedded-like environments
-use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env};
+use soroban_sdk::{contract, contracterror, contractimpl, contracttype, symbol_short, token, Address, Bytes, BytesN, Env, IntoVal, Map, Symbol, Vec};
+
+// Storage TTL settings, in ledgers. A ledger closes roughly every 5 seconds, so a day is about 17280 ledgers.
+// Every state-changing call extends the instance entry back to INSTANCE_BUMP_AMOUNT once its remaining TTL
+// drops below INSTANCE_LIFETIME_THRESHOLD, so an active vault can never be archived with funds inside.
+pub const DAY_IN_LEDGERS: u32 = 17280;
+pub const INSTANCE_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+pub const INSTANCE_LIFETIME_THRESHOLD: u32 = INSTANCE_BUMP_AMOUNT - DAY_IN_LEDGERS;
+// Locked balances live in persistent storage so they survive long lock periods. Each write extends
+// the entry to PERSISTENT_BUMP_AMOUNT once its remaining TTL drops below PERSISTENT_LIFETIME_THRESHOLD.
+pub const PERSISTENT_BUMP_AMOUNT: u32 = 90 * DAY_IN_LEDGERS;
+pub const PERSISTENT_LIFETIME_THRESHOLD: u32 = PERSISTENT_BUMP_AMOUNT - DAY_IN_LEDGERS;
+
+// The version of this code. Bump it with any release that changes behaviour clients can observe;
+// initialize records it in storage, and migrate brings the stored version up to it after an upgrade.
+pub const VERSION: u32 = 1;
+
+// The furthest into the future, in seconds from the current ledger time, any unlock can be set (about 10 years).
+// This catches typos like a timestamp in milliseconds, which would otherwise lock funds for millennia.
+pub const MAX_LOCK_DURATION: u64 = 10 * 365 * 24 * 60 * 60;
+
+// The length, in seconds, of the rolling window the owner's withdrawal limit applies to.
+pub const WITHDRAWAL_WINDOW: u64 = 24 * 60 * 60;
+
+// Reward rates are fixed-point: a depositor earns `balance * reward_rate / REWARD_RATE_SCALE` tokens per second.
+// A rate of REWARD_RATE_SCALE therefore pays one token per locked token per second.
+pub const REWARD_RATE_SCALE: i128 = 1_000_000_000;
+
+// The seconds in the 365-day year current_apr annualizes the per-second reward rate over.
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+// The reason codes carried by `bal_chg` events, saying why a depositor's balance changed.
+pub const BALANCE_REASON_DEPOSIT: u32 = 1;
+pub const BALANCE_REASON_WITHDRAW: u32 = 2;
+
+// The most depositor records one close call deletes. A vault with more past and present depositors than this
+// is closed over several calls, so no single call runs out of resources however many depositors it had.
+pub const CLOSE_BATCH_SIZE: u32 = 50;
+
+// Optional features configured once at initialize.
+// Bundling them in one struct keeps initialize's parameter list short as features are added;
+// each field is still stored under its own DataKey.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VaultOptions {
+    pub vesting_start: u64,         // The timestamp at which linear vesting of the owner's withdrawals begins
+    pub vesting_duration: u64,      // The length of the vesting window in seconds; 0 disables vesting
+    pub cliff_timestamp: u64,       // Nothing vests before this time; must not be after vesting_start + vesting_duration
+    pub penalty_bps: u32,           // The emergency withdrawal penalty in basis points; 0 disables emergency withdrawals
+    pub penalty_recipient: Address, // The address that receives the penalty cut of emergency withdrawals
+    pub fee_bps: u32,               // The fee in basis points taken from each owner withdrawal; 0 means no fee
+    pub fee_collector: Address,     // The address that receives withdrawal fees, and deposit fees unless they are retained
+    pub max_total_deposit: i128,    // The most the vault may hold in total; 0 means unlimited
+    pub min_deposit: i128,          // The smallest amount a single deposit may be; 0 means no floor
+    pub inactivity_window: u64,     // Seconds without an owner action before the beneficiary may withdraw
+    pub reward_rate: u64,           // Rewards per locked token per second, scaled by REWARD_RATE_SCALE; 0 disables rewards
+    pub withdrawal_destination: Option<Address>, // If set, the only address owner-side withdrawals may pay out to
+    pub grace_period: u64,          // Extra seconds after the unlock timestamp before withdrawals actually open
+    pub min_withdrawal: i128,       // The smallest amount a single withdraw may be, unless less than this remains; 0 means no floor
+    pub admin: Option<Address>,     // The address that controls pause, upgrade, and the allow/block lists; None leaves that to the owner
+    pub notify_contract: Option<Address>, // A contract whose on_withdraw(vault, to, amount) is called after each withdraw
+    pub notify_strict: bool,        // Whether a failing on_withdraw reverts the withdrawal instead of being ignored
+    pub deposit_cooldown: u64,      // The seconds each depositor must wait between deposits; 0 means no wait
+    pub credit_received: bool,      // Whether single deposits credit what a fee-on-transfer token delivers, not what was sent
+    pub payout_split: Vec<(Address, u32)>, // The (recipient, bps) shares distribute pays out, summing to 10000; empty disables distribute
+    pub allow_force_unlock: bool,   // Whether the owner may call force_unlock to open the vault ahead of schedule
+    pub recovery_address: Option<Address>, // An address that may call recovery_withdraw once the owner has gone quiet
+    pub recovery_delay: u64,        // Seconds without an owner action before the recovery address may withdraw
+    pub auto_extend_seconds: u64,   // If nonzero, every deposit pushes the unlock out to at least now + this many seconds
+    pub action_delay: u64,          // The least seconds a queued admin action must wait; nonzero also puts upgrade behind the queue
+    pub deposit_open_from: u64,     // Deposits are refused before this timestamp; 0 means from the start
+    pub deposit_open_until: u64,    // Deposits are refused after this timestamp; 0 means open indefinitely
+    pub min_hold_seconds: u64,      // Seconds after a depositor's latest own deposit before withdraw_own may pay them; 0 means none
+    pub balance_events: bool,       // Whether every depositor balance change also emits a unified `bal_chg` event
+    pub oracle: Option<Address>,    // An address whose set_unlock_condition must also report true before the owner can withdraw
+    pub deposit_fee_bps: u32,       // A fee in basis points, below 10000, taken from each primary-token deposit before it is credited
+    pub deposit_fee_retained: bool, // Whether deposit fees stay locked in the vault for the owner instead of going to the fee collector
+    pub label: Option<Symbol>,      // A human-readable name for telling vaults apart; informational only
+    pub signers: Vec<Address>,      // The distinct addresses whose approvals owner withdrawals need; empty leaves them to the owner
+    pub signer_threshold: u32,      // How many signers must approve each withdrawal, from 1 up to the signer count; 0 without signers
+}
+
+// An owner withdrawal waiting for approval from the vault's signers, as returned by get_proposal.
+// It executes as soon as `threshold` of the current signers appear in `approvals`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalProposal {
+    pub to: Address,              // The address the withdrawal pays out to
+    pub amount: i128,             // The amount of locked tokens to withdraw
+    pub approvals: Vec<Address>,  // The distinct signers that have approved so far, the proposer first
+}
+
+// A snapshot of the vault's core state, returned by get_state so clients can read everything in one call.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VaultState {
+    pub owner: Option<Address>, // The vault's owner, or None once ownership has been renounced
+    pub token_id: Address,     // The token contract the vault holds
+    pub unlock_timestamp: u64, // The ledger close time after which funds can be withdrawn
+    pub locked_amount: i128,   // The total amount of tokens currently locked
+}
+
+// The vault's configuration, mostly fixed at initialize, returned by get_config alongside get_state's dynamic view.
+// Roles (owner, admin, beneficiary) and lists (allowed tokens, depositors, blocklist) have their own getters.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VaultConfig {
+    pub token_id: Address,          // The token contract the vault holds
+    pub unlock_timestamp: u64,      // The ledger close time after which funds can be withdrawn; extend_lock can move it later
+    pub grace_period: u64,          // Extra seconds after the unlock timestamp before withdrawals actually open
+    pub vesting_start: u64,         // The timestamp at which linear vesting of the owner's withdrawals begins
+    pub vesting_duration: u64,      // The length of the vesting window in seconds; 0 means vesting is disabled
+    pub cliff_timestamp: u64,       // Nothing vests before this time
+    pub penalty_bps: u32,           // The emergency withdrawal penalty in basis points; 0 means emergency withdrawals are disabled
+    pub penalty_recipient: Address, // The address that receives the penalty cut of emergency withdrawals
+    pub fee_bps: u32,               // The fee in basis points taken from each owner withdrawal
+    pub fee_collector: Address,     // The address that receives withdrawal fees, and deposit fees unless they are retained
+    pub deposit_fee_bps: u32,       // The fee in basis points taken from each primary-token deposit
+    pub deposit_fee_retained: bool, // Whether deposit fees stay locked in the vault instead of going to the fee collector
+    pub max_total_deposit: i128,    // The most the vault may hold in total; 0 means unlimited
+    pub min_deposit: i128,          // The smallest amount a single deposit may be; 0 means no floor
+    pub min_withdrawal: i128,       // The smallest amount a single withdraw may be; 0 means no floor
+    pub deposit_cooldown: u64,      // The seconds each depositor must wait between deposits
+    pub inactivity_window: u64,     // Seconds without an owner action before the beneficiary may withdraw
+    pub recovery_delay: u64,        // Seconds without an owner action before the recovery address may withdraw
+    pub auto_extend_seconds: u64,   // The rolling lock term each deposit renews; 0 means deposits leave the unlock alone
+    pub action_delay: u64,          // The least seconds an admin action must sit in the queue before it can execute
+    pub deposit_open_from: u64,     // The timestamp deposits open at
+    pub deposit_open_until: u64,    // The timestamp deposits close after; 0 means they never close
+    pub min_hold_seconds: u64,      // The least seconds each depositor must hold after their latest deposit
+    pub reward_rate: u64,           // Rewards per locked token per second, scaled by REWARD_RATE_SCALE
+    pub withdrawal_destination: Option<Address>, // The only address owner-side withdrawals may pay out to, if fixed
+    pub credit_received: bool,      // Whether single deposits credit what a fee-on-transfer token delivers
+    pub allow_force_unlock: bool,   // Whether the owner may call force_unlock
+    pub balance_events: bool,       // Whether depositor balance changes also emit `bal_chg` events
+    pub oracle: Option<Address>,    // The address reporting the external unlock condition, if any
+}
+
+// Define the contract's error codes.
+// Returning these from contract functions (instead of panicking with a string) lets callers
+// match on the exact failure and keeps string data out of the compiled wasm.
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum VaultError {
+    AlreadyInitialized = 1, // initialize was called on a vault that is already set up
+    NotInitialized = 2,     // A required storage key is missing because initialize was never called
+    Unauthorized = 3,       // The caller is not allowed to perform this action
+    NonPositiveAmount = 4,  // A deposit or withdrawal amount was zero or negative
+    StillLocked = 5,        // The unlock timestamp has not been reached yet
+    InsufficientFunds = 6,  // The vault does not hold enough locked tokens for the withdrawal
+    Overflow = 7,           // An arithmetic update of the locked amount overflowed or underflowed
+    NoPendingOwner = 8,     // accept_ownership was called without a pending ownership transfer
+    Paused = 9,             // The vault is paused and is not accepting deposits or withdrawals
+    LockNotExtended = 10,   // extend_lock was given a timestamp that isn't later than the current unlock time
+    NotYetVested = 11,      // The withdrawal exceeds the portion of the vault that has vested so far
+    InvalidCliff = 12,      // The cliff timestamp falls after the end of the vesting window
+    InvalidSchedule = 13,   // A tranche schedule is empty, unsorted, or has a non-positive amount, or a deposit window closes before it opens
+    AlreadyUnlocked = 14,   // An early-exit path was used after the unlock timestamp; use withdraw instead
+    InvalidBasisPoints = 15, // A basis-point rate is above 10000 (100%), or a payout split doesn't add up to 10000
+    EmergencyWithdrawDisabled = 16, // The vault was initialized without an emergency withdrawal penalty
+    CapExceeded = 17,       // The deposit would push the locked total above the vault's deposit cap
+    BelowMinimum = 18,      // The deposit is smaller than the vault's minimum deposit
+    Reentrancy = 19,        // A token-moving function was re-entered while another one was still running
+    NoBeneficiary = 20,     // beneficiary_withdraw was called but no beneficiary has been set
+    OwnerStillActive = 21,  // The owner acted within the inactivity window, so the beneficiary must wait
+    UnlockBeforeVault = 22, // A personal unlock time was earlier than the vault's own unlock timestamp
+    TokenNotAllowed = 23,   // The token is neither the primary token nor on the owner's whitelist
+    OwnershipRenounced = 24, // The owner renounced ownership, so owner-only functions can never succeed again
+    InsufficientRewards = 25, // The reward pool can't cover the rewards a depositor is owed
+    InvalidAddress = 26,    // initialize was given an owner or token address that would be self-referential
+    UnlockInPast = 27,      // initialize was given an unlock timestamp earlier than the current ledger time
+    LockTooLong = 28,       // An unlock timestamp was more than MAX_LOCK_DURATION in the future
+    DestinationNotAllowed = 29, // A withdrawal tried to pay an address other than the fixed withdrawal destination
+    LimitExceeded = 30,     // The withdrawal would take the current window's total above the withdrawal limit
+    InvalidLimit = 31,      // set_withdrawal_limit was given a negative limit
+    BelowMinimumWithdrawal = 32, // The withdrawal is below the minimum and more than the minimum is still held
+    DepositorNotAllowed = 33, // The depositor allowlist is on and the depositor isn't on it
+    AddressBlocked = 34,      // The depositor or withdrawal destination is on the owner's blocklist
+    AlreadyMigrated = 35,     // The vault's storage is already at this code's version
+    InvalidUnlockCurve = 36,  // An unlock curve is empty, out of bounds, not monotonic, or has no vesting window to shape
+    VestingStarted = 37,      // The vesting window has already begun, so its shape can no longer change
+    DepositTooSoon = 38,      // The depositor deposited less than the deposit cooldown ago
+    TransferAmountMismatch = 39, // The vault's token balance grew by a different amount than was transferred in
+    VaultNotEmpty = 40,       // close or sweep was called while the vault still holds locked, free, or reward tokens (or an NFT)
+    NoPayoutSplit = 41,       // distribute was called on a vault initialized without a payout split
+    ForceUnlockDisabled = 42, // force_unlock was called on a vault initialized without allow_force_unlock
+    NoRecoveryAddress = 43,   // recovery_withdraw was called on a vault initialized without a recovery address
+    NftNotLocked = 44,        // withdraw_nft named an NFT the vault isn't holding
+    InvalidSigners = 45,      // initialize was given duplicate signers or a threshold of 0 or more than the signer count
+    ProposalNotFound = 46,    // A withdrawal proposal or request id was never issued or has already been paid out
+    ActionNotQueued = 47,     // An admin action was executed without having been queued first
+    ActionNotReady = 48,      // A queued admin action's delay hasn't elapsed, or it was queued for too soon
+    DepositWindowClosed = 49, // A deposit was made before the deposit window opened or after it closed
+    SnapshotTaken = 50,       // snapshot was called again after the balance snapshot had already been taken
+}
 
 // Define the contract's storage keys.
 // This enum helps organize the persistent data stored on the blockchain.
@@ -8,138 +203,3678 @@ pub enum DataKey {
     Owner,           // The Address of the vault's owner
     TokenId,         // The Address of the token contract this vault holds
     UnlockTimestamp, // The u64 timestamp (ledger close time) when tokens can be withdrawn
-    LockedAmount,    // The i128 total amount of tokens currently locked in the vault
+    LockedAmount,    // The i128 total amount of tokens currently locked in the vault (persistent storage)
+    PendingOwner,    // The Address nominated by the owner that still has to accept ownership
+    Balance(Address), // The i128 amount of tokens contributed by a given depositor (persistent storage)
+    Paused,          // The bool circuit-breaker flag; while true, deposits and withdrawals are rejected
+    VestingStart,    // The u64 timestamp at which linear vesting begins
+    VestingDuration, // The u64 length of the vesting window in seconds; 0 disables vesting
+    Withdrawn,       // The i128 total amount the owner has withdrawn to date, used for the vesting cap
+    CliffTimestamp,  // The u64 timestamp before which nothing vests, even if vesting has started
+    Schedule,        // The Vec<(u64, i128)> of (unlock_timestamp, amount) tranches for stepped releases
+    PenaltyBps,      // The u32 penalty, in basis points, charged on emergency withdrawals before unlock
+    PenaltyRecipient, // The Address that receives the penalty cut of emergency withdrawals
+    FeeBps,          // The u32 withdrawal fee in basis points
+    FeeCollector,    // The Address that receives withdrawal fees
+    MaxTotalDeposit, // The i128 cap on the total locked amount; 0 means unlimited
+    MinDeposit,      // The i128 smallest amount accepted by a single deposit
+    Version,         // The u32 VERSION of the code that last initialized or migrated the vault's storage
+    Locked,          // The bool reentrancy guard; true while a call is transferring tokens
+    Beneficiary,     // The Address allowed to withdraw if the owner goes inactive
+    InactivityWindow, // The u64 seconds the owner must be inactive before the beneficiary can withdraw
+    LastOwnerAction, // The u64 timestamp of the owner's most recent authorized call
+    LastActivity,    // The u64 timestamp of the most recent initialize, deposit, or withdrawal
+    DepositUnlock(Address), // The u64 personal unlock time a depositor chose (stored in persistent storage)
+    Depositors,      // The Vec<Address> of depositors with a non-zero balance (persistent storage)
+    AllowedToken(Address), // Present (as true) for each whitelisted token besides the primary TokenId
+    TokenBalance(Address), // The i128 amount held of a non-primary token (persistent storage)
+    Initialized,     // The bool set once initialize has completed
+    RelockedAmount,  // The i128 portion of the locked amount the owner relocked after unlock
+    RelockUnlock,    // The u64 timestamp after which the relocked portion can be withdrawn again
+    RewardRate,      // The u64 per-second reward rate, scaled by REWARD_RATE_SCALE
+    RewardPool,      // The i128 amount of tokens the owner has set aside for rewards
+    WithdrawalDestination, // The Address all owner-side withdrawals must pay out to, if fixed at initialize
+    MinWithdrawal,   // The i128 smallest amount a single withdraw may be; 0 means no floor
+    GracePeriod,     // The u64 seconds after UnlockTimestamp before withdrawals open
+    DepositSeq,      // The u64 number of deposit events emitted so far
+    WithdrawSeq,     // The u64 number of withdraw events emitted so far
+    FreeAmount,      // The i128 portion of partial deposits that isn't time-locked (persistent storage)
+    WithdrawalLimit, // The i128 most the owner may withdraw per WITHDRAWAL_WINDOW; 0 means unlimited
+    WithdrawnToday,  // The i128 amount withdrawn so far in the current limit window
+    WindowStart,     // The u64 timestamp the current limit window began
+    AccruedRewards(Address), // The i128 rewards a depositor has banked but not claimed (persistent storage)
+    RewardCheckpoint(Address), // The u64 timestamp a depositor's rewards were last settled (persistent storage)
+    DepositorAllowlist, // The bool set while only allowlisted addresses may deposit
+    AllowedDepositor(Address), // Present (as true) for each address on the depositor allowlist (persistent storage)
+    Admin,           // The Address that administers the vault (pause, upgrade, allow/block lists), if separate from the owner
+    UnlockCurve,     // The Vec<(u32, u32)> of (elapsed_fraction_bps, unlocked_fraction_bps) points shaping vesting
+    Blocked(Address), // Present (as true) for each address barred from depositing or receiving withdrawals (persistent storage)
+}
+
+// Storage keys for features added once DataKey neared the 50 variants Soroban allows in a contracttype enum.
+// Keys are encoded by variant name alone, so no name here may repeat one from DataKey.
+#[contracttype]
+pub enum DataKeyExt {
+    NotifyContract,  // The Address whose on_withdraw is called after each primary-token withdraw, if set
+    NotifyStrict,    // The bool set if a failing on_withdraw callback should revert the withdrawal
+    DepositCooldown, // The u64 seconds a depositor must wait between deposits; 0 means no wait
+    CreditReceived,  // The bool set if deposits credit the amount actually received rather than the amount sent
+    PayoutSplit,     // The Vec<(Address, u32)> of (recipient, bps) shares distribute pays out, if configured
+    TotalDeposited,  // The i128 sum of every primary-token deposit ever made
+    TotalWithdrawn,  // The i128 sum of every primary-token withdrawal ever made, fees and penalties included
+    Closed,          // The bool set once the owner has closed the vault, so it can never be initialized again
+    AllowForceUnlock, // The bool set if the owner may force the vault open before its unlock timestamp
+    RecoveryAddress, // The Address allowed to call recovery_withdraw, if configured
+    RecoveryDelay,   // The u64 seconds the owner must be inactive before the recovery address may withdraw
+    AutoExtendSeconds, // The u64 rolling lock term every deposit renews; 0 means deposits don't move the unlock
+    LastDeposit(Address), // The u64 timestamp of a depositor's latest deposit, kept only with a cooldown (persistent storage)
+    LockedNft(Address, u128), // The Address that deposited each (nft_contract, token_id) the vault holds (persistent storage)
+    LockedNftCount,  // The u32 number of NFTs the vault currently holds
+    InstanceLiveUntil, // The u32 ledger the vault last extended its instance entry to; see extend_instance_ttl
+    Signers,         // The Vec<Address> whose approvals owner withdrawals need, if multi-signer withdrawals are on
+    SignerThreshold, // The u32 number of distinct signers that must approve a withdrawal proposal
+    ProposalSeq,     // The u64 id of the latest withdrawal proposal
+    Proposal(u64),   // The pending WithdrawalProposal with this id, removed once it executes (persistent storage)
+    ActionDelay,     // The u64 least seconds between queueing an admin action and executing it
+    QueuedActions,   // The Map<BytesN<32>, u64> of queued admin action ids to the time each may execute after
+    AccruedFees,     // The i128 primary-token withdrawal fees held for the fee collector, outside the locked amount
+    DepositOpenFrom, // The u64 timestamp the deposit window opens at
+    DepositOpenUntil, // The u64 timestamp the deposit window closes after; 0 means it never closes
+    MinHoldSeconds,  // The u64 seconds a depositor must hold after their latest deposit before withdraw_own
+    LastCredit(Address), // The u64 timestamp a depositor's balance was last credited, kept only with a minimum hold (persistent storage)
+    BalanceEvents,   // The bool set if depositor balance changes also emit `bal_chg` events
+    SnapshotTime,    // The u64 timestamp the balance snapshot was taken at, once it has been
+    Snapshot(Address), // The i128 balance a depositor held when the snapshot was taken, if positive (persistent storage)
+    PendingWithdrawals, // The i128 total of withdrawal requests queued but not yet fulfilled
+    WithdrawalRequestSeq, // The u64 id of the latest withdrawal request
+    WithdrawalRequest(u64), // The (to, amount) of a queued withdrawal request, removed once fulfilled (persistent storage)
+    ProcessedRequest(Address, BytesN<32>), // The i128 result of a depositor's deposit_idempotent call with this id (persistent storage)
+    Oracle,          // The Address that reports the vault's external unlock condition, if configured
+    OracleUnlockFlag, // The bool the oracle last reported for the unlock condition; unset means not met
+    DepositFeeBps,   // The u32 deposit fee in basis points
+    DepositFeeRetained, // The bool set if deposit fees are added to the owner's locked amount rather than paid to the fee collector
+    Label,           // The Symbol the vault was labelled with at initialize, if any
+    ExitedDepositors, // The Vec<Address> of depositors who have withdrawn everything, so close can still find their records (persistent storage)
+    TotalBalances,   // The i128 sum of every depositor's balance
+    HeldTokenCount,  // The u32 number of non-primary tokens whose tracked TokenBalance is nonzero
+    OpenProposals,   // The Vec<u64> ids of withdrawal proposals that haven't executed yet (persistent storage)
+    OwnWithdrawn(Address), // The i128 total taken out of a depositor's balance, which their vested share is measured against (persistent storage)
+}
+
+// Extends the TTL of the contract instance (and with it all instance storage) so the vault stays live.
+// Contracts can't read an entry's TTL, so the vault records the ledger it extended the instance to for ttl_remaining.
+// The extension always goes all the way to INSTANCE_BUMP_AMOUNT, which keeps that record a lower bound
+// even if the instance was already live for longer, say because someone extended it from outside.
+fn extend_instance_ttl(env: &Env) {
+    let sequence = env.ledger().sequence();
+    let live_until: u32 = env.storage().instance().get(&DataKeyExt::InstanceLiveUntil).unwrap_or(0);
+    if live_until.saturating_sub(sequence) >= INSTANCE_LIFETIME_THRESHOLD {
+        return;
+    }
+    env.storage().instance().extend_ttl(INSTANCE_BUMP_AMOUNT, INSTANCE_BUMP_AMOUNT);
+    env.storage().instance().set(&DataKeyExt::InstanceLiveUntil, &sequence.saturating_add(INSTANCE_BUMP_AMOUNT));
+}
+
+// Increments a deposit or withdrawal sequence counter and returns the new value, starting from 1.
+// The counters never reset, so indexers can use them to order and de-duplicate events.
+fn next_seq(env: &Env, key: &DataKey) -> Result<u64, VaultError> {
+    let seq: u64 = env.storage().instance().get(key).unwrap_or(0);
+    let seq = seq.checked_add(1).ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(key, &seq);
+    Ok(seq)
+}
+
+// Adds to a lifetime deposit or withdrawal total. These only ever grow, so dashboards can show a vault's
+// throughput even once its balance is back to 0.
+fn add_to_lifetime_total(env: &Env, key: &DataKeyExt, amount: i128) -> Result<(), VaultError> {
+    let total: i128 = env.storage().instance().get(key).unwrap_or(0);
+    let total = total.checked_add(amount).ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(key, &total);
+    Ok(())
+}
+
+// Stamps the current ledger time as the vault's last activity, so off-chain monitors can spot stale vaults.
+fn record_activity(env: &Env) {
+    env.storage().instance().set(&DataKey::LastActivity, &env.ledger().timestamp());
+}
+
+// Writes a token amount (the locked total or a depositor's balance) to persistent storage and extends its TTL.
+fn write_persistent_amount(env: &Env, key: &DataKey, amount: i128) {
+    env.storage().persistent().set(key, &amount);
+    env.storage().persistent().extend_ttl(key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+}
+
+// Marks the start of a token-moving call, failing if one is already in progress.
+// The Soroban host already refuses direct contract re-entry; this guard is a second line of defense
+// against a malicious token calling back into the vault mid-transfer.
+// If the call later returns an error, the whole invocation reverts, so the flag never stays set.
+fn enter_reentrancy_guard(env: &Env) -> Result<(), VaultError> {
+    if env.storage().instance().get(&DataKey::Locked).unwrap_or(false) {
+        return Err(VaultError::Reentrancy);
+    }
+    env.storage().instance().set(&DataKey::Locked, &true);
+    Ok(())
+}
+
+// Clears the reentrancy guard once the token-moving call has finished its transfers.
+fn exit_reentrancy_guard(env: &Env) {
+    env.storage().instance().remove(&DataKey::Locked);
+}
+
+// Checks a single deposit amount: it must be positive and at least the operator's minimum deposit.
+fn check_deposit_amount(env: &Env, amount: i128) -> Result<(), VaultError> {
+    if amount <= 0 {
+        return Err(VaultError::NonPositiveAmount);
+    }
+    // Reject dust and spam deposits below the operator's floor.
+    let min_deposit: i128 = env.storage().instance().get(&DataKey::MinDeposit).unwrap_or(0);
+    if amount < min_deposit {
+        return Err(VaultError::BelowMinimum);
+    }
+    Ok(())
+}
+
+// Checks that adding `amount` to the vault would not exceed the deposit cap. A cap of 0 means unlimited.
+fn check_deposit_cap(env: &Env, amount: i128) -> Result<(), VaultError> {
+    if let Some(room) = deposit_room(env)? {
+        if amount > room {
+            return Err(VaultError::CapExceeded);
+        }
+    }
+    Ok(())
+}
+
+// Renews the rolling lock term, if one is configured, so the unlock is at least auto_extend_seconds from now.
+fn auto_extend_unlock(env: &Env) -> Result<(), VaultError> {
+    let auto_extend_seconds: u64 = env.storage().instance().get(&DataKeyExt::AutoExtendSeconds).unwrap_or(0);
+    if auto_extend_seconds == 0 {
+        return Ok(());
+    }
+    extend_unlock_to(env, env.ledger().timestamp().saturating_add(auto_extend_seconds))
+}
+
+// Returns an error if crediting `amount` to `depositor` would overflow any of the totals a deposit updates.
+// Run before any tokens move, so that even a vault crediting what it receives, which transfers before booking,
+// can never pull tokens in and then fail to account for them.
+fn check_deposit_fits(env: &Env, depositor: &Address, amount: i128) -> Result<(), VaultError> {
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+    let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+    let total_deposited: i128 = env.storage().instance().get(&DataKeyExt::TotalDeposited).unwrap_or(0);
+    for total in [locked_amount.checked_add(free_amount).ok_or(VaultError::Overflow)?, balance, total_deposited] {
+        total.checked_add(amount).ok_or(VaultError::Overflow)?;
+    }
+    Ok(())
+}
+
+// Returns how much more the vault may take before hitting its deposit cap, or None if there is no cap.
+fn deposit_room(env: &Env) -> Result<Option<i128>, VaultError> {
+    let cap: i128 = env.storage().instance().get(&DataKey::MaxTotalDeposit).unwrap_or(0);
+    if cap == 0 {
+        return Ok(None);
+    }
+    // The cap covers everything the vault holds for depositors, time-locked or free.
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0);
+    let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+    let total = locked_amount.checked_add(free_amount).ok_or(VaultError::Overflow)?;
+    Ok(Some(cap.saturating_sub(total).max(0)))
+}
+
+// Emits a `bal_chg` event for a depositor's balance moving from `old_balance` to `new_balance`, if the vault was
+// initialized with balance_events. These sit alongside the usual deposit and withdrawal events, giving indexers a
+// single canonical stream to replay balances from.
+fn publish_balance_change(env: &Env, account: &Address, old_balance: i128, new_balance: i128, reason: u32) {
+    if env.storage().instance().get(&DataKeyExt::BalanceEvents).unwrap_or(false) {
+        env.events().publish((symbol_short!("bal_chg"), account.clone()), (old_balance, new_balance, reason));
+    }
+}
+
+// Adds `amount`, paid for by `from`, to a depositor's own balance.
+fn credit_balance(env: &Env, from: &Address, depositor: &Address, amount: i128) -> Result<(), VaultError> {
+    // Nothing to credit, and listing a depositor who still has no balance would list them twice on the next credit.
+    if amount == 0 {
+        return Ok(());
+    }
+    let balance_key = DataKey::Balance(depositor.clone());
+    let balance: i128 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+    // Rewards earned on the old balance are banked before it changes.
+    settle_rewards(env, depositor, balance)?;
+    // A depositor with no balance is either new or returning after withdrawing everything; list them either way.
+    if balance == 0 {
+        list_depositor(env, depositor);
+    }
+    let new_balance = balance.checked_add(amount).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &balance_key, new_balance);
+    add_to_total_balances(env, amount)?;
+    publish_balance_change(env, depositor, balance, new_balance, BALANCE_REASON_DEPOSIT);
+    // Each of the depositor's own deposits restarts their minimum hold. A sponsor's deposit_for doesn't, or anyone
+    // could keep a depositor locked out by crediting them a token just before their hold ends.
+    if from == depositor && env.storage().instance().get::<_, u64>(&DataKeyExt::MinHoldSeconds).unwrap_or(0) > 0 {
+        let key = DataKeyExt::LastCredit(depositor.clone());
+        env.storage().persistent().set(&key, &env.ledger().timestamp());
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    }
+    Ok(())
+}
+
+// Returns the sum of every depositor's balance: how much of what the vault holds is attributed to someone.
+fn total_balances(env: &Env) -> i128 {
+    env.storage().instance().get(&DataKeyExt::TotalBalances).unwrap_or(0)
+}
+
+// Adds `delta`, which is negative when balances go down, to the sum of every depositor's balance.
+fn add_to_total_balances(env: &Env, delta: i128) -> Result<(), VaultError> {
+    let total = total_balances(env).checked_add(delta).ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(&DataKeyExt::TotalBalances, &total);
+    Ok(())
+}
+
+// Returns how much of the depositors' balances belongs to someone other than the owner. Owner-side withdrawals of
+// locked tokens leave this much of the locked amount alone, so those depositors can always take it out with withdraw_own.
+fn others_balances(env: &Env) -> i128 {
+    let owner_balance: i128 = match env.storage().instance().get::<_, Address>(&DataKey::Owner) {
+        Some(owner) => env.storage().persistent().get(&DataKey::Balance(owner)).unwrap_or(0),
+        None => 0,
+    };
+    total_balances(env).saturating_sub(owner_balance).max(0)
+}
+
+// Takes an owner-side withdrawal of `amount` locked or free tokens off the owner's own balance wherever the tokens
+// nobody is credited with can't cover it, so the owner can't withdraw_own it again.
+// Must run before the withdrawal comes off the locked amount or the free portion.
+fn debit_owner_share(env: &Env, amount: i128) -> Result<(), VaultError> {
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+    let unattributed = locked_amount.saturating_add(free_amount).saturating_sub(total_balances(env)).max(0);
+    let owed = amount.saturating_sub(unattributed);
+    if owed <= 0 {
+        return Ok(());
+    }
+    let owner = read_owner(env)?;
+    let balance_key = DataKey::Balance(owner.clone());
+    let balance: i128 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+    let debit = owed.min(balance);
+    if debit <= 0 {
+        return Ok(());
+    }
+
+    settle_rewards(env, &owner, balance)?;
+    let new_balance = balance - debit;
+    write_persistent_amount(env, &balance_key, new_balance);
+    add_to_total_balances(env, -debit)?;
+    record_own_withdrawn(env, &owner, debit)?;
+    publish_balance_change(env, &owner, balance, new_balance, BALANCE_REASON_WITHDRAW);
+    if new_balance == 0 {
+        delist_depositor(env, &owner);
+    }
+    Ok(())
+}
+
+// Adds `amount` to the total taken out of a depositor's balance.
+fn record_own_withdrawn(env: &Env, depositor: &Address, amount: i128) -> Result<(), VaultError> {
+    let key = DataKeyExt::OwnWithdrawn(depositor.clone());
+    let withdrawn: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+    env.storage().persistent().set(&key, &withdrawn.checked_add(amount).ok_or(VaultError::Overflow)?);
+    env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    Ok(())
+}
+
+// Returns how much of a depositor's balance has vested and can come out now. The vault's vesting applies to each
+// depositor's own base, their balance plus what they've already taken out: the same fraction of it has vested as of
+// the vault's base, and what they've taken out counts against that.
+fn own_vested_available(env: &Env, depositor: &Address, balance: i128) -> Result<i128, VaultError> {
+    let withdrawn: i128 = env.storage().persistent().get(&DataKeyExt::OwnWithdrawn(depositor.clone())).unwrap_or(0);
+    let base = balance.checked_add(withdrawn).ok_or(VaultError::Overflow)?;
+    let vested = match env.storage().instance().get::<_, Vec<(u64, i128)>>(&DataKey::Schedule) {
+        // Tranches are amounts of the owner's schedule, so a depositor vests the share of it that has matured.
+        Some(schedule) => {
+            let now = env.ledger().timestamp();
+            let (mut released, mut scheduled) = (0i128, 0i128);
+            for (unlock_timestamp, amount) in schedule.iter() {
+                scheduled = scheduled.saturating_add(amount);
+                if unlock_timestamp <= now {
+                    released = released.saturating_add(amount);
+                }
+            }
+            if scheduled == 0 {
+                base
+            } else {
+                // released <= scheduled, so this can't overflow.
+                (base / scheduled) * released + (base % scheduled) * released / scheduled
+            }
+        }
+        None => vested_of(env, base),
+    };
+    Ok(vested.saturating_sub(withdrawn).clamp(0, balance))
+}
+
+// Returns the rewards a depositor has earned but not yet claimed, including what has accrued on `balance`
+// since their last checkpoint.
+fn pending_rewards(env: &Env, depositor: &Address, balance: i128) -> Result<i128, VaultError> {
+    let accrued: i128 = env.storage().persistent().get(&DataKey::AccruedRewards(depositor.clone())).unwrap_or(0);
+    let checkpoint: u64 = env.storage().persistent().get(&DataKey::RewardCheckpoint(depositor.clone())).unwrap_or(0);
+    let reward_rate: u64 = env.storage().instance().get(&DataKey::RewardRate).unwrap_or(0);
+    if balance == 0 || reward_rate == 0 {
+        return Ok(accrued);
+    }
+
+    let elapsed = env.ledger().timestamp().saturating_sub(checkpoint) as i128;
+    let earned = balance
+        .checked_mul(reward_rate as i128)
+        .and_then(|scaled| scaled.checked_mul(elapsed))
+        .ok_or(VaultError::Overflow)?
+        / REWARD_RATE_SCALE;
+    accrued.checked_add(earned).ok_or(VaultError::Overflow)
+}
+
+// Banks the rewards a depositor has earned on `balance` and restarts their accrual from now.
+// Must run before every change to the depositor's balance, so each stretch of time is paid at the balance held then.
+fn settle_rewards(env: &Env, depositor: &Address, balance: i128) -> Result<i128, VaultError> {
+    let accrued = pending_rewards(env, depositor, balance)?;
+    write_persistent_amount(env, &DataKey::AccruedRewards(depositor.clone()), accrued);
+    let checkpoint_key = DataKey::RewardCheckpoint(depositor.clone());
+    env.storage().persistent().set(&checkpoint_key, &env.ledger().timestamp());
+    env.storage().persistent().extend_ttl(&checkpoint_key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    Ok(accrued)
+}
+
+// Reads the list of addresses that currently hold a balance in the vault.
+fn read_depositors(env: &Env) -> Vec<Address> {
+    env.storage().persistent().get(&DataKey::Depositors).unwrap_or(Vec::new(env))
+}
+
+// Writes the depositor list to persistent storage and extends its TTL.
+fn write_depositors(env: &Env, depositors: &Vec<Address>) {
+    env.storage().persistent().set(&DataKey::Depositors, depositors);
+    env.storage().persistent().extend_ttl(&DataKey::Depositors, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+}
+
+// Reads the list of addresses that once held a balance but have withdrawn all of it.
+fn read_exited_depositors(env: &Env) -> Vec<Address> {
+    env.storage().persistent().get(&DataKeyExt::ExitedDepositors).unwrap_or(Vec::new(env))
+}
+
+// Writes the exited depositor list to persistent storage and extends its TTL.
+fn write_exited_depositors(env: &Env, exited: &Vec<Address>) {
+    env.storage().persistent().set(&DataKeyExt::ExitedDepositors, exited);
+    env.storage().persistent().extend_ttl(&DataKeyExt::ExitedDepositors, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+}
+
+// Reads the ids of withdrawal proposals still waiting for approvals.
+fn read_open_proposals(env: &Env) -> Vec<u64> {
+    env.storage().persistent().get(&DataKeyExt::OpenProposals).unwrap_or(Vec::new(env))
+}
+
+// Writes the open proposal ids to persistent storage and extends their TTL.
+fn write_open_proposals(env: &Env, open: &Vec<u64>) {
+    env.storage().persistent().set(&DataKeyExt::OpenProposals, open);
+    env.storage().persistent().extend_ttl(&DataKeyExt::OpenProposals, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+}
+
+// Adds a depositor whose balance is about to become positive to the depositor list, taking them off the exited list
+// if they are returning.
+fn list_depositor(env: &Env, depositor: &Address) {
+    let mut depositors = read_depositors(env);
+    depositors.push_back(depositor.clone());
+    write_depositors(env, &depositors);
+    let mut exited = read_exited_depositors(env);
+    if let Some(index) = exited.first_index_of(depositor) {
+        exited.remove(index);
+        write_exited_depositors(env, &exited);
+    }
+}
+
+// Moves a depositor whose balance has reached 0 from the depositor list to the exited list. They are no longer a
+// participant, but their rewards, snapshot, and timing records remain, and close needs to find them.
+fn delist_depositor(env: &Env, depositor: &Address) {
+    let mut depositors = read_depositors(env);
+    if let Some(index) = depositors.first_index_of(depositor) {
+        depositors.remove(index);
+        write_depositors(env, &depositors);
+        let mut exited = read_exited_depositors(env);
+        exited.push_back(depositor.clone());
+        write_exited_depositors(env, &exited);
+    }
+}
+
+// Returns how much of the locked amount is still held back by relock, or 0 once the relock has expired.
+fn active_relocked_amount(env: &Env) -> i128 {
+    let relock_unlock: u64 = env.storage().instance().get(&DataKey::RelockUnlock).unwrap_or(0);
+    if env.ledger().timestamp() >= relock_unlock {
+        return 0;
+    }
+    env.storage().instance().get(&DataKey::RelockedAmount).unwrap_or(0)
+}
+
+// Returns the time withdrawals actually open: the unlock timestamp plus any grace period set at initialize.
+fn effective_unlock_time(env: &Env) -> Result<u64, VaultError> {
+    let unlock_timestamp: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?;
+    let grace_period: u64 = env.storage().instance().get(&DataKey::GracePeriod).unwrap_or(0);
+    Ok(unlock_timestamp.saturating_add(grace_period))
+}
+
+// Returns whether the external unlock condition holds: always for a vault without an oracle, otherwise only once the
+// oracle has reported it met. This gates everything the owner can take out after the unlock time (locked tokens,
+// other whitelisted tokens, NFTs, sweeps, and token migrations) on top of the unlock time, not instead of it.
+fn unlock_condition_met(env: &Env) -> bool {
+    !env.storage().instance().has(&DataKeyExt::Oracle) || env.storage().instance().get(&DataKeyExt::OracleUnlockFlag).unwrap_or(false)
+}
+
+// Returns the total of withdrawal requests still waiting for fulfill_withdrawal. Every other owner-side withdrawal
+// leaves this much of the locked amount alone, so a queued request can always still be paid.
+fn pending_withdrawals(env: &Env) -> i128 {
+    env.storage().instance().get(&DataKeyExt::PendingWithdrawals).unwrap_or(0)
+}
+
+// Returns how much of the time-locked portion the owner could withdraw right now: 0 before unlock, otherwise the
+// locked amount minus anything relocked, capped by what has vested (with vesting or a tranche schedule) but not yet
+// been withdrawn, less whatever pending withdrawal requests have reserved and other depositors' balances.
+// This is the single rule every owner withdrawal of locked tokens is checked against.
+fn locked_withdrawable_amount(env: &Env) -> Result<i128, VaultError> {
+    let unlock_timestamp: u64 = effective_unlock_time(env)?;
+    if env.ledger().timestamp() < unlock_timestamp || !unlock_condition_met(env) {
+        return Ok(0);
+    }
+
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let unrelocked = locked_amount.saturating_sub(active_relocked_amount(env));
+    let withdrawn: i128 = env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0);
+    let unclaimed_vested = compute_vested(env).checked_sub(withdrawn).ok_or(VaultError::Overflow)?;
+    let reserved = pending_withdrawals(env).saturating_add(others_balances(env));
+    Ok(unrelocked.min(unclaimed_vested).saturating_sub(reserved).max(0))
+}
+
+// Returns how much the owner could withdraw right now: the whole free portion plus the withdrawable locked portion.
+fn withdrawable_amount(env: &Env) -> Result<i128, VaultError> {
+    let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+    free_amount.checked_add(locked_withdrawable_amount(env)?).ok_or(VaultError::Overflow)
+}
+
+// Pays several owner-side withdrawals out of the locked amount at once, for an already-authorized caller.
+// The unlock, vesting, and withdrawal limit apply to the total, which comes off the locked amount in one update.
+fn withdraw_to_many(env: &Env, recipients: &Vec<(Address, i128)>) -> Result<(), VaultError> {
+    ensure_no_signers(env)?;
+    ensure_not_paused(env)?;
+
+    // Validate every amount and add up the total before anything moves.
+    let mut total: i128 = 0;
+    for (to, amount) in recipients.iter() {
+        check_destination(env, &to)?;
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+        total = total.checked_add(amount).ok_or(VaultError::Overflow)?;
+    }
+    if total == 0 {
+        return Err(VaultError::NonPositiveAmount);
+    }
+    let (locked_amount, withdrawn) = check_owner_withdrawal(env, total)?;
+    record_limited_withdrawal(env, total)?;
+
+    let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+    enter_reentrancy_guard(env)?;
+
+    // Decrement the aggregate once for the whole batch.
+    debit_owner_share(env, total)?;
+    let remaining = locked_amount.checked_sub(total).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &DataKey::LockedAmount, remaining);
+    let withdrawn = withdrawn.checked_add(total).ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(&DataKey::Withdrawn, &withdrawn);
+    extend_instance_ttl(env);
+    record_activity(env);
+    add_to_lifetime_total(env, &DataKeyExt::TotalWithdrawn, total)?;
+
+    let token_client = token::Client::new(env, &token_id);
+    for (to, amount) in recipients.iter() {
+        pay_out(env, &token_client, &to, amount)?;
+    }
+    exit_reentrancy_guard(env);
+
+    // Report each payout with the locked amount as it stood after that recipient was paid.
+    let mut running_locked = locked_amount;
+    for (to, amount) in recipients.iter() {
+        running_locked -= amount;
+        let seq = next_seq(env, &DataKey::WithdrawSeq)?;
+        env.events().publish((symbol_short!("withdraw"), to), (amount, running_locked, seq));
+    }
+    Ok(())
+}
+
+// Tiny withdrawals waste fees, so they are refused unless the vault holds less than the minimum anyway,
+// in which case the last of it can still be swept out.
+fn check_min_withdrawal(env: &Env, amount: i128, free_amount: i128) -> Result<(), VaultError> {
+    let min_withdrawal: i128 = env.storage().instance().get(&DataKey::MinWithdrawal).unwrap_or(0);
+    if amount < min_withdrawal {
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        if locked_amount.saturating_add(free_amount) >= min_withdrawal {
+            return Err(VaultError::BelowMinimumWithdrawal);
+        }
+    }
+    Ok(())
+}
+
+// Rounds a withdrawal up to the whole remaining balance when it would otherwise leave behind a positive residue
+// smaller than the minimum withdrawal, as fee or vesting math computed by the caller easily can. The residue is
+// only absorbed if all of it is withdrawable right now; otherwise the amount is left as requested.
+fn round_up_final_withdrawal(env: &Env, amount: i128, free_amount: i128) -> Result<i128, VaultError> {
+    let min_withdrawal: i128 = env.storage().instance().get(&DataKey::MinWithdrawal).unwrap_or(0);
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let remaining = locked_amount.saturating_add(free_amount).saturating_sub(amount);
+    if remaining > 0 && remaining < min_withdrawal && locked_amount <= locked_withdrawable_amount(env)? {
+        return Ok(amount + remaining);
+    }
+    Ok(amount)
+}
+
+// Returns when a depositor's own funds unlock. The same time lock applies to depositors as to the owner,
+// extended by any personal unlock time the depositor chose with deposit_with_unlock and by the minimum hold
+// after their latest deposit.
+fn own_unlock_time(env: &Env, depositor: &Address) -> Result<u64, VaultError> {
+    let unlock_timestamp: u64 = effective_unlock_time(env)?;
+    let personal_unlock: u64 = env.storage().persistent().get(&DataKey::DepositUnlock(depositor.clone())).unwrap_or(0);
+    let min_hold: u64 = env.storage().instance().get(&DataKeyExt::MinHoldSeconds).unwrap_or(0);
+    let hold_end = match env.storage().persistent().get::<_, u64>(&DataKeyExt::LastCredit(depositor.clone())) {
+        Some(last_credit) if min_hold > 0 => last_credit.saturating_add(min_hold),
+        _ => 0,
+    };
+    Ok(unlock_timestamp.max(personal_unlock).max(hold_end))
+}
+
+// Checks the rules a depositor's own withdrawal must meet: the vault's unlock, any personal unlock, the minimum
+// hold, the depositor's balance, and how much of it has vested. Returns the depositor's balance and the vault's locked amount.
+fn check_own_withdrawal(env: &Env, depositor: &Address, amount: i128) -> Result<(i128, i128), VaultError> {
+    if env.ledger().timestamp() < own_unlock_time(env, depositor)? {
+        return Err(VaultError::StillLocked);
+    }
+
+    // In a multisig vault the owner's own share, like everything else the owner takes out, only leaves by proposal.
+    if read_owner(env).ok().as_ref() == Some(depositor) {
+        ensure_no_signers(env)?;
+    }
+
+    // A depositor can only take out what they put in.
+    let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+    if amount > balance {
+        return Err(VaultError::InsufficientFunds);
+    }
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    if amount > locked_amount {
+        return Err(VaultError::InsufficientFunds);
+    }
+    if amount > own_vested_available(env, depositor, balance)? {
+        return Err(VaultError::NotYetVested);
+    }
+    Ok((balance, locked_amount))
+}
+
+// Executes a queued admin action: fails unless `action_id` was queued and its time has come, then dequeues it
+// and emits an `act_exec` event with topics `(act_exec, action_id)`.
+fn consume_action(env: &Env, action_id: &BytesN<32>) -> Result<(), VaultError> {
+    let mut queued: Map<BytesN<32>, u64> = env.storage().instance().get(&DataKeyExt::QueuedActions).unwrap_or(Map::new(env));
+    let execute_after = queued.get(action_id.clone()).ok_or(VaultError::ActionNotQueued)?;
+    if env.ledger().timestamp() < execute_after {
+        return Err(VaultError::ActionNotReady);
+    }
+    queued.remove(action_id.clone());
+    env.storage().instance().set(&DataKeyExt::QueuedActions, &queued);
+    env.events().publish((symbol_short!("act_exec"), action_id.clone()), ());
+    Ok(())
+}
+
+// Returns the action id force_unlock must be queued under in a vault with an action delay: the sha256 of "force_unlock".
+fn force_unlock_action_id(env: &Env) -> BytesN<32> {
+    env.crypto().sha256(&Bytes::from_slice(env, b"force_unlock")).to_bytes()
+}
+
+// Returns the vault's signers; empty unless multi-signer withdrawals are on.
+fn read_signers(env: &Env) -> Vec<Address> {
+    env.storage().instance().get(&DataKeyExt::Signers).unwrap_or(Vec::new(env))
+}
+
+// Returns an error if the vault has signers, in which case the owner can only withdraw through an approved proposal.
+fn ensure_no_signers(env: &Env) -> Result<(), VaultError> {
+    if !read_signers(env).is_empty() {
+        return Err(VaultError::Unauthorized);
+    }
+    Ok(())
+}
+
+// Checks a signer set for initialize: without signers the threshold must be 0; with them it must be between 1 and the
+// number of signers, and no signer may appear twice.
+fn validate_signers(signers: &Vec<Address>, threshold: u32) -> Result<(), VaultError> {
+    if signers.is_empty() {
+        return if threshold == 0 { Ok(()) } else { Err(VaultError::InvalidSigners) };
+    }
+    if threshold == 0 || threshold > signers.len() {
+        return Err(VaultError::InvalidSigners);
+    }
+    for (index, signer) in signers.iter().enumerate() {
+        if signers.first_index_of(&signer) != Some(index as u32) {
+            return Err(VaultError::InvalidSigners);
+        }
+    }
+    Ok(())
+}
+
+// Returns an error if `signer` isn't one of the vault's signers; otherwise requires its authorization.
+fn require_signer(env: &Env, signer: &Address) -> Result<(), VaultError> {
+    if !read_signers(env).contains(signer) {
+        return Err(VaultError::Unauthorized);
+    }
+    signer.require_auth();
+    Ok(())
+}
+
+// Executes a proposal if enough of the current signers have approved it, returning whether it did.
+// Approvals from addresses that have since been removed as signers no longer count.
+fn execute_if_approved(env: &Env, proposal_id: u64, proposal: &WithdrawalProposal) -> Result<bool, VaultError> {
+    let signers = read_signers(env);
+    let threshold: u32 = env.storage().instance().get(&DataKeyExt::SignerThreshold).unwrap_or(0);
+    let approvals = proposal.approvals.iter().filter(|approver| signers.contains(approver)).count() as u32;
+    if approvals < threshold {
+        return Ok(false);
+    }
+
+    ensure_not_paused(env)?;
+    check_destination(env, &proposal.to)?;
+    env.storage().persistent().remove(&DataKeyExt::Proposal(proposal_id));
+    let mut open = read_open_proposals(env);
+    if let Some(index) = open.first_index_of(proposal_id) {
+        open.remove(index);
+        write_open_proposals(env, &open);
+    }
+    let locked_amount = withdraw_locked_for_owner(env, &proposal.to, proposal.amount)?;
+    env.events().publish((symbol_short!("ms_exec"), proposal.to.clone()), (proposal.amount, locked_amount, proposal_id));
+    Ok(true)
+}
+
+// Returns an error unless the owner has made no authorized call for at least `window` seconds,
+// as the beneficiary (with the inactivity window) and the recovery address (with the recovery delay) need.
+fn check_owner_inactive(env: &Env, window: u64) -> Result<(), VaultError> {
+    let last_owner_action: u64 = env.storage().instance().get(&DataKey::LastOwnerAction).unwrap_or(0);
+    if env.ledger().timestamp() < last_owner_action.saturating_add(window) {
+        return Err(VaultError::OwnerStillActive);
+    }
+    Ok(())
+}
+
+// Pays `amount` of the owner's locked tokens to `to` on the owner's behalf, for the beneficiary, the recovery address,
+// or an approved multi-signer proposal, once the caller has checked its own authorization. The owner's unlock,
+// vesting, limit, and fee rules all apply. Returns the remaining locked amount.
+fn withdraw_locked_for_owner(env: &Env, to: &Address, amount: i128) -> Result<i128, VaultError> {
+    let (locked_amount, withdrawn) = check_owner_withdrawal(env, amount)?;
+    record_limited_withdrawal(env, amount)?;
+
+    let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+    enter_reentrancy_guard(env)?;
+
+    debit_owner_share(env, amount)?;
+    let locked_amount = locked_amount.checked_sub(amount).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &DataKey::LockedAmount, locked_amount);
+    let withdrawn = withdrawn.checked_add(amount).ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(&DataKey::Withdrawn, &withdrawn);
+    extend_instance_ttl(env);
+    record_activity(env);
+    add_to_lifetime_total(env, &DataKeyExt::TotalWithdrawn, amount)?;
+
+    let token_client = token::Client::new(env, &token_id);
+    pay_out(env, &token_client, to, amount)?;
+    exit_reentrancy_guard(env);
+    Ok(locked_amount)
+}
+
+// Checks that the owner may take `amount` of locked tokens out of the vault right now,
+// i.e. that it is within locked_withdrawable_amount.
+// Returns the current locked amount and withdrawn-to-date so the caller can update them.
+fn check_owner_withdrawal(env: &Env, amount: i128) -> Result<(i128, i128), VaultError> {
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let withdrawn: i128 = env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0);
+    if amount <= locked_withdrawable_amount(env)? {
+        return Ok((locked_amount, withdrawn));
+    }
+
+    // The amount is too large; work out why so the caller gets a useful error.
+    let unlock_timestamp: u64 = effective_unlock_time(env)?;
+    if env.ledger().timestamp() < unlock_timestamp || !unlock_condition_met(env) {
+        return Err(VaultError::StillLocked);
+    }
+    // Tokens reserved for pending withdrawal requests, or credited to other depositors, aren't the owner's to take.
+    if amount > locked_amount.saturating_sub(pending_withdrawals(env)).saturating_sub(others_balances(env)) {
+        return Err(VaultError::InsufficientFunds);
+    }
+    // Tokens the owner relocked stay out of reach until their own unlock time.
+    if amount > locked_amount.saturating_sub(active_relocked_amount(env)) {
+        return Err(VaultError::StillLocked);
+    }
+    Err(VaultError::NotYetVested)
 }
 
-// Declare the smart contract struct.
-// This is a placeholder; the actual contract logic is in the impl block.
-#[contract]
-pub struct VaultContract;
+// Returns the withdrawal fee on `amount`, rounded down so any fraction stays with the recipient.
+fn withdrawal_fee(env: &Env, amount: i128) -> Result<i128, VaultError> {
+    let fee_bps: u32 = env.storage().instance().get(&DataKey::FeeBps).unwrap_or(0);
+    Ok(amount.checked_mul(fee_bps as i128).ok_or(VaultError::Overflow)? / 10_000)
+}
+
+// Returns the deposit fee on `amount`, rounded down so any fraction stays with the depositor.
+fn deposit_fee(env: &Env, amount: i128) -> i128 {
+    let fee_bps: u32 = env.storage().instance().get(&DataKeyExt::DepositFeeBps).unwrap_or(0);
+    // Split like the locked part of a deposit so amounts near i128::MAX don't overflow.
+    amount / 10_000 * fee_bps as i128 + amount % 10_000 * fee_bps as i128 / 10_000
+}
+
+// Returns the part of `fee` in deposit fees that stays locked in the vault as protocol-owned liquidity: all of it in
+// a vault initialized with deposit_fee_retained, otherwise none, since it is paid to the fee collector.
+fn retained_deposit_fee(env: &Env, fee: i128) -> i128 {
+    if env.storage().instance().get(&DataKeyExt::DepositFeeRetained).unwrap_or(false) {
+        fee
+    } else {
+        0
+    }
+}
+
+// Pays deposit fees that aren't retained on to the fee collector, once the deposit itself has arrived.
+fn pay_deposit_fee(env: &Env, token_client: &token::Client, fee: i128) -> Result<(), VaultError> {
+    let paid = fee - retained_deposit_fee(env, fee);
+    if paid > 0 {
+        let fee_collector: Address = env.storage().instance().get(&DataKey::FeeCollector).ok_or(VaultError::NotInitialized)?;
+        token_client.transfer(&env.current_contract_address(), &fee_collector, &paid);
+    }
+    Ok(())
+}
+
+// Sends `amount` from the vault to `to`, first splitting off the configured withdrawal fee.
+// Primary-token fees stay in the vault as accrued fees for the collector to take with collect_fees;
+// fees in other whitelisted tokens go straight to the fee collector.
+fn pay_out(env: &Env, token_client: &token::Client, to: &Address, amount: i128) -> Result<(), VaultError> {
+    let fee = withdrawal_fee(env, amount)?;
+    if fee > 0 {
+        let primary_token: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        if token_client.address == primary_token {
+            let accrued: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+            env.storage().instance().set(&DataKeyExt::AccruedFees, &accrued.checked_add(fee).ok_or(VaultError::Overflow)?);
+        } else {
+            let fee_collector: Address = env.storage().instance().get(&DataKey::FeeCollector).ok_or(VaultError::NotInitialized)?;
+            token_client.transfer(&env.current_contract_address(), &fee_collector, &fee);
+        }
+    }
+
+    let net_amount = amount - fee;
+    if net_amount > 0 {
+        token_client.transfer(&env.current_contract_address(), to, &net_amount);
+    }
+    Ok(())
+}
+
+// Transfers `amount` tokens from `from` into the vault and checks the vault's balance grew by exactly that much.
+// A fee-on-transfer token, or one that reports failure instead of panicking, would otherwise leave the vault
+// crediting more than it holds. If the transfer itself fails, the whole invocation reverts.
+fn receive_tokens(env: &Env, token_client: &token::Client, from: &Address, amount: i128) -> Result<(), VaultError> {
+    if transfer_in(env, token_client, from, amount)? != amount {
+        return Err(VaultError::TransferAmountMismatch);
+    }
+    Ok(())
+}
+
+// Transfers `amount` tokens from `from` into the vault and returns how much the vault's balance actually grew.
+fn transfer_in(env: &Env, token_client: &token::Client, from: &Address, amount: i128) -> Result<i128, VaultError> {
+    let vault = env.current_contract_address();
+    let before = token_client.balance(&vault);
+    token_client.transfer(from, &vault, &amount);
+    token_client.balance(&vault).checked_sub(before).ok_or(VaultError::Overflow)
+}
+
+// Performs a deposit whose caller has already been authorized: checks, bookkeeping, transfer, and event.
+// A vault that credits what it receives pulls the tokens in first and books the measured amount instead.
+// Shared by deposit, deposit_with_unlock, deposit_with_memo, and deposit_partial so they can never drift apart.
+// Only `lock_bps` basis points of the amount are time-locked; the rest goes to the free portion.
+// A memo, if given, is only carried in the event and never stored.
+// `from` pays and `credit_to` is credited with the balance; they differ only for deposit_for.
+fn deposit_from(env: &Env, from: &Address, credit_to: &Address, amount: i128, lock_bps: u32, memo: Option<BytesN<32>>) -> Result<i128, VaultError> {
+    ensure_not_paused(env)?;
+    ensure_not_blocked(env, from)?;
+    ensure_not_blocked(env, credit_to)?;
+    ensure_depositor_allowed(env, from)?;
+    check_deposit_window(env)?;
+    check_deposit_cooldown(env, from)?;
+
+    check_deposit_amount(env, amount)?;
+    // Enforce the deposit cap, and make sure the deposit can be booked without overflowing, before any tokens move.
+    check_deposit_cap(env, amount)?;
+    check_deposit_fits(env, credit_to, amount)?;
+    auto_extend_unlock(env)?;
+
+    // Retrieve the token contract ID from storage and create a client to interact with the token contract.
+    let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+    let token_client = token::Client::new(env, &token_id);
+    enter_reentrancy_guard(env)?;
+
+    // For fee-on-transfer tokens, measure what actually arrives and book only that.
+    // The guard is already held, so the token can't re-enter between the transfer and the bookkeeping.
+    let credit_received: bool = env.storage().instance().get(&DataKeyExt::CreditReceived).unwrap_or(false);
+    let received = if credit_received { transfer_in(env, &token_client, from, amount)? } else { amount };
+
+    // The deposit fee comes off the top; only the rest is credited to the depositor.
+    let fee = deposit_fee(env, received);
+    let amount = received - fee;
+
+    // Unless the tokens were already pulled in above, the bookkeeping is updated before calling out to the token
+    // contract (checks-effects-interactions). Split the deposit into its time-locked and free parts.
+    // The split is done in two steps so amounts near i128::MAX don't overflow the multiplication.
+    let lock_bps = lock_bps as i128;
+    let locked_part = amount / 10_000 * lock_bps + amount % 10_000 * lock_bps / 10_000;
+    let free_part = amount - locked_part;
+    // Update the total locked amount in the vault, including any fee kept as protocol-owned liquidity.
+    let mut locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    // Use checked_add to prevent integer overflow, which is a common smart contract vulnerability.
+    locked_amount = locked_amount.checked_add(locked_part + retained_deposit_fee(env, fee)).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &DataKey::LockedAmount, locked_amount);
+    if free_part > 0 {
+        let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+        let free_amount = free_amount.checked_add(free_part).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(env, &DataKey::FreeAmount, free_amount);
+    }
+
+    // Credit the depositor's own balance so the vault knows who contributed what.
+    credit_balance(env, from, credit_to, amount)?;
+    extend_instance_ttl(env);
+    record_activity(env);
+    add_to_lifetime_total(env, &DataKeyExt::TotalDeposited, received)?;
+
+    // Transfer tokens from the from account to this contract's address.
+    // This uses transfer rather than transfer_from, so no allowance is involved. The caller's
+    // from.require_auth() authorizes the whole invocation tree, including this token transfer.
+    // If the transfer fails or delivers the wrong amount, the whole invocation reverts, including the updates above.
+    if !credit_received {
+        receive_tokens(env, &token_client, from, received)?;
+    }
+    pay_deposit_fee(env, &token_client, fee)?;
+    exit_reentrancy_guard(env);
+
+    // Publish a deposit event so off-chain indexers can pick up activity without polling.
+    // This runs last, so the event is only emitted once the transfer and storage update have succeeded.
+    // The sequence number is taken here so it follows exactly the order events are emitted in.
+    let seq = next_seq(env, &DataKey::DepositSeq)?;
+    match memo {
+        Some(memo) => env.events().publish((symbol_short!("deposit"), from.clone()), (amount, locked_amount, seq, memo)),
+        None if credit_to != from => env.events().publish((symbol_short!("dep_for"), from.clone(), credit_to.clone()), (amount, locked_amount, seq)),
+        None => env.events().publish((symbol_short!("deposit"), from.clone()), (amount, locked_amount, seq)),
+    }
+    Ok(locked_amount)
+}
+
+// Deposits a whitelisted token other than the primary one. These deposits only add to the token's own total:
+// minimums, caps, and per-depositor balances are all denominated in the primary token.
+fn deposit_other_token(env: &Env, token_id: &Address, from: &Address, amount: i128) -> Result<i128, VaultError> {
+    ensure_not_paused(env)?;
+    ensure_not_blocked(env, from)?;
+    ensure_depositor_allowed(env, from)?;
+    check_deposit_window(env)?;
+    check_deposit_cooldown(env, from)?;
+    ensure_token_allowed(env, token_id)?;
+    if amount <= 0 {
+        return Err(VaultError::NonPositiveAmount);
+    }
+    enter_reentrancy_guard(env)?;
+
+    let balance_key = DataKey::TokenBalance(token_id.clone());
+    let balance: i128 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+    if balance == 0 {
+        adjust_held_token_count(env, true)?;
+    }
+    let balance = balance.checked_add(amount).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &balance_key, balance);
+    extend_instance_ttl(env);
+    record_activity(env);
+
+    receive_tokens(env, &token::Client::new(env, token_id), from, amount)?;
+    exit_reentrancy_guard(env);
+
+    let seq = next_seq(env, &DataKey::DepositSeq)?;
+    env.events().publish((symbol_short!("deposit"), from.clone(), token_id.clone()), (amount, balance, seq));
+    Ok(balance)
+}
+
+// Counts a non-primary token in or out of the tokens the vault holds a balance of, so close can tell it isn't empty
+// without enumerating the whitelist.
+fn adjust_held_token_count(env: &Env, held: bool) -> Result<(), VaultError> {
+    let count: u32 = env.storage().instance().get(&DataKeyExt::HeldTokenCount).unwrap_or(0);
+    let count = if held { count.checked_add(1) } else { count.checked_sub(1) }.ok_or(VaultError::Overflow)?;
+    env.storage().instance().set(&DataKeyExt::HeldTokenCount, &count);
+    Ok(())
+}
+
+// Withdraws a token other than the primary one for an already-authorized owner.
+// The time lock and withdrawal fee apply; vesting and schedules, which count primary tokens, do not.
+// The token doesn't need to still be whitelisted, so removing it from the list never strands a balance.
+fn withdraw_other_token(env: &Env, token_id: &Address, to: &Address, amount: i128) -> Result<i128, VaultError> {
+    let unlock_timestamp: u64 = effective_unlock_time(env)?;
+    if env.ledger().timestamp() < unlock_timestamp || !unlock_condition_met(env) {
+        return Err(VaultError::StillLocked);
+    }
+
+    let balance_key = DataKey::TokenBalance(token_id.clone());
+    let balance: i128 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+    if amount > balance {
+        return Err(VaultError::InsufficientFunds);
+    }
+    enter_reentrancy_guard(env)?;
+
+    let balance = balance.checked_sub(amount).ok_or(VaultError::Overflow)?;
+    write_persistent_amount(env, &balance_key, balance);
+    if balance == 0 && amount > 0 {
+        adjust_held_token_count(env, false)?;
+    }
+    extend_instance_ttl(env);
+    record_activity(env);
+
+    let token_client = token::Client::new(env, token_id);
+    pay_out(env, &token_client, to, amount)?;
+    exit_reentrancy_guard(env);
+
+    let seq = next_seq(env, &DataKey::WithdrawSeq)?;
+    env.events().publish((symbol_short!("withdraw"), to.clone(), token_id.clone()), (amount, balance, seq));
+    Ok(balance)
+}
+
+// Upgrades storage written before versioning (schema version 0) to the version 1 layout.
+// Back then the locked amount lived in instance storage, and there was no Initialized flag,
+// so without this step such a vault would read as empty and could even be initialized again.
+fn migrate_from_v0(env: &Env) {
+    if let Some(locked_amount) = env.storage().instance().get::<_, i128>(&DataKey::LockedAmount) {
+        env.storage().instance().remove(&DataKey::LockedAmount);
+        write_persistent_amount(env, &DataKey::LockedAmount, locked_amount);
+    }
+    env.storage().instance().set(&DataKey::Initialized, &true);
+}
+
+// Tells the notify contract, if one is set, that `amount` primary tokens just left the vault for `to`.
+// Unless the vault is strict, the call is wrapped so a failing or missing callback can't block withdrawals.
+fn notify_withdrawal(env: &Env, to: &Address, amount: i128) {
+    let Some(notify_contract) = env.storage().instance().get::<_, Address>(&DataKeyExt::NotifyContract) else {
+        return;
+    };
+    let func = Symbol::new(env, "on_withdraw");
+    let args: Vec<soroban_sdk::Val> = (env.current_contract_address(), to.clone(), amount).into_val(env);
+    if env.storage().instance().get(&DataKeyExt::NotifyStrict).unwrap_or(false) {
+        env.invoke_contract::<()>(&notify_contract, &func, args);
+    } else {
+        let _ = env.try_invoke_contract::<(), soroban_sdk::Error>(&notify_contract, &func, args);
+    }
+}
+
+// Moves one NFT by calling `transfer(from, to, token_id)` on its contract, the transfer interface NFT contracts share.
+fn transfer_nft(env: &Env, nft_contract: &Address, from: &Address, to: &Address, token_id: u128) {
+    let args: Vec<soroban_sdk::Val> = (from.clone(), to.clone(), token_id).into_val(env);
+    env.invoke_contract::<()>(nft_contract, &Symbol::new(env, "transfer"), args);
+}
+
+// Enforces the deposit cooldown for `from` and starts a new one. This keeps a spammer from bloating the
+// depositor list with a stream of dust deposits. Nothing is stored while the cooldown is 0.
+fn check_deposit_cooldown(env: &Env, from: &Address) -> Result<(), VaultError> {
+    let cooldown: u64 = env.storage().instance().get(&DataKeyExt::DepositCooldown).unwrap_or(0);
+    if cooldown == 0 {
+        return Ok(());
+    }
+    let now = env.ledger().timestamp();
+    let key = DataKeyExt::LastDeposit(from.clone());
+    if let Some(last) = env.storage().persistent().get::<_, u64>(&key) {
+        if now < last.saturating_add(cooldown) {
+            return Err(VaultError::DepositTooSoon);
+        }
+    }
+    env.storage().persistent().set(&key, &now);
+    env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    Ok(())
+}
+
+// Returns an error if the owner has blocked `addr`.
+fn ensure_not_blocked(env: &Env, addr: &Address) -> Result<(), VaultError> {
+    if env.storage().persistent().has(&DataKey::Blocked(addr.clone())) {
+        return Err(VaultError::AddressBlocked);
+    }
+    Ok(())
+}
+
+// Returns how many primary tokens the books say the vault holds: the locked amount plus the other buckets kept
+// in the same token, the free portion, the reward pool, and fees accrued for the collector.
+fn tracked_balance(env: &Env) -> Result<i128, VaultError> {
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+    let pool: i128 = env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0);
+    let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+    let accrued_fees: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+    locked_amount
+        .checked_add(pool)
+        .and_then(|t| t.checked_add(free_amount))
+        .and_then(|t| t.checked_add(accrued_fees))
+        .ok_or(VaultError::Overflow)
+}
+
+// Returns an error if the current ledger time falls outside the deposit window set at initialize.
+fn check_deposit_window(env: &Env) -> Result<(), VaultError> {
+    let now = env.ledger().timestamp();
+    let open_from: u64 = env.storage().instance().get(&DataKeyExt::DepositOpenFrom).unwrap_or(0);
+    let open_until: u64 = env.storage().instance().get(&DataKeyExt::DepositOpenUntil).unwrap_or(0);
+    if now < open_from || (open_until != 0 && now > open_until) {
+        return Err(VaultError::DepositWindowClosed);
+    }
+    Ok(())
+}
+
+// Returns an error if the depositor allowlist is on and `from` isn't on it.
+fn ensure_depositor_allowed(env: &Env, from: &Address) -> Result<(), VaultError> {
+    if !env.storage().instance().get(&DataKey::DepositorAllowlist).unwrap_or(false) {
+        return Ok(());
+    }
+    let key = DataKey::AllowedDepositor(from.clone());
+    if !env.storage().persistent().has(&key) {
+        return Err(VaultError::DepositorNotAllowed);
+    }
+    env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    Ok(())
+}
+
+// Returns an error unless the owner has added `token_id` to the vault's token whitelist.
+fn ensure_token_allowed(env: &Env, token_id: &Address) -> Result<(), VaultError> {
+    if !env.storage().instance().has(&DataKey::AllowedToken(token_id.clone())) {
+        return Err(VaultError::TokenNotAllowed);
+    }
+    Ok(())
+}
+
+// Returns whether initialize has completed on this vault.
+fn is_initialized(env: &Env) -> bool {
+    env.storage().instance().get(&DataKey::Initialized).unwrap_or(false)
+}
+
+// Loads the owner, telling a vault whose ownership was renounced apart from one that was never initialized.
+fn read_owner(env: &Env) -> Result<Address, VaultError> {
+    match env.storage().instance().get(&DataKey::Owner) {
+        Some(owner) => Ok(owner),
+        None if is_initialized(env) => Err(VaultError::OwnershipRenounced),
+        None => Err(VaultError::NotInitialized),
+    }
+}
+
+// Loads the admin, falling back to the owner when no separate admin was ever set.
+fn read_admin(env: &Env) -> Result<Address, VaultError> {
+    match env.storage().instance().get(&DataKey::Admin) {
+        Some(admin) => Ok(admin),
+        None => read_owner(env),
+    }
+}
+
+// Requires the admin's authorization for an administrative call. Without a separate admin this is require_owner,
+// so the call still counts as owner activity.
+fn require_admin(env: &Env) -> Result<Address, VaultError> {
+    if !env.storage().instance().has(&DataKey::Admin) {
+        return require_owner(env);
+    }
+    let admin = read_admin(env)?;
+    admin.require_auth();
+    Ok(admin)
+}
+
+// Loads the owner and requires their authorization for the current call.
+// Every owner action also refreshes the last-owner-action timestamp that gates beneficiary withdrawals.
+fn require_owner(env: &Env) -> Result<Address, VaultError> {
+    let owner = read_owner(env)?;
+    owner.require_auth();
+    env.storage().instance().set(&DataKey::LastOwnerAction, &env.ledger().timestamp());
+    Ok(owner)
+}
+
+// Returns whether the current withdrawal limit window is over (or never started), so the next withdrawal opens a new one.
+fn limit_window_expired(env: &Env) -> bool {
+    let window_start: Option<u64> = env.storage().instance().get(&DataKey::WindowStart);
+    window_start.is_none_or(|start| env.ledger().timestamp() >= start.saturating_add(WITHDRAWAL_WINDOW))
+}
+
+// Returns whether withdrawing `amount` now would stay within the withdrawal limit, without recording anything.
+fn within_withdrawal_limit(env: &Env, amount: i128) -> bool {
+    let limit: i128 = env.storage().instance().get(&DataKey::WithdrawalLimit).unwrap_or(0);
+    if limit == 0 {
+        return true;
+    }
+    let withdrawn_today: i128 = if limit_window_expired(env) { 0 } else { env.storage().instance().get(&DataKey::WithdrawnToday).unwrap_or(0) };
+    withdrawn_today.saturating_add(amount) <= limit
+}
+
+// Counts `amount` against the owner's withdrawal limit, failing with LimitExceeded if the current window can't take it.
+// The window restarts at the first withdrawal made WITHDRAWAL_WINDOW or more seconds after the previous one began.
+fn record_limited_withdrawal(env: &Env, amount: i128) -> Result<(), VaultError> {
+    let limit: i128 = env.storage().instance().get(&DataKey::WithdrawalLimit).unwrap_or(0);
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let mut withdrawn_today: i128 = env.storage().instance().get(&DataKey::WithdrawnToday).unwrap_or(0);
+    if limit_window_expired(env) {
+        env.storage().instance().set(&DataKey::WindowStart, &env.ledger().timestamp());
+        withdrawn_today = 0;
+    }
+
+    let withdrawn_today = withdrawn_today.checked_add(amount).ok_or(VaultError::Overflow)?;
+    if withdrawn_today > limit {
+        return Err(VaultError::LimitExceeded);
+    }
+    env.storage().instance().set(&DataKey::WithdrawnToday, &withdrawn_today);
+    Ok(())
+}
+
+// Returns an error if a withdrawal destination was fixed at initialize and `to` isn't it.
+fn check_destination(env: &Env, to: &Address) -> Result<(), VaultError> {
+    ensure_not_blocked(env, to)?;
+    let destination: Option<Address> = env.storage().instance().get(&DataKey::WithdrawalDestination);
+    match destination {
+        Some(destination) if destination != *to => Err(VaultError::DestinationNotAllowed),
+        _ => Ok(()),
+    }
+}
+
+// Moves the vault's unlock out to `min_unlock` if it is currently earlier, never shortening it.
+// Emits the same `extend` event as extend_lock when the unlock actually moves.
+fn extend_unlock_to(env: &Env, min_unlock: u64) -> Result<(), VaultError> {
+    let unlock_timestamp: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?;
+    if min_unlock <= unlock_timestamp {
+        return Ok(());
+    }
+    check_lock_duration(env, min_unlock)?;
+    env.storage().instance().set(&DataKey::UnlockTimestamp, &min_unlock);
+    env.events().publish((symbol_short!("extend"),), (unlock_timestamp, min_unlock));
+    Ok(())
+}
+
+// Records `unlock_timestamp` as a depositor's personal unlock, keeping the later of it and any existing one.
+fn extend_deposit_unlock(env: &Env, depositor: &Address, unlock_timestamp: u64) {
+    let unlock_key = DataKey::DepositUnlock(depositor.clone());
+    let existing: u64 = env.storage().persistent().get(&unlock_key).unwrap_or(0);
+    env.storage().persistent().set(&unlock_key, &existing.max(unlock_timestamp));
+    env.storage().persistent().extend_ttl(&unlock_key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+}
+
+// Returns an error if `unlock_timestamp` is more than MAX_LOCK_DURATION after the current ledger time.
+fn check_lock_duration(env: &Env, unlock_timestamp: u64) -> Result<(), VaultError> {
+    if unlock_timestamp > env.ledger().timestamp().saturating_add(MAX_LOCK_DURATION) {
+        return Err(VaultError::LockTooLong);
+    }
+    Ok(())
+}
+
+// Returns an error if the owner has paused the vault.
+// Called at the top of every function that moves tokens.
+fn ensure_not_paused(env: &Env) -> Result<(), VaultError> {
+    if env.storage().instance().get(&DataKey::Paused).unwrap_or(false) {
+        return Err(VaultError::Paused);
+    }
+    Ok(())
+}
+
+// Reads the unlocked fraction, in basis points, off an unlock curve at `elapsed_bps` of the vesting window.
+// The curve implicitly starts at (0, 0) and ends at (10000, 10000); between points it is linear.
+fn interpolate_curve(curve: &Vec<(u32, u32)>, elapsed_bps: u32) -> u32 {
+    let mut prev = (0u32, 0u32);
+    for (x, y) in curve.iter().chain(core::iter::once((10_000, 10_000))) {
+        if elapsed_bps <= x {
+            if x == prev.0 {
+                return y;
+            }
+            // Points are validated to be monotonic, so neither difference can underflow.
+            return prev.1 + ((y - prev.1) as u64 * (elapsed_bps - prev.0) as u64 / (x - prev.0) as u64) as u32;
+        }
+        prev = (x, y);
+    }
+    10_000
+}
+
+// Checks that an unlock curve's elapsed fractions strictly increase, its unlocked fractions never decrease,
+// and both stay within 10000 basis points.
+fn validate_unlock_curve(curve: &Vec<(u32, u32)>) -> Result<(), VaultError> {
+    if curve.is_empty() {
+        return Err(VaultError::InvalidUnlockCurve);
+    }
+    let mut prev: Option<(u32, u32)> = None;
+    for (x, y) in curve.iter() {
+        if x > 10_000 || y > 10_000 {
+            return Err(VaultError::InvalidUnlockCurve);
+        }
+        if let Some((prev_x, prev_y)) = prev {
+            if x <= prev_x || y < prev_y {
+                return Err(VaultError::InvalidUnlockCurve);
+            }
+        }
+        prev = Some((x, y));
+    }
+    Ok(())
+}
+
+// Computes how much of the vault has vested at the current ledger time.
+// The vesting base is everything ever locked for the owner: what is still locked plus what has already been withdrawn.
+// For a tranche schedule, the vested amount is the sum of every tranche whose timestamp has passed.
+// Otherwise nothing vests before the cliff, and after it the normal linear calculation applies.
+// With vesting disabled (a duration of 0) the whole base counts as vested and only the unlock timestamp applies.
+fn compute_vested(env: &Env) -> i128 {
+    let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0);
+    let withdrawn: i128 = env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0);
+    let total = locked_amount.saturating_add(withdrawn);
+
+    if let Some(schedule) = env.storage().instance().get::<_, Vec<(u64, i128)>>(&DataKey::Schedule) {
+        let now = env.ledger().timestamp();
+        let mut released: i128 = 0;
+        for (unlock_timestamp, amount) in schedule.iter() {
+            if unlock_timestamp > now {
+                break; // The schedule is sorted, so no later tranche can have matured either.
+            }
+            released = released.saturating_add(amount);
+        }
+        return released.min(total);
+    }
+    vested_of(env, total)
+}
+
+// Computes how much of `total` has vested at the current ledger time under the cliff, linear vesting, and unlock
+// curve; tranche schedules are handled by the callers.
+fn vested_of(env: &Env, total: i128) -> i128 {
+    let cliff: u64 = env.storage().instance().get(&DataKey::CliffTimestamp).unwrap_or(0);
+    if env.ledger().timestamp() < cliff {
+        return 0;
+    }
+
+    let duration: u64 = env.storage().instance().get(&DataKey::VestingDuration).unwrap_or(0);
+    if duration == 0 {
+        return total;
+    }
+    let start: u64 = env.storage().instance().get(&DataKey::VestingStart).unwrap_or(0);
+    let elapsed = env.ledger().timestamp().saturating_sub(start);
+    if elapsed >= duration {
+        return total;
+    }
+
+    if let Some(curve) = env.storage().instance().get::<_, Vec<(u32, u32)>>(&DataKey::UnlockCurve) {
+        // elapsed < duration, so this is below 10000 and fits a u32.
+        let elapsed_bps = (elapsed as u128 * 10_000 / duration as u128) as u32;
+        let unlocked_bps = interpolate_curve(&curve, elapsed_bps) as i128;
+        return (total / 10_000) * unlocked_bps + (total % 10_000) * unlocked_bps / 10_000;
+    }
+
+    // total * elapsed / duration, split into quotient and remainder so the multiplication can't overflow.
+    let (duration, elapsed) = (duration as i128, elapsed as i128);
+    (total / duration) * elapsed + (total % duration) * elapsed / duration
+}
+
+// Declare the smart contract struct.
+// This is a placeholder; the actual contract logic is in the impl block.
+#[contract]
+pub struct VaultContract;
+
+// Implement the contract's public functions.
+// Functions annotated with #[contractimpl] are callable from outside the contract.
+#[contractimpl]
+impl VaultContract {
+    /// Initializes the vault contract.
+    /// This function sets up the initial state of the vault and can only be called once.
+    /// Emits an `init` event with topics `(init, owner, token_id)` and data `unlock_timestamp`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment, providing access to ledger, storage, etc.
+    /// * owner - The address of the account that will own and control this vault.
+    /// * token_id - The address of the token contract that this vault will manage. To lock native XLM, pass the
+    ///              native Stellar Asset Contract's address; it speaks the same token interface, so nothing else changes.
+    /// * unlock_timestamp - The specific ledger close time (in seconds since epoch)
+    ///                        after which the owner can withdraw funds.
+    /// * options - Optional features (vesting, cliff, emergency withdrawal penalty). See VaultOptions.
+    pub fn initialize(env: Env, owner: Address, token_id: Address, unlock_timestamp: u64, options: VaultOptions) -> Result<(), VaultError> {
+        // Check if the contract has already been initialized.
+        // This uses the dedicated 'Initialized' flag rather than any config key, because some keys (like 'Owner')
+        // can be cleared later and that must never reopen the vault to a fresh initialize.
+        // A closed vault has no Initialized flag left, so its own marker keeps it shut.
+        if is_initialized(&env) || env.storage().instance().has(&DataKeyExt::Closed) {
+            // If it has, return an error. Returning Err from a contract function reverts all changes.
+            return Err(VaultError::AlreadyInitialized);
+        }
+
+        // The owner can't be the token contract, and the vault can't manage itself as its token,
+        // which would send every transfer straight back into the vault.
+        if owner == token_id || token_id == env.current_contract_address() {
+            return Err(VaultError::InvalidAddress);
+        }
+
+        // An unlock time that has already passed would leave the vault unlocked from the start.
+        // An unlock time equal to the current ledger time is allowed, for vaults that are meant to be open immediately.
+        if unlock_timestamp < env.ledger().timestamp() {
+            return Err(VaultError::UnlockInPast);
+        }
+        check_lock_duration(&env, unlock_timestamp)?;
+        // A rolling term longer than the lock limit would make every deposit fail.
+        if options.auto_extend_seconds > MAX_LOCK_DURATION {
+            return Err(VaultError::LockTooLong);
+        }
+
+        if options.deposit_open_until != 0 && options.deposit_open_until < options.deposit_open_from {
+            return Err(VaultError::InvalidSchedule);
+        }
+
+        // A cliff after the vesting window ends would never let anything vest on schedule.
+        // A cliff exactly at the end of the window is allowed and unlocks everything at once.
+        if options.cliff_timestamp > options.vesting_start.saturating_add(options.vesting_duration) {
+            return Err(VaultError::InvalidCliff);
+        }
+        // A deposit fee of 100% would credit depositors nothing, so it must stay below 10000.
+        if options.penalty_bps > 10_000 || options.fee_bps > 10_000 || options.deposit_fee_bps >= 10_000 {
+            return Err(VaultError::InvalidBasisPoints);
+        }
+        // A payout split must hand out exactly the whole amount.
+        let mut split_total: u32 = 0;
+        for (_, bps) in options.payout_split.iter() {
+            split_total = split_total.checked_add(bps).ok_or(VaultError::InvalidBasisPoints)?;
+        }
+        if !options.payout_split.is_empty() && split_total != 10_000 {
+            return Err(VaultError::InvalidBasisPoints);
+        }
+        validate_signers(&options.signers, options.signer_threshold)?;
+
+        // Store the initial state values in instance storage.
+        // env.storage().instance().set() writes data persistently to the blockchain.
+        env.storage().instance().set(&DataKey::Owner, &owner);
+        env.storage().instance().set(&DataKey::LastOwnerAction, &env.ledger().timestamp());
+        env.storage().instance().set(&DataKey::TokenId, &token_id);
+        env.storage().instance().set(&DataKey::UnlockTimestamp, &unlock_timestamp);
+        env.storage().instance().set(&DataKey::VestingStart, &options.vesting_start);
+        env.storage().instance().set(&DataKey::VestingDuration, &options.vesting_duration);
+        env.storage().instance().set(&DataKey::CliffTimestamp, &options.cliff_timestamp);
+        env.storage().instance().set(&DataKey::PenaltyBps, &options.penalty_bps);
+        env.storage().instance().set(&DataKey::PenaltyRecipient, &options.penalty_recipient);
+        env.storage().instance().set(&DataKey::FeeBps, &options.fee_bps);
+        env.storage().instance().set(&DataKey::FeeCollector, &options.fee_collector);
+        env.storage().instance().set(&DataKey::MaxTotalDeposit, &options.max_total_deposit);
+        env.storage().instance().set(&DataKey::MinDeposit, &options.min_deposit);
+        env.storage().instance().set(&DataKey::InactivityWindow, &options.inactivity_window);
+        env.storage().instance().set(&DataKey::RewardRate, &options.reward_rate);
+        env.storage().instance().set(&DataKey::GracePeriod, &options.grace_period);
+        env.storage().instance().set(&DataKey::MinWithdrawal, &options.min_withdrawal);
+        env.storage().instance().set(&DataKeyExt::DepositCooldown, &options.deposit_cooldown);
+        env.storage().instance().set(&DataKeyExt::CreditReceived, &options.credit_received);
+        env.storage().instance().set(&DataKeyExt::AllowForceUnlock, &options.allow_force_unlock);
+        env.storage().instance().set(&DataKeyExt::AutoExtendSeconds, &options.auto_extend_seconds);
+        env.storage().instance().set(&DataKeyExt::ActionDelay, &options.action_delay);
+        env.storage().instance().set(&DataKeyExt::DepositOpenFrom, &options.deposit_open_from);
+        env.storage().instance().set(&DataKeyExt::DepositOpenUntil, &options.deposit_open_until);
+        env.storage().instance().set(&DataKeyExt::MinHoldSeconds, &options.min_hold_seconds);
+        env.storage().instance().set(&DataKeyExt::BalanceEvents, &options.balance_events);
+        env.storage().instance().set(&DataKeyExt::DepositFeeBps, &options.deposit_fee_bps);
+        env.storage().instance().set(&DataKeyExt::DepositFeeRetained, &options.deposit_fee_retained);
+        if !options.payout_split.is_empty() {
+            env.storage().instance().set(&DataKeyExt::PayoutSplit, &options.payout_split);
+        }
+        if let Some(admin) = &options.admin {
+            env.storage().instance().set(&DataKey::Admin, admin);
+        }
+        // Signers can only be set here, so a compromised owner key can never switch the approval requirement off.
+        if !options.signers.is_empty() {
+            env.storage().instance().set(&DataKeyExt::Signers, &options.signers);
+            env.storage().instance().set(&DataKeyExt::SignerThreshold, &options.signer_threshold);
+        }
+        if let Some(label) = &options.label {
+            env.storage().instance().set(&DataKeyExt::Label, label);
+        }
+        if let Some(oracle) = &options.oracle {
+            env.storage().instance().set(&DataKeyExt::Oracle, oracle);
+        }
+        if let Some(recovery_address) = &options.recovery_address {
+            env.storage().instance().set(&DataKeyExt::RecoveryAddress, recovery_address);
+            env.storage().instance().set(&DataKeyExt::RecoveryDelay, &options.recovery_delay);
+        }
+        if let Some(notify_contract) = &options.notify_contract {
+            env.storage().instance().set(&DataKeyExt::NotifyContract, notify_contract);
+            env.storage().instance().set(&DataKeyExt::NotifyStrict, &options.notify_strict);
+        }
+        if let Some(destination) = &options.withdrawal_destination {
+            env.storage().instance().set(&DataKey::WithdrawalDestination, destination);
+        }
+        // Initialize the locked and withdrawn amounts to 0.
+        write_persistent_amount(&env, &DataKey::LockedAmount, 0);
+        env.storage().instance().set(&DataKey::Withdrawn, &0i128);
+        env.storage().instance().set(&DataKey::Version, &VERSION);
+        // Mark the vault as set up only once every key above has been written.
+        env.storage().instance().set(&DataKey::Initialized, &true);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+
+        // Announce the new vault for deployment tooling, now that every storage write has been made.
+        env.events().publish((symbol_short!("init"), owner, token_id), unlock_timestamp);
+        Ok(())
+    }
+
+    /// Initializes the vault with a schedule of discrete unlock tranches instead of a single unlock time.
+    /// The owner can withdraw up to the sum of all tranches whose timestamps have passed, minus what they
+    /// have already withdrawn. Like initialize, this can only be called once.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * owner - The address of the account that will own and control this vault.
+    /// * token_id - The address of the token contract that this vault will manage. To lock native XLM, pass the
+    ///              native Stellar Asset Contract's address; it speaks the same token interface, so nothing else changes.
+    /// * tranches - A non-empty list of (unlock_timestamp, amount) pairs sorted by timestamp.
+    ///                Every amount must be positive.
+    pub fn initialize_with_schedule(env: Env, owner: Address, token_id: Address, tranches: Vec<(u64, i128)>) -> Result<(), VaultError> {
+        // Validate the schedule before touching storage.
+        let first = tranches.first().ok_or(VaultError::InvalidSchedule)?;
+        let mut previous_timestamp = first.0;
+        for (unlock_timestamp, amount) in tranches.iter() {
+            if unlock_timestamp < previous_timestamp || amount <= 0 {
+                return Err(VaultError::InvalidSchedule);
+            }
+            previous_timestamp = unlock_timestamp;
+        }
+
+        // Nothing can be withdrawn before the first tranche, so that becomes the vault's unlock time.
+        // Schedule vaults have no linear vesting, emergency exit, or withdrawal fee, so the recipients are never used.
+        let options = VaultOptions {
+            vesting_start: 0,
+            vesting_duration: 0,
+            cliff_timestamp: 0,
+            penalty_bps: 0,
+            penalty_recipient: owner.clone(),
+            fee_bps: 0,
+            fee_collector: owner.clone(),
+            max_total_deposit: 0,
+            min_deposit: 0,
+            inactivity_window: 0,
+            reward_rate: 0,
+            withdrawal_destination: None,
+            grace_period: 0,
+            min_withdrawal: 0,
+            admin: None,
+            notify_contract: None,
+            notify_strict: false,
+            deposit_cooldown: 0,
+            credit_received: false,
+            allow_force_unlock: false,
+            recovery_address: None,
+            recovery_delay: 0,
+            auto_extend_seconds: 0,
+            action_delay: 0,
+            deposit_open_from: 0,
+            deposit_open_until: 0,
+            min_hold_seconds: 0,
+            balance_events: false,
+            oracle: None,
+            deposit_fee_bps: 0,
+            deposit_fee_retained: false,
+            label: None,
+            signers: Vec::new(&env),
+            signer_threshold: 0,
+            payout_split: Vec::new(&env),
+        };
+        // The schedule is written first so initialize's `init` event still comes after every storage write.
+        // If initialize fails, the whole call reverts, schedule included.
+        env.storage().instance().set(&DataKey::Schedule, &tranches);
+        Self::initialize(env.clone(), owner, token_id, first.0, options)
+    }
+
+    /// Deposits tokens into the vault.
+    /// No prior `approve` is needed: the token `transfer` is a sub-invocation of this call, so the
+    /// depositor's single authorization of `deposit` also covers moving their tokens.
+    /// Deposits of the vault's primary token (the one given to initialize) are credited to the depositor's balance
+    /// and emit a `deposit` event with topics `(deposit, from)` and data `(amount, locked_amount, deposit_seq)`.
+    /// Deposits of any other whitelisted token only add to that token's total and emit a `deposit` event with
+    /// topics `(deposit, from, token_id)` and data `(amount, token_balance, deposit_seq)`.
+    /// `deposit_seq` counts every deposit event the vault has emitted, starting from 1.
+    /// If a deposit fee is configured, `amount * deposit_fee_bps / 10000` of a primary-token deposit goes to the fee
+    /// collector, or stays locked for the owner with deposit_fee_retained, and only the rest is credited and reported.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * token_id - The token to deposit: the primary token or one the owner added with add_allowed_token.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit. Must be positive. For the primary token it must also be at least
+    ///            the minimum deposit and must not push the locked total above the deposit cap if one is set.
+    ///
+    /// Returns the new locked amount (or, for other tokens, that token's new total), saving a follow-up read.
+    pub fn deposit(env: Env, token_id: Address, from: Address, amount: i128) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        let primary_token: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        if token_id != primary_token {
+            return deposit_other_token(&env, &token_id, &from, amount);
+        }
+        deposit_from(&env, &from, &from, amount, 10_000, None)
+    }
+
+    /// Deposits primary tokens paid for by a sponsor but credited to someone else.
+    /// `from` pays and must authorize the call; the deposit counts towards `beneficiary`'s balance, so only the
+    /// beneficiary can later take it out with withdraw_own. The deposit allowlist and cooldown apply to the sponsor,
+    /// and neither address may be blocked. Otherwise this behaves exactly like deposit, including returning the new
+    /// locked amount, but emits a `dep_for` event with topics `(dep_for, from, beneficiary)` and data
+    /// `(amount, locked_amount, deposit_seq)` instead of a `deposit` event.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The sponsor paying for the deposit. This account must authorize the call.
+    /// * beneficiary - The address credited with the deposit. It doesn't need to sign.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    pub fn deposit_for(env: Env, from: Address, beneficiary: Address, amount: i128) -> Result<i128, VaultError> {
+        from.require_auth();
+        deposit_from(&env, &from, &beneficiary, amount, 10_000, None)
+    }
+
+    /// Deposits as much of `amount` as fits under the deposit cap instead of reverting when it doesn't all fit.
+    /// Deposits `min(amount, cap - current total)` of the primary token, with the usual minimum-deposit rule applied to
+    /// the accepted amount; without a cap this is the same as deposit. The `deposit` event reports the accepted amount.
+    /// Fails with CapExceeded, moving nothing, only if the vault is already full.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The most the depositor is willing to deposit. Must be a positive value.
+    ///
+    /// Returns the amount that was actually accepted; the rest stays with the depositor.
+    pub fn deposit_up_to_cap(env: Env, from: Address, amount: i128) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+        let accepted = match deposit_room(&env)? {
+            Some(0) => return Err(VaultError::CapExceeded),
+            Some(room) => amount.min(room),
+            None => amount,
+        };
+        deposit_from(&env, &from, &from, accepted, 10_000, None)?;
+        Ok(accepted)
+    }
+
+    /// Deposits the owner's primary tokens into the vault, time-locking only part of them.
+    /// `amount * lock_fraction_bps / 10000` joins the locked amount as usual; the rest goes to a free portion that
+    /// the owner can withdraw at any time, even before unlock. Only the owner can do this, since the free portion only
+    /// comes out through withdraw. Otherwise this behaves exactly like deposit, including crediting the full amount to
+    /// the owner's balance and returning the new locked amount.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The owner, depositing their own tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    /// * lock_fraction_bps - The share of the deposit to time-lock, in basis points (0 to 10000).
+    pub fn deposit_partial(env: Env, from: Address, amount: i128, lock_fraction_bps: u32) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        // Anyone else's free portion would be the owner's to withdraw.
+        if read_owner(&env)? != from {
+            return Err(VaultError::Unauthorized);
+        }
+        if lock_fraction_bps > 10_000 {
+            return Err(VaultError::InvalidBasisPoints);
+        }
+        deposit_from(&env, &from, &from, amount, lock_fraction_bps, None)
+    }
+
+    /// Deposits primary tokens into the vault, tagging the deposit with a reference for off-chain reconciliation.
+    /// This behaves exactly like deposit (including returning the new locked amount), except the `deposit` event
+    /// carries data `(amount, locked_amount, deposit_seq, memo)`.
+    /// The memo is not stored, so it costs nothing beyond the event.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    /// * memo - An opaque 32-byte reference, such as a hash of a custodian's internal deposit ID.
+    pub fn deposit_with_memo(env: Env, from: Address, amount: i128, memo: BytesN<32>) -> Result<i128, VaultError> {
+        from.require_auth();
+        deposit_from(&env, &from, &from, amount, 10_000, Some(memo))
+    }
+
+    /// Deposits primary tokens at most once per client-supplied request id, so a deposit can be safely retried.
+    /// The first call with a given `request_id` behaves exactly like deposit and records the locked amount it returned.
+    /// Any later call from the same depositor with the same id moves nothing, emits nothing, and returns that recorded
+    /// result, even if `amount` differs. Ids are scoped to the depositor, so no one else can use up yours.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    /// * request_id - A unique 32-byte id the client picks for this deposit and reuses for every retry of it.
+    pub fn deposit_idempotent(env: Env, from: Address, amount: i128, request_id: BytesN<32>) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        let key = DataKeyExt::ProcessedRequest(from.clone(), request_id);
+        if let Some(result) = env.storage().persistent().get::<_, i128>(&key) {
+            return Ok(result);
+        }
+        let result = deposit_from(&env, &from, &from, amount, 10_000, None)?;
+        env.storage().persistent().set(&key, &result);
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        Ok(result)
+    }
+
+    /// Deposits tokens into the vault and locks the depositor's share until a personal unlock time.
+    /// This behaves exactly like deposit (including returning the new locked amount), but also records an unlock
+    /// time for the depositor that withdraw_own honours on top of the vault's own unlock.
+    /// Depositing again with an earlier time never shortens the lock.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    /// * unlock_timestamp - The ledger close time after which this depositor may withdraw.
+    ///                      Must not be earlier than the vault's unlock timestamp.
+    pub fn deposit_with_unlock(env: Env, from: Address, amount: i128, unlock_timestamp: u64) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        let vault_unlock: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?;
+        if unlock_timestamp < vault_unlock {
+            return Err(VaultError::UnlockBeforeVault);
+        }
+        check_lock_duration(&env, unlock_timestamp)?;
+
+        // Keep the later of any existing personal unlock and the new one.
+        extend_deposit_unlock(&env, &from, unlock_timestamp);
+
+        deposit_from(&env, &from, &from, amount, 10_000, None)
+    }
+
+    /// Deposits primary tokens and makes sure they stay locked for at least `min_lock_seconds` more.
+    /// For the owner, the whole vault's unlock becomes `max(current unlock, now + min_lock_seconds)`, and if it moves an
+    /// `extend` event is emitted as for extend_lock. Anyone else can only lock their own share, so for them the term
+    /// becomes a personal unlock that withdraw_own honours, as with deposit_with_unlock. Either way freshly added funds
+    /// are locked for a minimum term and no lock is ever shortened. Otherwise this behaves exactly like deposit,
+    /// including returning the new locked amount.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The address of the account depositing tokens. This account must authorize the call.
+    /// * amount - The amount of tokens to deposit, subject to the same rules as deposit.
+    /// * min_lock_seconds - The shortest time from now the funds may unlock. Must not exceed MAX_LOCK_DURATION.
+    pub fn deposit_and_extend(env: Env, from: Address, amount: i128, min_lock_seconds: u64) -> Result<i128, VaultError> {
+        from.require_auth();
+
+        let min_unlock = env.ledger().timestamp().saturating_add(min_lock_seconds);
+        if read_owner(&env)? == from {
+            extend_unlock_to(&env, min_unlock)?;
+        } else {
+            check_lock_duration(&env, min_unlock)?;
+            extend_deposit_unlock(&env, &from, min_unlock);
+        }
+        deposit_from(&env, &from, &from, amount, 10_000, None)
+    }
+
+    /// Deposits tokens from several accounts in one call, crediting each depositor's own balance.
+    /// Every depositor must authorize the call. The same minimum-deposit rule as deposit applies to each entry,
+    /// the deposit cap applies to the batch total, and the whole call reverts if any entry is invalid.
+    /// Emits one `deposit` event per entry, as if each had been a separate deposit.
+    /// Each entry must arrive in full, even in a vault initialized with credit_received.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * deposits - A non-empty list of (depositor, amount) pairs. Every amount must be positive.
+    pub fn deposit_batch(env: Env, deposits: Vec<(Address, i128)>) -> Result<(), VaultError> {
+        ensure_not_paused(&env)?;
+        check_deposit_window(&env)?;
+
+        // Validate every entry and add up the total before anything moves.
+        let mut total: i128 = 0;
+        for (from, amount) in deposits.iter() {
+            from.require_auth();
+            ensure_not_blocked(&env, &from)?;
+            ensure_depositor_allowed(&env, &from)?;
+            check_deposit_cooldown(&env, &from)?;
+            check_deposit_amount(&env, amount)?;
+            total = total.checked_add(amount).ok_or(VaultError::Overflow)?;
+        }
+        if total == 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+        check_deposit_cap(&env, total)?;
+        auto_extend_unlock(&env)?;
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        // Credit every depositor and the aggregate before calling out to the token contract.
+        // Each entry pays its own deposit fee, and the fees are passed on together once everything has arrived.
+        let mut fees: i128 = 0;
+        for (from, amount) in deposits.iter() {
+            let fee = deposit_fee(&env, amount);
+            credit_balance(&env, &from, &from, amount - fee)?;
+            fees += fee;
+        }
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        let new_locked_amount = locked_amount.checked_add(total - fees + retained_deposit_fee(&env, fees)).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, new_locked_amount);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+        add_to_lifetime_total(&env, &DataKeyExt::TotalDeposited, total)?;
+
+        let token_client = token::Client::new(&env, &token_id);
+        for (from, amount) in deposits.iter() {
+            receive_tokens(&env, &token_client, &from, amount)?;
+        }
+        pay_deposit_fee(&env, &token_client, fees)?;
+        exit_reentrancy_guard(&env);
+
+        // Report each deposit, net of its fee, with the locked amount as it stood after that entry.
+        let mut running_locked = locked_amount;
+        for (from, amount) in deposits.iter() {
+            let fee = deposit_fee(&env, amount);
+            let amount = amount - fee;
+            running_locked += amount + retained_deposit_fee(&env, fee);
+            let seq = next_seq(&env, &DataKey::DepositSeq)?;
+            env.events().publish((symbol_short!("deposit"), from), (amount, running_locked, seq));
+        }
+        Ok(())
+    }
+
+    /// Locks a specific NFT in the vault until the unlock timestamp.
+    /// The NFT contract must implement `transfer(from, to, token_id: u128)`; `from` must authorize the call, which also
+    /// covers that transfer. The same pause, blocklist, allowlist, and cooldown rules apply as for deposit.
+    /// Emits an `nft_dep` event with topics `(nft_dep, from, nft_contract)` and data `token_id`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * from - The account the NFT is taken from. This account must authorize the call.
+    /// * nft_contract - The NFT contract the token belongs to.
+    /// * token_id - The id of the NFT within that contract.
+    pub fn deposit_nft(env: Env, from: Address, nft_contract: Address, token_id: u128) -> Result<(), VaultError> {
+        from.require_auth();
+        ensure_not_paused(&env)?;
+        ensure_not_blocked(&env, &from)?;
+        ensure_depositor_allowed(&env, &from)?;
+        check_deposit_window(&env)?;
+        check_deposit_cooldown(&env, &from)?;
+        enter_reentrancy_guard(&env)?;
+
+        let key = DataKeyExt::LockedNft(nft_contract.clone(), token_id);
+        env.storage().persistent().set(&key, &from);
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        let count: u32 = env.storage().instance().get(&DataKeyExt::LockedNftCount).unwrap_or(0);
+        env.storage().instance().set(&DataKeyExt::LockedNftCount, &count.checked_add(1).ok_or(VaultError::Overflow)?);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+
+        // The NFT contract reverts the whole invocation if `from` doesn't own the token.
+        transfer_nft(&env, &nft_contract, &from, &env.current_contract_address(), token_id);
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("nft_dep"), from, nft_contract), token_id);
+        Ok(())
+    }
+
+    /// Withdraws a locked NFT after the unlock timestamp (plus any grace period) has passed.
+    /// Only whoever deposited the NFT can take it out. For an NFT the owner deposited, the same rules apply as for
+    /// withdraw, including the fixed withdrawal destination and the oracle's unlock condition; any other depositor must
+    /// authorize the call and is held to their own unlock, as with withdraw_own.
+    /// Emits an `nft_wd` event with topics `(nft_wd, to, nft_contract)` and data `token_id`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send the NFT to.
+    /// * nft_contract - The NFT contract the token belongs to.
+    /// * token_id - The id of the NFT within that contract.
+    pub fn withdraw_nft(env: Env, to: Address, nft_contract: Address, token_id: u128) -> Result<(), VaultError> {
+        let key = DataKeyExt::LockedNft(nft_contract.clone(), token_id);
+        let depositor: Address = env.storage().persistent().get(&key).ok_or(VaultError::NftNotLocked)?;
+        if read_owner(&env).ok() == Some(depositor.clone()) {
+            require_owner(&env)?;
+            ensure_no_signers(&env)?;
+            ensure_not_paused(&env)?;
+            check_destination(&env, &to)?;
+            if env.ledger().timestamp() < effective_unlock_time(&env)? || !unlock_condition_met(&env) {
+                return Err(VaultError::StillLocked);
+            }
+        } else {
+            depositor.require_auth();
+            ensure_not_paused(&env)?;
+            ensure_not_blocked(&env, &depositor)?;
+            ensure_not_blocked(&env, &to)?;
+            if env.ledger().timestamp() < own_unlock_time(&env, &depositor)? {
+                return Err(VaultError::StillLocked);
+            }
+        }
+        enter_reentrancy_guard(&env)?;
+
+        env.storage().persistent().remove(&key);
+        let count: u32 = env.storage().instance().get(&DataKeyExt::LockedNftCount).unwrap_or(0);
+        env.storage().instance().set(&DataKeyExt::LockedNftCount, &count.checked_sub(1).ok_or(VaultError::Overflow)?);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+
+        transfer_nft(&env, &nft_contract, &env.current_contract_address(), &to, token_id);
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("nft_wd"), to, nft_contract), token_id);
+        Ok(())
+    }
+
+    /// Returns whether the vault is holding the given NFT.
+    pub fn is_nft_locked(env: Env, nft_contract: Address, token_id: u128) -> bool {
+        env.storage().persistent().has(&DataKeyExt::LockedNft(nft_contract, token_id))
+    }
+
+    /// Withdraws tokens from the vault after the unlock timestamp has passed.
+    /// Only the vault owner can call this function. When vesting is enabled, the owner can only
+    /// withdraw up to the vested amount minus what they have already withdrawn of the primary token.
+    /// Locked tokens credited to other depositors stay theirs to take out with withdraw_own; whatever the owner takes
+    /// beyond the tokens nobody is credited with comes off the owner's own balance.
+    /// If a withdrawal fee is configured, `amount * fee_bps / 10000` is set aside for the fee collector (see
+    /// collect_fees) and the rest goes to `to`; the full amount is deducted from the locked total.
+    /// Emits a `withdraw` event with topics `(withdraw, to)` and data `(amount, remaining_locked_amount, withdraw_seq)`
+    /// for the primary token, or topics `(withdraw, to, token_id)` and data `(amount, remaining_token_balance,
+    /// withdraw_seq)` for any other token. `withdraw_seq` counts every withdraw event, starting from 1. Vesting and schedules only apply to the primary token.
+    /// Any free portion left by deposit_partial is paid out first and can be withdrawn even before unlock.
+    /// If a notify contract was set at initialize, its `on_withdraw(vault, to, amount)` is called after a primary-token
+    /// withdrawal; a failing callback only reverts the withdrawal if the vault was initialized with notify_strict.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * token_id - The token to withdraw: the primary token or any other token the vault holds a balance of.
+    /// * to - The address to send the withdrawn tokens to. Typically the owner's address.
+    /// * amount - The amount of tokens to withdraw. Must be a positive value, and for the primary token at least the
+    ///            minimum withdrawal unless the vault holds less than that in total. If it would leave less than the
+    ///            minimum behind and everything is withdrawable, the whole remaining balance is withdrawn instead.
+    ///
+    /// Returns the amount still locked afterwards (or, for other tokens, that token's remaining total).
+    pub fn withdraw(env: Env, token_id: Address, to: Address, amount: i128) -> Result<i128, VaultError> {
+        // Ensure that only the owner has authorized this transaction.
+        require_owner(&env)?;
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+
+        // Validate the withdrawal amount.
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        // Other whitelisted tokens have their own, simpler bookkeeping.
+        let primary_token: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        if token_id != primary_token {
+            return withdraw_other_token(&env, &token_id, &to, amount);
+        }
+
+        // The free portion is always available and is used first; only the rest comes out of locked tokens.
+        let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+
+        let amount = round_up_final_withdrawal(&env, amount, free_amount)?;
+        check_min_withdrawal(&env, amount, free_amount)?;
+        let from_free = amount.min(free_amount);
+        let from_locked = amount - from_free;
+
+        // Check the unlock time, the locked funds, and the vesting cap for the locked part.
+        let (mut locked_amount, mut withdrawn) = if from_locked > 0 {
+            check_owner_withdrawal(&env, from_locked)?
+        } else {
+            let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+            (locked_amount, env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0))
+        };
+        record_limited_withdrawal(&env, amount)?;
+        enter_reentrancy_guard(&env)?;
+
+        // Update the total locked amount before any tokens leave (checks-effects-interactions),
+        // so a malicious token re-entering here would already see the reduced balance.
+        // Use checked_sub to prevent integer underflow.
+        debit_owner_share(&env, amount)?;
+        if from_free > 0 {
+            write_persistent_amount(&env, &DataKey::FreeAmount, free_amount - from_free);
+        }
+        locked_amount = locked_amount.checked_sub(from_locked).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, locked_amount);
+        withdrawn = withdrawn.checked_add(from_locked).ok_or(VaultError::Overflow)?;
+        env.storage().instance().set(&DataKey::Withdrawn, &withdrawn);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+        add_to_lifetime_total(&env, &DataKeyExt::TotalWithdrawn, amount)?;
+
+        // Create a client to interact with the token contract and send the tokens, minus any fee.
+        let token_client = token::Client::new(&env, &token_id);
+        pay_out(&env, &token_client, &to, amount)?;
+        exit_reentrancy_guard(&env);
+
+        // Publish a withdraw event carrying the amount sent and what remains locked.
+        // Like deposit, this only runs after the transfer and the locked amount update have succeeded.
+        let seq = next_seq(&env, &DataKey::WithdrawSeq)?;
+        env.events().publish((symbol_short!("withdraw"), to.clone()), (amount, locked_amount, seq));
+
+        // Let an integration react now that the funds are gone.
+        notify_withdrawal(&env, &to, amount);
+        Ok(locked_amount)
+    }
+
+    /// Previews a primary-token withdraw of `amount` without moving any tokens or requiring any auth.
+    /// The same rounding, minimum, unlock, and vesting rules as withdraw apply, and the same errors are returned;
+    /// checks tied to the caller or recipient, like pausing, the withdrawal destination, and the withdrawal limit, are not.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * amount - The amount that would be passed to withdraw.
+    ///
+    /// Returns `(net_to_recipient, fee)`, which together make up the amount withdraw would actually deduct.
+    pub fn preview_withdraw(env: Env, amount: i128) -> Result<(i128, i128), VaultError> {
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+        let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+        let amount = round_up_final_withdrawal(&env, amount, free_amount)?;
+        check_min_withdrawal(&env, amount, free_amount)?;
+        let from_locked = amount - amount.min(free_amount);
+        if from_locked > 0 {
+            check_owner_withdrawal(&env, from_locked)?;
+        }
+
+        let fee = withdrawal_fee(&env, amount)?;
+        Ok((amount - fee, fee))
+    }
+
+    /// Withdraws tokens to several recipients in one call, after the unlock timestamp has passed.
+    /// Only the vault owner can call this function. The same unlock, vesting, and fee rules as withdraw apply
+    /// to the batch total, and the whole call reverts if any amount is invalid or the total can't be covered.
+    /// Emits one `withdraw` event per recipient, as if each had been a separate withdraw.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * recipients - A non-empty list of (recipient, amount) pairs. Every amount must be positive.
+    pub fn withdraw_batch(env: Env, recipients: Vec<(Address, i128)>) -> Result<(), VaultError> {
+        require_owner(&env)?;
+        withdraw_to_many(&env, &recipients)
+    }
+
+    /// Pays `amount` of the vault's locked tokens out across the payout split fixed at initialize.
+    /// Only the owner can call this, under the same rules as withdraw_batch (unlock, vesting, limit, and fee), and it
+    /// emits the same per-recipient `withdraw` events. Each recipient gets `amount * bps / 10000`, rounded down, except
+    /// the last, who also gets whatever rounding left over so the full amount is always paid.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * amount - The total to distribute. Must be positive.
+    pub fn distribute(env: Env, amount: i128) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        let split: Vec<(Address, u32)> = env.storage().instance().get(&DataKeyExt::PayoutSplit).ok_or(VaultError::NoPayoutSplit)?;
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        let mut shares: Vec<(Address, i128)> = Vec::new(&env);
+        let mut paid: i128 = 0;
+        for (index, (recipient, bps)) in split.iter().enumerate() {
+            let share = if index as u32 == split.len() - 1 {
+                amount - paid
+            } else {
+                amount.checked_mul(bps as i128).ok_or(VaultError::Overflow)? / 10_000
+            };
+            paid += share;
+            // A share can round down to nothing; withdraw_batch-style payouts must be positive.
+            if share > 0 {
+                shares.push_back((recipient, share));
+            }
+        }
+        withdraw_to_many(&env, &shares)
+    }
+
+    /// Tops up the reward pool that claim_rewards pays out of.
+    /// Only the owner can call this. The tokens are the vault's primary token and are transferred from the owner;
+    /// they are kept apart from the locked amount, so they can't be withdrawn as deposits.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * amount - The amount of tokens to add to the pool. Must be a positive value.
+    pub fn fund_rewards(env: Env, amount: i128) -> Result<(), VaultError> {
+        let owner = require_owner(&env)?;
+        ensure_not_paused(&env)?;
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        let pool: i128 = env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0);
+        let pool = pool.checked_add(amount).ok_or(VaultError::Overflow)?;
+        env.storage().instance().set(&DataKey::RewardPool, &pool);
+        extend_instance_ttl(&env);
+
+        receive_tokens(&env, &token::Client::new(&env, &token_id), &owner, amount)?;
+        exit_reentrancy_guard(&env);
+        Ok(())
+    }
+
+    /// Pays a depositor every reward they have earned so far out of the reward pool.
+    /// Rewards accrue each second at `balance * reward_rate / REWARD_RATE_SCALE` from the time of deposit.
+    /// The depositor must authorize the call. Fails with InsufficientRewards, paying nothing, if the pool can't
+    /// cover the full amount owed. Emits a `reward` event with topics `(reward, depositor)` and data `amount`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * depositor - The depositor claiming their rewards, who also receives them.
+    pub fn claim_rewards(env: Env, depositor: Address) -> Result<i128, VaultError> {
+        depositor.require_auth();
+        ensure_not_paused(&env)?;
+        ensure_not_blocked(&env, &depositor)?;
+
+        let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+        let owed = settle_rewards(&env, &depositor, balance)?;
+        let pool: i128 = env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0);
+        if owed > pool {
+            return Err(VaultError::InsufficientRewards);
+        }
+        if owed == 0 {
+            return Ok(0);
+        }
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        env.storage().instance().set(&DataKey::RewardPool, &(pool - owed));
+        write_persistent_amount(&env, &DataKey::AccruedRewards(depositor.clone()), 0);
+        extend_instance_ttl(&env);
+
+        token::Client::new(&env, &token_id).transfer(&env.current_contract_address(), &depositor, &owed);
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("reward"), depositor), owed);
+        Ok(owed)
+    }
+
+    /// Sends every primary token the vault holds to `to` and zeroes its bookkeeping, as a safety valve for stuck dust.
+    /// Only the owner can call this, and only once everything still locked could be withdrawn anyway: after the unlock,
+    /// past any vesting or relock, and with no withdrawal requests pending. What the books still track, the locked
+    /// amount, the free portion, and the reward pool together, must be below the minimum withdrawal, or 0 in a vault
+    /// without one; otherwise it reverts with VaultNotEmpty, so this can't be used to empty a vault at once.
+    /// The locked amount, the free portion, and the reward pool are then cleared and the token contract's real
+    /// balance is paid out in full, with no fee, counting against the withdrawal limit. Depositor balances are left as
+    /// they were, and so are accrued fees, which still belong to the fee collector.
+    /// Emits a `swept` event with topics `(swept, to)` and data `amount`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send everything to.
+    ///
+    /// Returns the amount swept.
+    pub fn sweep(env: Env, to: Address) -> Result<i128, VaultError> {
+        require_owner(&env)?;
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+
+        // Everything still locked must be withdrawable right now, so the sweep never jumps a vesting schedule or relock.
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        if env.ledger().timestamp() < effective_unlock_time(&env)? || !unlock_condition_met(&env) {
+            return Err(VaultError::StillLocked);
+        }
+        // Pending withdrawal requests have a claim on the locked amount that sweeping would leave unpaid.
+        if pending_withdrawals(&env) > 0 {
+            return Err(VaultError::VaultNotEmpty);
+        }
+        check_owner_withdrawal(&env, locked_amount)?;
+
+        // Only dust may be swept: anything the books track at or above the minimum has to go through withdraw.
+        let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+        let reward_pool: i128 = env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0);
+        let dust_bound: i128 = env.storage().instance().get::<_, i128>(&DataKey::MinWithdrawal).unwrap_or(0).max(1);
+        if locked_amount.saturating_add(free_amount).saturating_add(reward_pool) >= dust_bound {
+            return Err(VaultError::VaultNotEmpty);
+        }
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        let token_client = token::Client::new(&env, &token_id);
+        let accrued_fees: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+        let amount = token_client.balance(&env.current_contract_address()).saturating_sub(accrued_fees).max(0);
+        record_limited_withdrawal(&env, amount)?;
+        enter_reentrancy_guard(&env)?;
+
+        debit_owner_share(&env, locked_amount.saturating_add(free_amount))?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, 0);
+        write_persistent_amount(&env, &DataKey::FreeAmount, 0);
+        env.storage().instance().set(&DataKey::RewardPool, &0i128);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+        add_to_lifetime_total(&env, &DataKeyExt::TotalWithdrawn, amount)?;
+
+        if amount > 0 {
+            token_client.transfer(&env.current_contract_address(), &to, &amount);
+        }
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("swept"), to), amount);
+        Ok(amount)
+    }
+
+    /// Pays the withdrawal fees the vault has accrued in its primary token out to `to` and resets them to 0.
+    /// Only the fee collector can call this. Accrued fees are kept apart from the locked amount, so collecting them
+    /// never touches depositor funds. Emits a `fees` event with topics `(fees, to)` and data `amount`.
+    /// Returns the amount collected, which is 0 if nothing had accrued.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send the fees to.
+    pub fn collect_fees(env: Env, to: Address) -> Result<i128, VaultError> {
+        let fee_collector: Address = env.storage().instance().get(&DataKey::FeeCollector).ok_or(VaultError::NotInitialized)?;
+        fee_collector.require_auth();
+        ensure_not_blocked(&env, &to)?;
+
+        let amount: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+        if amount == 0 {
+            return Ok(0);
+        }
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        env.storage().instance().set(&DataKeyExt::AccruedFees, &0i128);
+        extend_instance_ttl(&env);
+        token::Client::new(&env, &token_id).transfer(&env.current_contract_address(), &to, &amount);
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("fees"), to), amount);
+        Ok(amount)
+    }
+
+    /// Returns the primary-token withdrawal fees waiting for the fee collector.
+    pub fn get_accrued_fees(env: Env) -> i128 {
+        env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0)
+    }
+
+    /// Folds any primary tokens the vault holds beyond its bookkeeping into the locked amount.
+    /// Tokens sent straight to the vault's address (rather than through deposit) aren't tracked anywhere, so they
+    /// can't be withdrawn; this picks them up. Only the owner can call this. The surplus is not credited to any
+    /// depositor's balance. Emits a `reconcile` event with data `(surplus, locked_amount)` when there is a surplus.
+    /// Returns the surplus that was credited, which is 0 if the books already matched.
+    pub fn reconcile(env: Env) -> Result<i128, VaultError> {
+        require_owner(&env)?;
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        let actual = token::Client::new(&env, &token_id).balance(&env.current_contract_address());
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        let tracked = tracked_balance(&env)?;
+        let surplus = actual.checked_sub(tracked).ok_or(VaultError::Overflow)?;
+        if surplus <= 0 {
+            return Ok(0);
+        }
+
+        let locked_amount = locked_amount.checked_add(surplus).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, locked_amount);
+        extend_instance_ttl(&env);
+        env.events().publish((symbol_short!("reconcile"),), (surplus, locked_amount));
+        Ok(surplus)
+    }
+
+    /// Reports whether the vault's books match the primary tokens it really holds, for monitoring.
+    /// Returns `(true, 0)` when they match, or `false` and the actual balance minus the tracked amount (the locked
+    /// amount plus the free portion, reward pool, and accrued fees): positive for a surplus such as a direct transfer,
+    /// which reconcile can fold in, and negative for a shortfall. This never reverts; an uninitialized vault, or one
+    /// whose token can't report a balance, returns the sentinel `(false, 0)`.
+    pub fn healthcheck(env: Env) -> (bool, i128) {
+        let Some(token_id) = env.storage().instance().get::<_, Address>(&DataKey::TokenId) else {
+            return (false, 0);
+        };
+        let Ok(tracked) = tracked_balance(&env) else {
+            return (false, 0);
+        };
+        let Ok(Ok(actual)) = token::Client::new(&env, &token_id).try_balance(&env.current_contract_address()) else {
+            return (false, 0);
+        };
+        match actual.checked_sub(tracked) {
+            Some(0) => (true, 0),
+            Some(delta) => (false, delta),
+            None => (false, 0),
+        }
+    }
+
+    /// Moves the vault onto a new primary token, for when the token contract it manages is itself migrated.
+    /// Only the owner can call this. An empty vault just switches its token. A vault still holding primary tokens
+    /// must have unlocked, so depositors' locked funds are never swapped out from under them, and needs a
+    /// `swap_contract`: the vault sends it every old token it holds and then calls its `swap(to, amount)`, which must
+    /// pay the vault exactly as many new tokens in return. The books carry over one for one, so every locked amount,
+    /// balance, pool, and fee stays as it was. Emits a `tok_mig` event with topics `(tok_mig, old_token, new_token)` and
+    /// data `amount`, the number of tokens swapped.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * new_token_id - The token contract the vault will manage from now on.
+    /// * swap_contract - The contract that exchanges old tokens for new ones; only needed if the vault holds any.
+    pub fn migrate_token(env: Env, new_token_id: Address, swap_contract: Option<Address>) -> Result<(), VaultError> {
+        let owner = require_owner(&env)?;
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+
+        let vault = env.current_contract_address();
+        let old_token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        // The same address checks as initialize, and the new token can't already be tracked as a secondary one.
+        let secondary_balance: i128 = env.storage().persistent().get(&DataKey::TokenBalance(new_token_id.clone())).unwrap_or(0);
+        if new_token_id == old_token_id || new_token_id == owner || new_token_id == vault || secondary_balance != 0 {
+            return Err(VaultError::InvalidAddress);
+        }
+
+        let old_token = token::Client::new(&env, &old_token_id);
+        let amount = old_token.balance(&vault);
+        if amount > 0 {
+            if env.ledger().timestamp() < effective_unlock_time(&env)? || !unlock_condition_met(&env) {
+                return Err(VaultError::StillLocked);
+            }
+            let swap_contract = swap_contract.ok_or(VaultError::VaultNotEmpty)?;
+            enter_reentrancy_guard(&env)?;
+            old_token.transfer(&vault, &swap_contract, &amount);
+            let new_token = token::Client::new(&env, &new_token_id);
+            let before = new_token.balance(&vault);
+            let args: Vec<soroban_sdk::Val> = (vault.clone(), amount).into_val(&env);
+            env.invoke_contract::<()>(&swap_contract, &Symbol::new(&env, "swap"), args);
+            if new_token.balance(&vault).checked_sub(before).ok_or(VaultError::Overflow)? != amount {
+                return Err(VaultError::TransferAmountMismatch);
+            }
+            exit_reentrancy_guard(&env);
+        }
+
+        env.storage().instance().set(&DataKey::TokenId, &new_token_id);
+        extend_instance_ttl(&env);
+        env.events().publish((symbol_short!("tok_mig"), old_token_id, new_token_id), amount);
+        Ok(())
+    }
+
+    /// Adds a token to the vault's whitelist so it can be deposited and withdrawn alongside the primary token.
+    /// Only the admin can call this. Adding a token that is already listed, or the primary token itself, is a no-op.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * token_id - The address of the token contract to accept.
+    pub fn add_allowed_token(env: Env, token_id: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().instance().set(&DataKey::AllowedToken(token_id), &true);
+        Ok(())
+    }
+
+    /// Removes a token from the vault's whitelist so no further deposits of it are accepted.
+    /// Only the admin can call this. Any balance of the token already in the vault can still be withdrawn,
+    /// so delisting a token never strands funds. The primary token is always accepted and can't be removed.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * token_id - The address of the token contract to stop accepting.
+    pub fn remove_allowed_token(env: Env, token_id: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().instance().remove(&DataKey::AllowedToken(token_id));
+        Ok(())
+    }
+
+    /// Blocks an address, for compliance with sanctions lists.
+    /// Only the admin can call this. A blocked address can't deposit, can't be the destination of any withdrawal,
+    /// and can't withdraw its own balance or claim rewards. Blocking takes precedence over the depositor allowlist.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * addr - The address to block.
+    pub fn block_address(env: Env, addr: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        let key = DataKey::Blocked(addr);
+        env.storage().persistent().set(&key, &true);
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        Ok(())
+    }
+
+    /// Lifts a block placed with block_address. Only the admin can call this. Unblocking an address that isn't
+    /// blocked is a no-op.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * addr - The address to unblock.
+    pub fn unblock_address(env: Env, addr: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().persistent().remove(&DataKey::Blocked(addr));
+        Ok(())
+    }
+
+    /// Turns the depositor allowlist on or off, for invite-only vaults.
+    /// Only the admin can call this. While it's on, every deposit path rejects depositors that haven't been added
+    /// with add_depositor; while it's off (the default), anyone can deposit. The list itself is kept either way.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * enabled - Whether only allowlisted addresses may deposit.
+    pub fn set_depositor_allowlist(env: Env, enabled: bool) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().instance().set(&DataKey::DepositorAllowlist, &enabled);
+        Ok(())
+    }
+
+    /// Adds an address to the depositor allowlist. Only the admin can call this. Adding it twice is a no-op.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * addr - The address to let deposit while the allowlist is on.
+    pub fn add_depositor(env: Env, addr: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        let key = DataKey::AllowedDepositor(addr);
+        env.storage().persistent().set(&key, &true);
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        Ok(())
+    }
+
+    /// Removes an address from the depositor allowlist. Only the admin can call this.
+    /// The address's existing balance is untouched; it just can't deposit more while the allowlist is on.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * addr - The address to stop accepting deposits from.
+    pub fn remove_depositor(env: Env, addr: Address) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().persistent().remove(&DataKey::AllowedDepositor(addr));
+        Ok(())
+    }
+
+    /// Sets the most the vault may pay out of the primary token per WITHDRAWAL_WINDOW (one day).
+    /// Only the owner can call this. The limit covers withdraw, withdraw_batch, emergency_withdraw, sweep, and
+    /// beneficiary_withdraw combined, so a stolen owner key can only drain the vault slowly. The new limit
+    /// applies to the current window immediately, counting what's already been withdrawn in it.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * limit - The per-window limit in tokens; 0 removes the limit. Must not be negative.
+    pub fn set_withdrawal_limit(env: Env, limit: i128) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        if limit < 0 {
+            return Err(VaultError::InvalidLimit);
+        }
+        env.storage().instance().set(&DataKey::WithdrawalLimit, &limit);
+        Ok(())
+    }
+
+    /// Sets the beneficiary who can withdraw if the owner stops using the vault (a dead-man's switch).
+    /// Only the owner can call this, which also counts as owner activity. A vault with signers has no beneficiary,
+    /// since one would let the owner alone name someone to withdraw without any approval.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * beneficiary - The address allowed to call beneficiary_withdraw once the owner has been inactive.
+    pub fn set_beneficiary(env: Env, beneficiary: Address) -> Result<(), VaultError> {
+        require_owner(&env)?;
+        ensure_no_signers(&env)?;
+
+        env.storage().instance().set(&DataKey::Beneficiary, &beneficiary);
+        Ok(())
+    }
+
+    /// Withdraws tokens on the owner's behalf once the owner has gone inactive.
+    /// Only the beneficiary can call this, and only after the unlock timestamp AND after the inactivity window
+    /// has passed since the owner's last authorized call. The owner's unlock, vesting, and fee rules still apply.
+    /// Like every other owner-side exit, this is closed in a vault with signers.
+    /// Emits a `bene_wd` event with topics `(bene_wd, to)` and data `(amount, remaining_locked_amount)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send the withdrawn tokens to.
+    /// * amount - The amount of tokens to withdraw. Must be a positive value.
+    pub fn beneficiary_withdraw(env: Env, to: Address, amount: i128) -> Result<(), VaultError> {
+        let beneficiary: Address = env.storage().instance().get(&DataKey::Beneficiary).ok_or(VaultError::NoBeneficiary)?;
+        beneficiary.require_auth();
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        // The owner must have been silent for the whole inactivity window.
+        let inactivity_window: u64 = env.storage().instance().get(&DataKey::InactivityWindow).unwrap_or(0);
+        check_owner_inactive(&env, inactivity_window)?;
+        let locked_amount = withdraw_locked_for_owner(&env, &to, amount)?;
+
+        env.events().publish((symbol_short!("bene_wd"), to), (amount, locked_amount));
+        Ok(())
+    }
+
+    /// Withdraws tokens on the owner's behalf if the owner key has been lost.
+    /// Only the recovery address set at initialize can call this, and only once the owner has made no authorized
+    /// call for the recovery delay. As with beneficiary_withdraw, the owner's unlock, vesting, and fee rules still apply,
+    /// so recovery can rescue stuck funds but never open the vault any earlier than the owner could. In a vault with
+    /// signers, which can still withdraw by proposal if the owner key is lost, this is closed.
+    /// Emits a `recov_wd` event with topics `(recov_wd, to)` and data `(amount, remaining_locked_amount)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send the withdrawn tokens to.
+    /// * amount - The amount of tokens to withdraw. Must be a positive value.
+    pub fn recovery_withdraw(env: Env, to: Address, amount: i128) -> Result<(), VaultError> {
+        let recovery_address: Address = env.storage().instance().get(&DataKeyExt::RecoveryAddress).ok_or(VaultError::NoRecoveryAddress)?;
+        recovery_address.require_auth();
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        let recovery_delay: u64 = env.storage().instance().get(&DataKeyExt::RecoveryDelay).unwrap_or(0);
+        check_owner_inactive(&env, recovery_delay)?;
+        let locked_amount = withdraw_locked_for_owner(&env, &to, amount)?;
+
+        env.events().publish((symbol_short!("recov_wd"), to), (amount, locked_amount));
+        Ok(())
+    }
+
+    /// Withdraws tokens from the vault before the unlock timestamp in exchange for a penalty.
+    /// Only the vault owner can call this function, and only while the vault is still locked.
+    /// The penalty (amount * penalty_bps / 10000) goes to the penalty recipient and the rest goes to `to`;
+    /// the full amount is deducted from the locked total.
+    /// Emits an `emergency` event with topics `(emergency, to)` and data `(amount, penalty, remaining_locked_amount)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address to send the withdrawn tokens (minus the penalty) to.
+    /// * amount - The gross amount of tokens to take out of the vault. Must be a positive value.
+    pub fn emergency_withdraw(env: Env, to: Address, amount: i128) -> Result<(), VaultError> {
+        require_owner(&env)?;
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        // Once the vault is unlocked the normal, unpenalized withdraw applies.
+        let unlock_timestamp: u64 = effective_unlock_time(&env)?;
+        if env.ledger().timestamp() >= unlock_timestamp {
+            return Err(VaultError::AlreadyUnlocked);
+        }
+
+        // A zero penalty would turn this into a free early unlock, so it disables the feature instead.
+        let penalty_bps: u32 = env.storage().instance().get(&DataKey::PenaltyBps).unwrap_or(0);
+        if penalty_bps == 0 {
+            return Err(VaultError::EmergencyWithdrawDisabled);
+        }
+
+        let mut locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        if amount > locked_amount.saturating_sub(pending_withdrawals(&env)).saturating_sub(others_balances(&env)) {
+            return Err(VaultError::InsufficientFunds);
+        }
+        record_limited_withdrawal(&env, amount)?;
+
+        // Split the withdrawal into the penalty cut and the net amount for the recipient.
+        let penalty = amount.checked_mul(penalty_bps as i128).ok_or(VaultError::Overflow)? / 10_000;
+        let net_amount = amount - penalty;
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        // The full amount leaves the vault, so it counts towards the owner's withdrawn-to-date as well.
+        // Bookkeeping is updated before the transfers (checks-effects-interactions).
+        debit_owner_share(&env, amount)?;
+        locked_amount = locked_amount.checked_sub(amount).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, locked_amount);
+        let withdrawn: i128 = env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0);
+        let withdrawn = withdrawn.checked_add(amount).ok_or(VaultError::Overflow)?;
+        env.storage().instance().set(&DataKey::Withdrawn, &withdrawn);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+        add_to_lifetime_total(&env, &DataKeyExt::TotalWithdrawn, amount)?;
+
+        let token_client = token::Client::new(&env, &token_id);
+        if net_amount > 0 {
+            token_client.transfer(&env.current_contract_address(), &to, &net_amount);
+        }
+        if penalty > 0 {
+            let penalty_recipient: Address = env.storage().instance().get(&DataKey::PenaltyRecipient).ok_or(VaultError::NotInitialized)?;
+            token_client.transfer(&env.current_contract_address(), &penalty_recipient, &penalty);
+        }
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("emergency"), to), (amount, penalty, locked_amount));
+        Ok(())
+    }
+
+    /// Queues an owner withdrawal to be paid out once the vault has the liquidity for it.
+    /// This is groundwork for vaults whose tokens may one day be out in a yield strategy and can't always come back
+    /// straight away. The vault has no way to move tokens out to a strategy yet, so for now its whole balance is always
+    /// on hand and a request can be fulfilled as soon as it is made. Only the owner can call this, under the same
+    /// unlock and vesting rules as withdraw, checked against this request plus every one still pending. Nothing leaves
+    /// the locked amount until fulfill_withdrawal pays the request.
+    /// Emits a `wd_req` event with topics `(wd_req, to)` and data `(request_id, amount)`. Returns the request id.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * to - The address the withdrawal will pay out to.
+    /// * amount - The amount of tokens to withdraw. Must be a positive value.
+    pub fn request_withdrawal(env: Env, to: Address, amount: i128) -> Result<u64, VaultError> {
+        require_owner(&env)?;
+        ensure_no_signers(&env)?;
+        ensure_not_paused(&env)?;
+        check_destination(&env, &to)?;
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        // The check already leaves out what earlier requests have reserved.
+        check_owner_withdrawal(&env, amount)?;
+        let pending = pending_withdrawals(&env).checked_add(amount).ok_or(VaultError::Overflow)?;
+
+        let request_id: u64 = env.storage().instance().get(&DataKeyExt::WithdrawalRequestSeq).unwrap_or(0);
+        let request_id = request_id.checked_add(1).ok_or(VaultError::Overflow)?;
+        env.storage().instance().set(&DataKeyExt::WithdrawalRequestSeq, &request_id);
+        env.storage().instance().set(&DataKeyExt::PendingWithdrawals, &pending);
+        let key = DataKeyExt::WithdrawalRequest(request_id);
+        env.storage().persistent().set(&key, &(to.clone(), amount));
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        extend_instance_ttl(&env);
+
+        env.events().publish((symbol_short!("wd_req"), to), (request_id, amount));
+        Ok(request_id)
+    }
+
+    /// Pays out a queued withdrawal request once the vault holds enough tokens for it.
+    /// Anyone can call this, since the request already fixes who is paid and how much. It reverts with
+    /// InsufficientFunds, leaving the request pending, while the vault's token balance (less fees accrued for the
+    /// collector) is below the requested amount; until strategies exist (see request_withdrawal) that can't happen,
+    /// and the check is only there for them. The withdrawal limit and fee apply as for withdraw.
+    /// Emits a `wd_fill` event with topics `(wd_fill, to)` and data `(amount, remaining_locked_amount, request_id)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * request_id - The id returned by request_withdrawal.
+    pub fn fulfill_withdrawal(env: Env, request_id: u64) -> Result<(), VaultError> {
+        ensure_not_paused(&env)?;
+        let key = DataKeyExt::WithdrawalRequest(request_id);
+        let (to, amount): (Address, i128) = env.storage().persistent().get(&key).ok_or(VaultError::ProposalNotFound)?;
+        check_destination(&env, &to)?;
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        let accrued_fees: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+        let liquidity = token::Client::new(&env, &token_id).balance(&env.current_contract_address()).saturating_sub(accrued_fees);
+        if liquidity < amount {
+            return Err(VaultError::InsufficientFunds);
+        }
+
+        env.storage().persistent().remove(&key);
+        let pending = pending_withdrawals(&env);
+        env.storage().instance().set(&DataKeyExt::PendingWithdrawals, &pending.checked_sub(amount).ok_or(VaultError::Overflow)?);
+        let locked_amount = withdraw_locked_for_owner(&env, &to, amount)?;
+
+        env.events().publish((symbol_short!("wd_fill"), to), (amount, locked_amount, request_id));
+        Ok(())
+    }
+
+    /// Returns the total of withdrawal requests still waiting to be fulfilled.
+    pub fn get_pending_withdrawals(env: Env) -> i128 {
+        pending_withdrawals(&env)
+    }
+
+    /// Returns the `(to, amount)` of a pending withdrawal request, or None if it was never made or has been paid.
+    pub fn get_withdrawal_request(env: Env, request_id: u64) -> Option<(Address, i128)> {
+        env.storage().persistent().get(&DataKeyExt::WithdrawalRequest(request_id))
+    }
+
+    /// Withdraws a depositor's own tokens from the vault after the unlock timestamp has passed.
+    /// Unlike withdraw, this only touches the caller's share, so no one can pull out funds they didn't deposit.
+    /// The vault's vesting applies to that share as described under withdrawable_for; taking out more than has vested
+    /// reverts with NotYetVested.
+    /// Emits a `w_own` event with topics `(w_own, depositor)` and data `(amount, remaining_balance)`, kept apart from
+    /// the owner's `withdraw` event so indexers can attribute each amount to the depositor who took it.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * depositor - The depositor withdrawing their tokens. This account must authorize the call
+    ///               and receives the withdrawn tokens.
+    /// * amount - The amount of tokens to withdraw. Must be positive and no more than the depositor's balance.
+    pub fn withdraw_own(env: Env, depositor: Address, amount: i128) -> Result<(), VaultError> {
+        depositor.require_auth();
+        ensure_not_paused(&env)?;
+        ensure_not_blocked(&env, &depositor)?;
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+
+        let (balance, mut locked_amount) = check_own_withdrawal(&env, &depositor, amount)?;
+        let balance_key = DataKey::Balance(depositor.clone());
+
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        enter_reentrancy_guard(&env)?;
+
+        // Decrement both the depositor's balance and the vault-wide total before any tokens move,
+        // banking the rewards earned on the old balance first.
+        settle_rewards(&env, &depositor, balance)?;
+        let old_balance = balance;
+        let balance = balance.checked_sub(amount).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &balance_key, balance);
+        add_to_total_balances(&env, -amount)?;
+        record_own_withdrawn(&env, &depositor, amount)?;
+        publish_balance_change(&env, &depositor, old_balance, balance, BALANCE_REASON_WITHDRAW);
+        // A depositor who has taken everything out is no longer a participant.
+        if balance == 0 {
+            delist_depositor(&env, &depositor);
+        }
+        locked_amount = locked_amount.checked_sub(amount).ok_or(VaultError::Overflow)?;
+        write_persistent_amount(&env, &DataKey::LockedAmount, locked_amount);
+        extend_instance_ttl(&env);
+        record_activity(&env);
+        add_to_lifetime_total(&env, &DataKeyExt::TotalWithdrawn, amount)?;
+
+        let token_client = token::Client::new(&env, &token_id);
+        token_client.transfer(&env.current_contract_address(), &depositor, &amount);
+        exit_reentrancy_guard(&env);
+
+        env.events().publish((symbol_short!("w_own"), depositor), (amount, balance));
+        Ok(())
+    }
+
+    /// Pauses the vault, rejecting all deposits and withdrawals until unpause is called.
+    /// This is a circuit breaker for when a bug or token compromise is discovered. Only the admin can call this.
+    pub fn pause(env: Env) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().instance().set(&DataKey::Paused, &true);
+        Ok(())
+    }
+
+    /// Lifts a pause so deposits and withdrawals work again. Only the admin can call this.
+    pub fn unpause(env: Env) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        env.storage().instance().set(&DataKey::Paused, &false);
+        Ok(())
+    }
+
+    /// Rolls part of an unlocked vault into a fresh lock without moving any tokens.
+    /// Only the owner can call this, and only once the vault has unlocked. The relocked `amount` can't be withdrawn
+    /// until `new_unlock_timestamp`, while the rest of the vault stays withdrawable. Relocking again while an earlier
+    /// relock is still running adds to it, and the combined portion unlocks at the later of the two times.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * amount - The amount of locked tokens to hold back. Must be positive and not already relocked.
+    /// * new_unlock_timestamp - The ledger close time after which the relocked tokens can be withdrawn again.
+    ///                          Must be in the future.
+    pub fn relock(env: Env, amount: i128, new_unlock_timestamp: u64) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        if amount <= 0 {
+            return Err(VaultError::NonPositiveAmount);
+        }
+        let unlock_timestamp: u64 = effective_unlock_time(&env)?;
+        let now = env.ledger().timestamp();
+        if now < unlock_timestamp {
+            return Err(VaultError::StillLocked);
+        }
+        if new_unlock_timestamp <= now {
+            return Err(VaultError::LockNotExtended);
+        }
+        check_lock_duration(&env, new_unlock_timestamp)?;
+
+        // Only tokens that aren't already relocked can be rolled into a new lock.
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        let relocked = active_relocked_amount(&env);
+        if amount > locked_amount.saturating_sub(relocked) {
+            return Err(VaultError::InsufficientFunds);
+        }
 
-// Implement the contract's public functions.
-// Functions annotated with #[contractimpl] are callable from outside the contract.
-#[contractimpl]
-impl VaultContract {
-    /// Initializes the vault contract.
-    /// This function sets up the initial state of the vault and can only be called once.
+        let relocked = relocked.checked_add(amount).ok_or(VaultError::Overflow)?;
+        let relock_unlock: u64 = env.storage().instance().get(&DataKey::RelockUnlock).unwrap_or(0);
+        env.storage().instance().set(&DataKey::RelockedAmount, &relocked);
+        env.storage().instance().set(&DataKey::RelockUnlock, &relock_unlock.max(new_unlock_timestamp));
+        extend_instance_ttl(&env);
+        Ok(())
+    }
+
+    /// Pushes the unlock timestamp further into the future.
+    /// Only the owner can call this, and the new timestamp must be strictly later than the
+    /// current one so the lock can never be shortened.
+    /// Emits an `extend` event with data `(old_unlock_timestamp, new_unlock_timestamp)`.
     ///
     /// # Arguments
-    /// * env - The Soroban environment, providing access to ledger, storage, etc.
-    /// * owner - The address of the account that will own and control this vault.
-    /// * token_id - The address of the token contract that this vault will manage.
-    /// * unlock_timestamp - The specific ledger close time (in seconds since epoch)
-    ///                        after which the owner can withdraw funds.
-    pub fn initialize(env: Env, owner: Address, token_id: Address, unlock_timestamp: u64) {
-        // Check if the contract has already been initialized.
-        // We use env.storage().instance().has(&DataKey::Owner) to check if the 'Owner' key exists.
-        if env.storage().instance().has(&DataKey::Owner) {
-            // If it has, panic! and revert the transaction.
-            // panic! is Soroban's way of signaling an unrecoverable error and reverting all changes.
-            panic!("Vault already initialized");
+    /// * env - The Soroban environment.
+    /// * new_unlock_timestamp - The new ledger close time (in seconds since epoch) after which funds unlock.
+    pub fn extend_lock(env: Env, new_unlock_timestamp: u64) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        let unlock_timestamp: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?;
+        if new_unlock_timestamp <= unlock_timestamp {
+            return Err(VaultError::LockNotExtended);
         }
+        check_lock_duration(&env, new_unlock_timestamp)?;
 
-        // Store the initial state values in instance storage.
-        // env.storage().instance().set() writes data persistently to the blockchain.
-        env.storage().instance().set(&DataKey::Owner, &owner);
-        env.storage().instance().set(&DataKey::TokenId, &token_id);
-        env.storage().instance().set(&DataKey::UnlockTimestamp, &unlock_timestamp);
-        // Initialize the locked amount to 0.
-        env.storage().instance().set(&DataKey::LockedAmount, &0i128);
+        env.storage().instance().set(&DataKey::UnlockTimestamp, &new_unlock_timestamp);
+        env.events().publish((symbol_short!("extend"),), (unlock_timestamp, new_unlock_timestamp));
+        Ok(())
     }
 
-    /// Deposits tokens into the vault.
+    /// Proposes an owner withdrawal of locked tokens for the vault's signers to approve.
+    /// A vault initialized with signers puts owner withdrawals under the control of `signer_threshold` of them, for
+    /// high-value vaults: withdraw, withdraw_batch, distribute, emergency_withdraw, request_withdrawal, sweep,
+    /// withdraw_nft, and migrate_token all revert with Unauthorized, and the signers can't be changed afterwards.
+    /// Only a signer can call this, and the proposal counts as that signer's approval, so with a threshold of 1 it
+    /// executes straight away. When it executes, the same unlock, vesting, limit, and fee rules apply as to withdraw.
+    /// Emits an `ms_prop` event with topics `(ms_prop, proposer)` and data `(proposal_id, to, amount)`, then an `ms_exec`
+    /// event with topics `(ms_exec, to)` and data `(amount, remaining_locked_amount, proposal_id)` if it executes.
+    /// Returns the id of the new proposal.
     ///
     /// # Arguments
     /// * env - The Soroban environment.
-    /// * from - The address of the account depositing tokens. This account must authorize the call.
-    /// * amount - The amount of tokens to deposit. Must be a positive value.
-    pub fn deposit(env: Env, from: Address, amount: i128) {
-        from.require_auth();
-
+    /// * proposer - The signer proposing the withdrawal. This account must authorize the call.
+    /// * to - The address the withdrawal would pay out to.
+    /// * amount - The amount of tokens to withdraw. Must be a positive value.
+    pub fn propose_withdrawal(env: Env, proposer: Address, to: Address, amount: i128) -> Result<u64, VaultError> {
+        require_signer(&env, &proposer)?;
+        check_destination(&env, &to)?;
         if amount <= 0 {
-            panic!("Deposit amount must be positive");
+            return Err(VaultError::NonPositiveAmount);
         }
 
-        // Retrieve the token contract ID from storage.
-        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).expect("Token ID not set");
-        // Create a client to interact with the token contract.
-        let token_client = token::Client::new(&env, &token_id);
+        let proposal_id: u64 = env.storage().instance().get(&DataKeyExt::ProposalSeq).unwrap_or(0);
+        let proposal_id = proposal_id.checked_add(1).ok_or(VaultError::Overflow)?;
+        env.storage().instance().set(&DataKeyExt::ProposalSeq, &proposal_id);
+        let proposal = WithdrawalProposal { to: to.clone(), amount, approvals: Vec::from_array(&env, [proposer.clone()]) };
+        env.events().publish((symbol_short!("ms_prop"), proposer), (proposal_id, to, amount));
+
+        if !execute_if_approved(&env, proposal_id, &proposal)? {
+            let key = DataKeyExt::Proposal(proposal_id);
+            env.storage().persistent().set(&key, &proposal);
+            env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+            let mut open = read_open_proposals(&env);
+            open.push_back(proposal_id);
+            write_open_proposals(&env, &open);
+        }
+        extend_instance_ttl(&env);
+        Ok(proposal_id)
+    }
+
+    /// Approves a pending withdrawal proposal, executing it once enough signers have approved.
+    /// Only a signer can call this. Approving the same proposal twice changes nothing, so one signer can never
+    /// count more than once. Emits an `ms_appr` event with topics `(ms_appr, signer)` and data `proposal_id` for a
+    /// new approval, and the `ms_exec` event described under propose_withdrawal if the proposal executes.
+    /// If the withdrawal can't go through yet, for example because the vault is still locked, the approval that would
+    /// execute it reverts and can simply be made again later. Returns whether the proposal executed.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * signer - The signer approving. This account must authorize the call.
+    /// * proposal_id - The id returned by propose_withdrawal.
+    pub fn approve_withdrawal(env: Env, signer: Address, proposal_id: u64) -> Result<bool, VaultError> {
+        require_signer(&env, &signer)?;
+        let key = DataKeyExt::Proposal(proposal_id);
+        let mut proposal: WithdrawalProposal = env.storage().persistent().get(&key).ok_or(VaultError::ProposalNotFound)?;
+        if proposal.approvals.contains(&signer) {
+            return Ok(false);
+        }
+
+        proposal.approvals.push_back(signer.clone());
+        env.events().publish((symbol_short!("ms_appr"), signer), proposal_id);
+        if execute_if_approved(&env, proposal_id, &proposal)? {
+            return Ok(true);
+        }
+        env.storage().persistent().set(&key, &proposal);
+        env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+        Ok(false)
+    }
 
-        // Transfer tokens from the from account to this contract's address.
-        // Note: The from account must have previously `approve`d this contract to spend amount.
-        token_client.transfer(&from, &env.current_contract_address(), &amount);
+    /// Returns the vault's signers and approval threshold; an empty list and 0 while multi-signer withdrawals are off.
+    pub fn get_signers(env: Env) -> (Vec<Address>, u32) {
+        (read_signers(&env), env.storage().instance().get(&DataKeyExt::SignerThreshold).unwrap_or(0))
+    }
 
-        // Update the total locked amount in the vault.
-        let mut locked_amount: i128 = env.storage().instance().get(&DataKey::LockedAmount).expect("Locked amount not set");
-        // Use checked_add to prevent integer overflow, which is a common smart contract vulnerability.
-        locked_amount = locked_amount.checked_add(amount).expect("Overflow in locked amount");
-        env.storage().instance().set(&DataKey::LockedAmount, &locked_amount);
+    /// Returns a pending withdrawal proposal, or None if the id was never issued or the proposal has executed.
+    pub fn get_proposal(env: Env, proposal_id: u64) -> Option<WithdrawalProposal> {
+        env.storage().persistent().get(&DataKeyExt::Proposal(proposal_id))
     }
 
-    /// Withdraws tokens from the vault after the unlock timestamp has passed.
-    /// Only the vault owner can call this function.
+    /// Queues an admin action so it can only be executed once `execute_after` has passed.
+    /// Only the admin can call this. `execute_after` must be at least the action delay set at initialize from now;
+    /// queueing an id again reschedules it. The id is any 32 bytes the admin and depositors agree describe the action.
+    /// Actions the vault performs itself are executed by calling them: upgrade, queued under the hash of the new wasm,
+    /// and force_unlock, queued under the sha256 of the bytes "force_unlock".
+    /// Emits an `act_queue` event with topics `(act_queue, action_id)` and data `execute_after`.
     ///
     /// # Arguments
     /// * env - The Soroban environment.
-    /// * to - The address to send the withdrawn tokens to. Typically the owner's address.
-    /// * amount - The amount of tokens to withdraw. Must be a positive value.
-    pub fn withdraw(env: Env, to: Address, amount: i128) {
-        // Retrieve the vault owner's address from storage.
-        let owner: Address = env.storage().instance().get(&DataKey::Owner).expect("Owner not set");
-        // Ensure that only the owner has authorized this transaction.
-        owner.require_auth();
+    /// * action_id - The id of the action to queue.
+    /// * execute_after - The ledger timestamp from which the action may be executed.
+    pub fn queue_action(env: Env, action_id: BytesN<32>, execute_after: u64) -> Result<(), VaultError> {
+        require_admin(&env)?;
+        let action_delay: u64 = env.storage().instance().get(&DataKeyExt::ActionDelay).unwrap_or(0);
+        if execute_after < env.ledger().timestamp().saturating_add(action_delay) {
+            return Err(VaultError::ActionNotReady);
+        }
 
-        // Validate the withdrawal amount.
-        if amount <= 0 {
-            panic!("Withdraw amount must be positive");
+        let mut queued: Map<BytesN<32>, u64> = env.storage().instance().get(&DataKeyExt::QueuedActions).unwrap_or(Map::new(&env));
+        queued.set(action_id.clone(), execute_after);
+        env.storage().instance().set(&DataKeyExt::QueuedActions, &queued);
+        extend_instance_ttl(&env);
+        env.events().publish((symbol_short!("act_queue"), action_id), execute_after);
+        Ok(())
+    }
+
+    /// Executes a queued admin action, removing it from the queue.
+    /// Only the admin can call this, and it reverts with ActionNotReady until the action's execute_after has passed.
+    /// This only marks an action the vault doesn't perform itself as done; upgrade and force_unlock consume their own
+    /// queued actions, so executing one of those here just discards it.
+    /// Emits an `act_exec` event with topics `(act_exec, action_id)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * action_id - The id the action was queued under.
+    pub fn execute_action(env: Env, action_id: BytesN<32>) -> Result<(), VaultError> {
+        require_admin(&env)?;
+        consume_action(&env, &action_id)
+    }
+
+    /// Returns when a queued admin action may execute, or None if it isn't queued.
+    pub fn get_queued_action(env: Env, action_id: BytesN<32>) -> Option<u64> {
+        let queued: Map<BytesN<32>, u64> = env.storage().instance().get(&DataKeyExt::QueuedActions).unwrap_or(Map::new(&env));
+        queued.get(action_id)
+    }
+
+    /// Records whether the vault's external unlock condition is met. Only the oracle set at initialize can call this,
+    /// and it may report the condition as often as it changes. While the condition is unmet the owner can't withdraw
+    /// locked tokens, other whitelisted tokens, or NFTs, sweep, or migrate a non-empty vault's token, even after the
+    /// unlock time; free tokens and depositors' own balances aren't affected.
+    /// Emits an `unl_cond` event with topics `(unl_cond, oracle)` and data `met`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * met - Whether the condition currently holds.
+    pub fn set_unlock_condition(env: Env, met: bool) -> Result<(), VaultError> {
+        let oracle: Address = env.storage().instance().get(&DataKeyExt::Oracle).ok_or(VaultError::Unauthorized)?;
+        oracle.require_auth();
+
+        env.storage().instance().set(&DataKeyExt::OracleUnlockFlag, &met);
+        extend_instance_ttl(&env);
+        env.events().publish((symbol_short!("unl_cond"), oracle), met);
+        Ok(())
+    }
+
+    /// Returns whether the external unlock condition is met; always true for a vault initialized without an oracle.
+    pub fn is_unlock_condition_met(env: Env) -> bool {
+        unlock_condition_met(&env)
+    }
+
+    /// Unlocks the vault early by moving its unlock timestamp to the current ledger time.
+    /// Only the owner can call this, and only in a vault initialized with allow_force_unlock; trustless vaults leave
+    /// that off so the lock can never be cut short. Any grace period, vesting, or tranche schedule still applies.
+    /// In a vault initialized with an action delay, the force unlock must first be queued with queue_action under the
+    /// sha256 of the bytes "force_unlock", giving depositors the delay to react.
+    /// Emits an `f_unlock` event with topics `(f_unlock, owner)` and data `(old_unlock_timestamp, new_unlock_timestamp)`.
+    pub fn force_unlock(env: Env) -> Result<(), VaultError> {
+        let owner = require_owner(&env)?;
+        if !env.storage().instance().get(&DataKeyExt::AllowForceUnlock).unwrap_or(false) {
+            return Err(VaultError::ForceUnlockDisabled);
+        }
+
+        let unlock_timestamp: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?;
+        let now = env.ledger().timestamp();
+        if unlock_timestamp <= now {
+            return Err(VaultError::AlreadyUnlocked);
+        }
+        let action_delay: u64 = env.storage().instance().get(&DataKeyExt::ActionDelay).unwrap_or(0);
+        if action_delay > 0 {
+            consume_action(&env, &force_unlock_action_id(&env))?;
         }
 
-        // Check if the current ledger time has passed the unlock timestamp.
-        let unlock_timestamp: u64 = env.storage().instance().get(&DataKey::UnlockTimestamp).expect("Unlock timestamp not set");
-        let current_ledger_time = env.ledger().timestamp(); // Get the current ledger close time.
+        env.storage().instance().set(&DataKey::UnlockTimestamp, &now);
+        env.events().publish((symbol_short!("f_unlock"), owner), (unlock_timestamp, now));
+        Ok(())
+    }
 
-        if current_ledger_time < unlock_timestamp {
-            panic!("Tokens are still locked");
+    /// Replaces the contract's code with a previously uploaded wasm, keeping all storage intact.
+    /// Only the admin can call this. The new code should then be told to migrate, which brings the stored version
+    /// (and any storage whose layout changed) up to date.
+    /// In a vault initialized with an action delay, the upgrade must first be queued with queue_action, using the wasm
+    /// hash as the action id, and this call executes it, so depositors get the delay to react to new code.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * new_wasm_hash - The hash of the new wasm, as returned when it was uploaded to the network.
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), VaultError> {
+        require_admin(&env)?;
+        let action_delay: u64 = env.storage().instance().get(&DataKeyExt::ActionDelay).unwrap_or(0);
+        if action_delay > 0 {
+            consume_action(&env, &new_wasm_hash)?;
         }
 
-        // Check for sufficient locked funds in the vault.
-        let mut locked_amount: i128 = env.storage().instance().get(&DataKey::LockedAmount).expect("Locked amount not set");
-        if amount > locked_amount {
-            panic!("Insufficient locked funds");
+        env.deployer().update_current_contract_wasm(new_wasm_hash);
+        Ok(())
+    }
+
+    /// Replaces linear vesting with a piecewise-linear unlock curve.
+    /// Each point `(elapsed_fraction_bps, unlocked_fraction_bps)` says how much of the vault has vested once that
+    /// share of the vesting window has passed; between points the unlocked share is interpolated, and the curve
+    /// implicitly runs from (0, 0) to (10000, 10000). Steep segments give step unlocks. The cliff still applies.
+    /// Only the owner can call this, and only before vesting_start, so the owner can never speed up a vesting
+    /// window that depositors have already seen begin.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * points - A non-empty list of points with strictly increasing elapsed fractions, non-decreasing unlocked
+    ///            fractions, and both fractions at most 10000.
+    pub fn set_unlock_curve(env: Env, points: Vec<(u32, u32)>) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        // The curve shapes the vesting window, so it needs one, and tranche schedules have their own shape.
+        let duration: u64 = env.storage().instance().get(&DataKey::VestingDuration).unwrap_or(0);
+        if duration == 0 || env.storage().instance().has(&DataKey::Schedule) {
+            return Err(VaultError::InvalidUnlockCurve);
+        }
+        let start: u64 = env.storage().instance().get(&DataKey::VestingStart).unwrap_or(0);
+        if env.ledger().timestamp() >= start {
+            return Err(VaultError::VestingStarted);
         }
+        validate_unlock_curve(&points)?;
 
-        // Retrieve the token contract ID and create a client.
-        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).expect("Token ID not set");
-        let token_client = token::Client::new(&env, &token_id);
+        env.storage().instance().set(&DataKey::UnlockCurve, &points);
+        extend_instance_ttl(&env);
+        Ok(())
+    }
+
+    /// Hands the admin role to a new address, separating it from the owner if it wasn't already.
+    /// Only the current admin (the owner, if no admin was ever set) can call this. The change takes effect
+    /// immediately, and from then on renouncing or transferring ownership no longer affects the admin.
+    /// Emits an `adm_xfer` event with topics `(adm_xfer, old_admin, new_admin)`.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * new_admin - The address that will control pause, upgrade, and the allow/block lists.
+    pub fn set_admin(env: Env, new_admin: Address) -> Result<(), VaultError> {
+        let old_admin = require_admin(&env)?;
 
-        // Transfer tokens from this contract's address to the to address.
-        token_client.transfer(&env.current_contract_address(), &to, &amount);
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+        env.events().publish((symbol_short!("adm_xfer"), old_admin, new_admin), ());
+        Ok(())
+    }
 
-        // Update the total locked amount in the vault.
-        // Use checked_sub to prevent integer underflow.
-        locked_amount = locked_amount.checked_sub(amount).expect("Underflow in locked amount");
-        env.storage().instance().set(&DataKey::LockedAmount, &locked_amount);
+    /// Brings the vault's storage up to this code's VERSION after an upgrade.
+    /// Only the admin can call this. Each layout change between the stored version and VERSION is applied in turn,
+    /// then the stored version is bumped. A vault with no stored version predates versioning and is treated as
+    /// version 0. Reverts with AlreadyMigrated if the stored version is already current, so running it twice is harmless.
+    pub fn migrate(env: Env) -> Result<(), VaultError> {
+        require_admin(&env)?;
+
+        let stored: u32 = env.storage().instance().get(&DataKey::Version).unwrap_or(0);
+        if stored >= VERSION {
+            return Err(VaultError::AlreadyMigrated);
+        }
+        if stored < 1 {
+            migrate_from_v0(&env);
+        }
+        env.storage().instance().set(&DataKey::Version, &VERSION);
+        extend_instance_ttl(&env);
+        Ok(())
+    }
+
+    /// Closes an emptied vault, deleting its state so the storage can archive instead of costing rent.
+    /// Only the owner can call this, and only once the locked amount, the free portion, the reward pool, the accrued
+    /// fees, the pending withdrawal requests, and every other whitelisted token's balance are all 0 and no NFTs are
+    /// locked.
+    /// Every configuration entry, every open proposal, and the records of every current and past depositor are
+    /// removed, so afterwards every getter that needs an initialized vault returns NotInitialized; the vault can't be
+    /// initialized again. Depositor records are deleted at most CLOSE_BATCH_SIZE per call: while some remain, close
+    /// returns false and leaves the vault open, and the owner calls it again until it returns true. Entries kept per
+    /// address or token, namely the token whitelist, depositor allowlist, and blocklist, can't be enumerated and so
+    /// are left behind: remove them first if they matter. Emits a `closed` event with topics `(closed, owner)` once the
+    /// vault is closed.
+    pub fn close(env: Env) -> Result<bool, VaultError> {
+        let owner = require_owner(&env)?;
+
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?;
+        let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+        let reward_pool: i128 = env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0);
+        let nft_count: u32 = env.storage().instance().get(&DataKeyExt::LockedNftCount).unwrap_or(0);
+        let accrued_fees: i128 = env.storage().instance().get(&DataKeyExt::AccruedFees).unwrap_or(0);
+        let held_tokens: u32 = env.storage().instance().get(&DataKeyExt::HeldTokenCount).unwrap_or(0);
+        if locked_amount != 0 || free_amount != 0 || reward_pool != 0 || nft_count != 0 || accrued_fees != 0 {
+            return Err(VaultError::VaultNotEmpty);
+        }
+        if pending_withdrawals(&env) != 0 || held_tokens != 0 {
+            return Err(VaultError::VaultNotEmpty);
+        }
+
+        // Per-depositor records can only be found through the depositor lists, including depositors who have left.
+        // Each call takes a batch off the ends of the lists, so a long history spreads over several calls.
+        let mut exited = read_exited_depositors(&env);
+        let mut depositors = read_depositors(&env);
+        let mut batch: Vec<Address> = Vec::new(&env);
+        while batch.len() < CLOSE_BATCH_SIZE {
+            match exited.pop_back().or_else(|| depositors.pop_back()) {
+                Some(depositor) => batch.push_back(depositor),
+                None => break,
+            }
+        }
+        for depositor in batch.iter() {
+            env.storage().persistent().remove(&DataKey::Balance(depositor.clone()));
+            env.storage().persistent().remove(&DataKey::DepositUnlock(depositor.clone()));
+            env.storage().persistent().remove(&DataKey::AccruedRewards(depositor.clone()));
+            env.storage().persistent().remove(&DataKey::RewardCheckpoint(depositor.clone()));
+            env.storage().persistent().remove(&DataKeyExt::LastDeposit(depositor.clone()));
+            env.storage().persistent().remove(&DataKeyExt::LastCredit(depositor.clone()));
+            env.storage().persistent().remove(&DataKeyExt::OwnWithdrawn(depositor.clone()));
+            env.storage().persistent().remove(&DataKeyExt::Snapshot(depositor));
+        }
+        if !exited.is_empty() || !depositors.is_empty() {
+            write_exited_depositors(&env, &exited);
+            write_depositors(&env, &depositors);
+            return Ok(false);
+        }
+
+        for key in [DataKey::LockedAmount, DataKey::FreeAmount, DataKey::Depositors] {
+            env.storage().persistent().remove(&key);
+        }
+        env.storage().persistent().remove(&DataKeyExt::ExitedDepositors);
+        // Executed proposals and fulfilled requests remove themselves, so only the proposals still open are left.
+        for proposal_id in read_open_proposals(&env).iter() {
+            env.storage().persistent().remove(&DataKeyExt::Proposal(proposal_id));
+        }
+        env.storage().persistent().remove(&DataKeyExt::OpenProposals);
+        for key in [
+            DataKey::Owner,
+            DataKey::TokenId,
+            DataKey::UnlockTimestamp,
+            DataKey::PendingOwner,
+            DataKey::Paused,
+            DataKey::VestingStart,
+            DataKey::VestingDuration,
+            DataKey::Withdrawn,
+            DataKey::CliffTimestamp,
+            DataKey::Schedule,
+            DataKey::PenaltyBps,
+            DataKey::PenaltyRecipient,
+            DataKey::FeeBps,
+            DataKey::FeeCollector,
+            DataKey::MaxTotalDeposit,
+            DataKey::MinDeposit,
+            DataKey::Version,
+            DataKey::Locked,
+            DataKey::Beneficiary,
+            DataKey::InactivityWindow,
+            DataKey::LastOwnerAction,
+            DataKey::LastActivity,
+            DataKey::Initialized,
+            DataKey::RelockedAmount,
+            DataKey::RelockUnlock,
+            DataKey::RewardRate,
+            DataKey::RewardPool,
+            DataKey::WithdrawalDestination,
+            DataKey::MinWithdrawal,
+            DataKey::GracePeriod,
+            DataKey::DepositSeq,
+            DataKey::WithdrawSeq,
+            DataKey::WithdrawalLimit,
+            DataKey::WithdrawnToday,
+            DataKey::WindowStart,
+            DataKey::DepositorAllowlist,
+            DataKey::Admin,
+            DataKey::UnlockCurve,
+        ] {
+            env.storage().instance().remove(&key);
+        }
+        for key in [
+            DataKeyExt::NotifyContract,
+            DataKeyExt::NotifyStrict,
+            DataKeyExt::DepositCooldown,
+            DataKeyExt::CreditReceived,
+            DataKeyExt::AllowForceUnlock,
+            DataKeyExt::RecoveryAddress,
+            DataKeyExt::RecoveryDelay,
+            DataKeyExt::LockedNftCount,
+            DataKeyExt::AutoExtendSeconds,
+            DataKeyExt::InstanceLiveUntil,
+            DataKeyExt::Signers,
+            DataKeyExt::SignerThreshold,
+            DataKeyExt::ProposalSeq,
+            DataKeyExt::ActionDelay,
+            DataKeyExt::QueuedActions,
+            DataKeyExt::AccruedFees,
+            DataKeyExt::DepositOpenFrom,
+            DataKeyExt::DepositOpenUntil,
+            DataKeyExt::MinHoldSeconds,
+            DataKeyExt::PendingWithdrawals,
+            DataKeyExt::WithdrawalRequestSeq,
+            DataKeyExt::SnapshotTime,
+            DataKeyExt::BalanceEvents,
+            DataKeyExt::Oracle,
+            DataKeyExt::OracleUnlockFlag,
+            DataKeyExt::DepositFeeBps,
+            DataKeyExt::DepositFeeRetained,
+            DataKeyExt::Label,
+            DataKeyExt::PayoutSplit,
+            DataKeyExt::TotalDeposited,
+            DataKeyExt::TotalWithdrawn,
+            DataKeyExt::TotalBalances,
+            DataKeyExt::HeldTokenCount,
+        ] {
+            env.storage().instance().remove(&key);
+        }
+        env.storage().instance().set(&DataKeyExt::Closed, &true);
+
+        env.events().publish((symbol_short!("closed"), owner), ());
+        Ok(true)
+    }
+
+    /// Permanently gives up ownership, making the vault's configuration immutable.
+    /// Only the current owner can call this. Afterwards every owner-only function, including withdraw,
+    /// reverts with OwnershipRenounced, and any pending ownership transfer is cancelled. Depositors can still
+    /// use withdraw_own, and a beneficiary, if one was set, can still withdraw once the inactivity window passes.
+    /// Admin functions revert too, unless a separate admin was set, in which case that admin keeps its role.
+    /// This cannot be undone.
+    pub fn renounce_ownership(env: Env) -> Result<(), VaultError> {
+        require_owner(&env)?;
+
+        env.storage().instance().remove(&DataKey::Owner);
+        env.storage().instance().remove(&DataKey::PendingOwner);
+        Ok(())
+    }
+
+    /// Starts a two-step ownership transfer by nominating a new owner.
+    /// Only the current owner can call this. The nominee does not gain control until they call
+    /// accept_ownership, so a typo'd or uncontrolled address can never end up owning the vault.
+    /// Calling this again replaces any previously pending nominee.
+    /// Emits an `own_nom` event with topics `(own_nom, owner, new_owner)`; accept_ownership later emits
+    /// `own_xfer` with topics `(own_xfer, old_owner, new_owner)` once the new owner is stored.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * new_owner - The address that will be allowed to accept ownership.
+    pub fn transfer_ownership(env: Env, new_owner: Address) -> Result<(), VaultError> {
+        let owner = require_owner(&env)?;
+
+        env.storage().instance().set(&DataKey::PendingOwner, &new_owner);
+        env.events().publish((symbol_short!("own_nom"), owner, new_owner), ());
+        Ok(())
+    }
+
+    /// Completes a two-step ownership transfer.
+    /// The pending owner must authorize this call; on success they become the owner
+    /// and the pending slot is cleared.
+    pub fn accept_ownership(env: Env) -> Result<(), VaultError> {
+        let pending_owner: Address = env.storage().instance().get(&DataKey::PendingOwner).ok_or(VaultError::NoPendingOwner)?;
+        // Only the nominated address can accept, which proves it is controlled by someone.
+        pending_owner.require_auth();
+
+        let old_owner = read_owner(&env)?;
+        env.storage().instance().set(&DataKey::Owner, &pending_owner);
+        env.storage().instance().set(&DataKey::LastOwnerAction, &env.ledger().timestamp());
+        env.storage().instance().remove(&DataKey::PendingOwner);
+        // Announce the change only once the new owner is stored.
+        env.events().publish((symbol_short!("own_xfer"), old_owner, pending_owner), ());
+        Ok(())
+    }
+
+    /// Returns the owner, token, unlock time, and locked amount in a single call.
+    /// All values are read in the same invocation, so they are consistent with one ledger snapshot.
+    pub fn get_state(env: Env) -> Result<VaultState, VaultError> {
+        Ok(VaultState {
+            owner: env.storage().instance().get(&DataKey::Owner),
+            token_id: env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?,
+            unlock_timestamp: env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?,
+            locked_amount: env.storage().persistent().get(&DataKey::LockedAmount).ok_or(VaultError::NotInitialized)?,
+        })
+    }
+
+    /// Returns the vault's configuration: its token, unlock, vesting, fee, penalty, cap, minimum, and timing settings.
+    /// Complements get_state, which reports the parts that change with every deposit and withdrawal.
+    pub fn get_config(env: Env) -> Result<VaultConfig, VaultError> {
+        let instance = env.storage().instance();
+        Ok(VaultConfig {
+            token_id: instance.get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?,
+            unlock_timestamp: instance.get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)?,
+            grace_period: instance.get(&DataKey::GracePeriod).unwrap_or(0),
+            vesting_start: instance.get(&DataKey::VestingStart).unwrap_or(0),
+            vesting_duration: instance.get(&DataKey::VestingDuration).unwrap_or(0),
+            cliff_timestamp: instance.get(&DataKey::CliffTimestamp).unwrap_or(0),
+            penalty_bps: instance.get(&DataKey::PenaltyBps).unwrap_or(0),
+            penalty_recipient: instance.get(&DataKey::PenaltyRecipient).ok_or(VaultError::NotInitialized)?,
+            fee_bps: instance.get(&DataKey::FeeBps).unwrap_or(0),
+            deposit_fee_bps: instance.get(&DataKeyExt::DepositFeeBps).unwrap_or(0),
+            deposit_fee_retained: instance.get(&DataKeyExt::DepositFeeRetained).unwrap_or(false),
+            fee_collector: instance.get(&DataKey::FeeCollector).ok_or(VaultError::NotInitialized)?,
+            max_total_deposit: instance.get(&DataKey::MaxTotalDeposit).unwrap_or(0),
+            min_deposit: instance.get(&DataKey::MinDeposit).unwrap_or(0),
+            min_withdrawal: instance.get(&DataKey::MinWithdrawal).unwrap_or(0),
+            deposit_cooldown: instance.get(&DataKeyExt::DepositCooldown).unwrap_or(0),
+            inactivity_window: instance.get(&DataKey::InactivityWindow).unwrap_or(0),
+            recovery_delay: instance.get(&DataKeyExt::RecoveryDelay).unwrap_or(0),
+            auto_extend_seconds: instance.get(&DataKeyExt::AutoExtendSeconds).unwrap_or(0),
+            action_delay: instance.get(&DataKeyExt::ActionDelay).unwrap_or(0),
+            deposit_open_from: instance.get(&DataKeyExt::DepositOpenFrom).unwrap_or(0),
+            deposit_open_until: instance.get(&DataKeyExt::DepositOpenUntil).unwrap_or(0),
+            min_hold_seconds: instance.get(&DataKeyExt::MinHoldSeconds).unwrap_or(0),
+            reward_rate: instance.get(&DataKey::RewardRate).unwrap_or(0),
+            withdrawal_destination: instance.get(&DataKey::WithdrawalDestination),
+            credit_received: instance.get(&DataKeyExt::CreditReceived).unwrap_or(false),
+            allow_force_unlock: instance.get(&DataKeyExt::AllowForceUnlock).unwrap_or(false),
+            balance_events: instance.get(&DataKeyExt::BalanceEvents).unwrap_or(false),
+            oracle: instance.get(&DataKeyExt::Oracle),
+        })
+    }
+
+    /// Returns how many ledgers remain before the vault's instance entry, and with it every instance setting,
+    /// could be archived: the ledger the vault last extended it to, minus the current ledger sequence.
+    /// Every state-changing call tops this back up to INSTANCE_BUMP_AMOUNT once it falls below
+    /// INSTANCE_LIFETIME_THRESHOLD, so monitoring can alert when an idle vault is running low.
+    /// Extensions made from outside the contract aren't seen, so the real figure may be higher, never lower.
+    pub fn ttl_remaining(env: Env) -> u32 {
+        let live_until: u32 = env.storage().instance().get(&DataKeyExt::InstanceLiveUntil).unwrap_or(0);
+        live_until.saturating_sub(env.ledger().sequence())
     }
 
     /// Returns the current total locked amount in the vault.
     /// This is a read-only function and doesn't require authorization.
     pub fn get_locked_amount(env: Env) -> i128 {
         // unwrap_or(0) provides a default value if the key isn't found (e.g., before initialization).
-        env.storage().instance().get(&DataKey::LockedAmount).unwrap_or(0)
+        env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0)
+    }
+
+    /// Returns how many deposit events the vault has emitted, which is also the latest deposit_seq.
+    pub fn get_deposit_seq(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::DepositSeq).unwrap_or(0)
+    }
+
+    /// Returns how many withdraw events the vault has emitted, which is also the latest withdraw_seq.
+    pub fn get_withdraw_seq(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::WithdrawSeq).unwrap_or(0)
+    }
+
+    /// Returns the free portion of partial deposits, which the owner can withdraw at any time.
+    pub fn get_free_amount(env: Env) -> i128 {
+        env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0)
+    }
+
+    /// Returns whether `who` could withdraw `amount` of the primary token right now, leaving authorization aside.
+    /// Every role `who` holds is considered: as the owner through withdraw (the vault's unlock, vesting, relocks,
+    /// the minimum withdrawal, and the withdrawal limit), as the beneficiary through beneficiary_withdraw (the same,
+    /// plus the owner's inactivity window), as the recovery address through recovery_withdraw (the same, with the
+    /// recovery delay instead), and as a depositor through withdraw_own (the vault's and their own unlock, and their
+    /// balance). Nothing is recorded or transferred.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * who - The address that would be withdrawing.
+    /// * amount - The amount it would withdraw.
+    pub fn can_withdraw(env: Env, who: Address, amount: i128) -> bool {
+        if amount <= 0 || ensure_not_paused(&env).is_err() {
+            return false;
+        }
+
+        if read_owner(&env).ok() == Some(who.clone()) && read_signers(&env).is_empty() {
+            let free_amount: i128 = env.storage().persistent().get(&DataKey::FreeAmount).unwrap_or(0);
+            let from_locked = amount - amount.min(free_amount);
+            if check_min_withdrawal(&env, amount, free_amount).is_ok()
+                && (from_locked == 0 || check_owner_withdrawal(&env, from_locked).is_ok())
+                && within_withdrawal_limit(&env, amount)
+            {
+                return true;
+            }
+        }
+
+        let beneficiary: Option<Address> = env.storage().instance().get(&DataKey::Beneficiary);
+        let inactivity_window: u64 = env.storage().instance().get(&DataKey::InactivityWindow).unwrap_or(0);
+        if beneficiary == Some(who.clone())
+            && read_signers(&env).is_empty()
+            && check_owner_inactive(&env, inactivity_window).is_ok()
+            && check_owner_withdrawal(&env, amount).is_ok()
+            && within_withdrawal_limit(&env, amount)
+        {
+            return true;
+        }
+
+        let recovery_address: Option<Address> = env.storage().instance().get(&DataKeyExt::RecoveryAddress);
+        let recovery_delay: u64 = env.storage().instance().get(&DataKeyExt::RecoveryDelay).unwrap_or(0);
+        if recovery_address == Some(who.clone())
+            && read_signers(&env).is_empty()
+            && check_owner_inactive(&env, recovery_delay).is_ok()
+            && check_owner_withdrawal(&env, amount).is_ok()
+            && within_withdrawal_limit(&env, amount)
+        {
+            return true;
+        }
+
+        ensure_not_blocked(&env, &who).is_ok() && check_own_withdrawal(&env, &who, amount).is_ok()
+    }
+
+    /// Returns how much the owner could withdraw right now.
+    /// This is the free portion from partial deposits plus, once unlocked, the locked amount less anything relocked,
+    /// capped by what has vested but not yet been withdrawn when vesting or a tranche schedule is configured, and less
+    /// what pending withdrawal requests have reserved and other depositors' balances.
+    /// withdraw accepts exactly the amounts up to this value.
+    pub fn withdrawable_now(env: Env) -> Result<i128, VaultError> {
+        withdrawable_amount(&env)
+    }
+
+    /// Returns how much a depositor could take out with withdraw_own right now.
+    /// This is 0 until the vault's unlock (plus any grace period), the depositor's own unlock from
+    /// deposit_with_unlock, and the minimum hold after their latest deposit have all passed, and 0 for a blocked depositor. After that it is the depositor's balance,
+    /// capped by what the vault still has locked, since the free portion of the owner's partial deposits comes out through withdraw, and by what has vested:
+    /// the vault's vesting, unlock curve, or tranche schedule releases the same fraction of everything the depositor put in, less what they've taken out.
+    /// Pausing doesn't count here, see is_paused.
+    ///
+    /// # Arguments
+    /// * env - The Soroban environment.
+    /// * depositor - The depositor to check.
+    pub fn withdrawable_for(env: Env, depositor: Address) -> i128 {
+        if ensure_not_blocked(&env, &depositor).is_err() {
+            return 0;
+        }
+        match own_unlock_time(&env, &depositor) {
+            Ok(unlock_timestamp) if env.ledger().timestamp() >= unlock_timestamp => {}
+            _ => return 0,
+        }
+
+        let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0);
+        let vested = own_vested_available(&env, &depositor, balance).unwrap_or(0);
+        balance.min(locked_amount).min(vested)
+    }
+
+    /// Returns the unlock curve set with set_unlock_curve, or an empty list if vesting is linear.
+    pub fn get_unlock_curve(env: Env) -> Vec<(u32, u32)> {
+        env.storage().instance().get(&DataKey::UnlockCurve).unwrap_or(Vec::new(&env))
+    }
+
+    /// Returns every tranche of a vault set up with initialize_with_schedule as (unlock_timestamp, amount, claimable),
+    /// in schedule order, or an empty list for any other vault. A tranche is claimable once the ledger time has reached
+    /// its timestamp; it stays claimable after it has been withdrawn, which vested_amount and the withdrawn total tell apart.
+    pub fn get_tranches(env: Env) -> Vec<(u64, i128, bool)> {
+        let now = env.ledger().timestamp();
+        let mut tranches = Vec::new(&env);
+        if let Some(schedule) = env.storage().instance().get::<_, Vec<(u64, i128)>>(&DataKey::Schedule) {
+            for (unlock_timestamp, amount) in schedule.iter() {
+                tranches.push_back((unlock_timestamp, amount, unlock_timestamp <= now));
+            }
+        }
+        tranches
+    }
+
+    /// Returns how much of the vault has vested at the current ledger time, including anything already withdrawn.
+    /// Vesting is linear from vesting_start over vesting_duration, or follows the unlock curve if one is set;
+    /// without vesting this is the full amount.
+    /// Before the cliff timestamp this is always 0.
+    pub fn vested_amount(env: Env) -> i128 {
+        compute_vested(&env)
+    }
+
+    /// Returns the maximum total amount the vault will accept, or 0 if deposits are unlimited.
+    pub fn get_deposit_cap(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MaxTotalDeposit).unwrap_or(0)
+    }
+
+    /// Returns the total of every primary-token deposit the vault has ever taken.
+    pub fn get_total_deposited(env: Env) -> i128 {
+        env.storage().instance().get(&DataKeyExt::TotalDeposited).unwrap_or(0)
+    }
+
+    /// Returns the total of every primary-token withdrawal the vault has ever paid out, by any path,
+    /// counting the full amount before fees or penalties.
+    pub fn get_total_withdrawn(env: Env) -> i128 {
+        env.storage().instance().get(&DataKeyExt::TotalWithdrawn).unwrap_or(0)
+    }
+
+    /// Returns the seconds each depositor must wait between deposits, or 0 if there is no cooldown.
+    pub fn get_deposit_cooldown(env: Env) -> u64 {
+        env.storage().instance().get(&DataKeyExt::DepositCooldown).unwrap_or(0)
+    }
+
+    /// Returns the annual reward rate a locked token earns, in basis points, from the per-second reward rate over a
+    /// 365-day year without compounding, rounded down and capped at u32::MAX. Every locked token earns at the same rate,
+    /// so this doesn't depend on how much is locked, except that a vault with nothing locked reports 0. It doesn't
+    /// account for a reward pool too small to pay out a whole year.
+    pub fn current_apr(env: Env) -> u32 {
+        let locked_amount: i128 = env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0);
+        let reward_rate: u64 = env.storage().instance().get(&DataKey::RewardRate).unwrap_or(0);
+        if locked_amount <= 0 {
+            return 0;
+        }
+        // A u64 rate over a year in basis points stays far below i128::MAX, so this can't overflow.
+        let apr = reward_rate as i128 * SECONDS_PER_YEAR as i128 * 10_000 / REWARD_RATE_SCALE;
+        apr.min(u32::MAX as i128) as u32
+    }
+
+    /// Returns the label the vault was initialized with, or an empty symbol if it has none.
+    /// The label only helps people tell their vaults apart; nothing in the vault's logic reads it.
+    pub fn get_label(env: Env) -> Symbol {
+        env.storage().instance().get(&DataKeyExt::Label).unwrap_or(symbol_short!(""))
+    }
+
+    /// Returns the deposit fee in basis points, or 0 if deposits are credited in full.
+    /// Whether the fee goes to the fee collector or stays locked in the vault is set by deposit_fee_retained at initialize.
+    pub fn get_deposit_fee_bps(env: Env) -> u32 {
+        env.storage().instance().get(&DataKeyExt::DepositFeeBps).unwrap_or(0)
+    }
+
+    /// Returns the smallest amount a single withdraw may be, or 0 if there is no floor.
+    pub fn get_min_withdrawal(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MinWithdrawal).unwrap_or(0)
+    }
+
+    /// Returns the smallest amount a single deposit may be, or 0 if there is no floor.
+    pub fn get_min_deposit(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MinDeposit).unwrap_or(0)
+    }
+
+    /// Returns the amount of tokens a given depositor has contributed to the vault.
+    /// Addresses that never deposited have a balance of 0.
+    pub fn get_balance(env: Env, depositor: Address) -> i128 {
+        env.storage().persistent().get(&DataKey::Balance(depositor)).unwrap_or(0)
+    }
+
+    /// Returns whether the vault is currently paused.
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
     }
 
     /// Returns the unlock timestamp for the vault.
-    pub fn get_unlock_time(env: Env) -> u64 {
-        env.storage().instance().get(&DataKey::UnlockTimestamp).expect("Unlock timestamp not set")
+    pub fn get_unlock_time(env: Env) -> Result<u64, VaultError> {
+        env.storage().instance().get(&DataKey::UnlockTimestamp).ok_or(VaultError::NotInitialized)
+    }
+
+    /// Returns the time withdrawals actually open: the unlock timestamp plus the grace period, if any.
+    pub fn effective_unlock_time(env: Env) -> Result<u64, VaultError> {
+        effective_unlock_time(&env)
+    }
+
+    /// Returns whether the current ledger time has reached the effective unlock time (including any grace period).
+    /// Lets UIs show a locked/unlocked badge without comparing timestamps themselves.
+    pub fn is_unlocked(env: Env) -> Result<bool, VaultError> {
+        let unlock_timestamp: u64 = effective_unlock_time(&env)?;
+        Ok(env.ledger().timestamp() >= unlock_timestamp)
+    }
+
+    /// Returns the number of seconds left until the vault unlocks, or 0 if it is already unlocked.
+    pub fn time_until_unlock(env: Env) -> Result<u64, VaultError> {
+        let unlock_timestamp: u64 = effective_unlock_time(&env)?;
+        // saturating_sub clamps at 0 once the unlock time has passed instead of underflowing.
+        Ok(unlock_timestamp.saturating_sub(env.ledger().timestamp()))
+    }
+
+    /// Returns the VERSION of the code the vault is currently running, so clients know which behaviour to expect.
+    pub fn get_version(_env: Env) -> u32 {
+        VERSION
+    }
+
+    /// Returns the version the vault's storage was last initialized or migrated by.
+    /// This lags get_version between an upgrade and the matching migrate.
+    pub fn get_stored_version(env: Env) -> Result<u32, VaultError> {
+        env.storage().instance().get(&DataKey::Version).ok_or(VaultError::NotInitialized)
+    }
+
+    /// Returns how many primary tokens the vault's address actually holds, according to the token contract.
+    /// This can exceed get_locked_amount if tokens were sent to the vault directly; see reconcile.
+    pub fn actual_token_balance(env: Env) -> Result<i128, VaultError> {
+        let token_id: Address = env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)?;
+        Ok(token::Client::new(&env, &token_id).balance(&env.current_contract_address()))
+    }
+
+    /// Returns the rewards a depositor could claim right now.
+    pub fn get_pending_rewards(env: Env, depositor: Address) -> Result<i128, VaultError> {
+        let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+        pending_rewards(&env, &depositor, balance)
+    }
+
+    /// Returns how many tokens are left in the reward pool.
+    pub fn get_reward_pool(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::RewardPool).unwrap_or(0)
+    }
+
+    /// Returns the portion of the locked amount still held back by relock and when it unlocks, or (0, 0) if none.
+    pub fn get_relock(env: Env) -> (i128, u64) {
+        let relocked = active_relocked_amount(&env);
+        if relocked == 0 {
+            return (0, 0);
+        }
+        (relocked, env.storage().instance().get(&DataKey::RelockUnlock).unwrap_or(0))
+    }
+
+    /// Returns whether the vault has been initialized.
+    pub fn is_initialized(env: Env) -> bool {
+        is_initialized(&env)
+    }
+
+    /// Returns the owner's address of the vault, or OwnershipRenounced if the owner gave up control.
+    pub fn get_owner(env: Env) -> Result<Address, VaultError> {
+        read_owner(&env)
+    }
+
+    /// Returns the vault's admin: the separate admin if one was set, otherwise the owner.
+    pub fn get_admin(env: Env) -> Result<Address, VaultError> {
+        read_admin(&env)
+    }
+
+    /// Returns the (recipient, bps) shares distribute pays out, or an empty list if no split was configured.
+    pub fn get_payout_split(env: Env) -> Vec<(Address, u32)> {
+        env.storage().instance().get(&DataKeyExt::PayoutSplit).unwrap_or(Vec::new(&env))
+    }
+
+    /// Returns the contract notified of each withdrawal, if one was set at initialize.
+    pub fn get_notify_contract(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKeyExt::NotifyContract)
+    }
+
+    /// Returns the address nominated to become the next owner, if a transfer is pending.
+    pub fn get_pending_owner(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::PendingOwner)
+    }
+
+    /// Returns the ledger timestamp of the vault's most recent initialize, deposit, or withdrawal (0 if never initialized).
+    pub fn get_last_activity(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::LastActivity).unwrap_or(0)
+    }
+
+    /// Returns whether the vault currently accepts deposits of a token.
+    /// The primary token is always accepted; any other token only while it is on the owner's whitelist.
+    pub fn is_token_allowed(env: Env, token_id: Address) -> bool {
+        let primary_token: Option<Address> = env.storage().instance().get(&DataKey::TokenId);
+        primary_token == Some(token_id.clone()) || env.storage().instance().has(&DataKey::AllowedToken(token_id))
+    }
+
+    /// Returns whether the owner has blocked `addr`.
+    pub fn is_blocked(env: Env, addr: Address) -> bool {
+        env.storage().persistent().has(&DataKey::Blocked(addr))
     }
 
-    /// Returns the owner's address of the vault.
-    pub fn get_owner(env: Env) -> Address {
-        env.storage().instance().get(&DataKey::Owner).expect("Owner not set")
+    /// Returns whether only allowlisted addresses may currently deposit.
+    pub fn is_depositor_allowlist_enabled(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::DepositorAllowlist).unwrap_or(false)
+    }
+
+    /// Returns whether `addr` is on the depositor allowlist, whether or not the allowlist is currently on.
+    pub fn is_depositor_allowed(env: Env, addr: Address) -> bool {
+        env.storage().persistent().has(&DataKey::AllowedDepositor(addr))
+    }
+
+    /// Returns how much of a token the vault is holding.
+    /// For the primary token this is the locked amount; for any other token it is that token's own total.
+    pub fn get_token_balance(env: Env, token_id: Address) -> i128 {
+        let primary_token: Option<Address> = env.storage().instance().get(&DataKey::TokenId);
+        if primary_token == Some(token_id.clone()) {
+            return env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0);
+        }
+        env.storage().persistent().get(&DataKey::TokenBalance(token_id)).unwrap_or(0)
+    }
+
+    /// Returns every address that currently holds a non-zero balance in the vault, in order of first deposit.
+    pub fn get_depositors(env: Env) -> Vec<Address> {
+        read_depositors(&env)
+    }
+
+    /// Freezes every depositor's balance as it stands once the vault has unlocked, for pro-rata distributions.
+    /// Anyone can call this, but only once and only after the unlock timestamp (plus any grace period), so the
+    /// snapshot reflects the balances the vault unlocked with rather than whatever is left after withdrawals begin.
+    /// Read it back with get_snapshot. Emits a `snapshot` event with data `(depositor_count, timestamp)`.
+    pub fn snapshot(env: Env) -> Result<(), VaultError> {
+        if env.storage().instance().has(&DataKeyExt::SnapshotTime) {
+            return Err(VaultError::SnapshotTaken);
+        }
+        let now = env.ledger().timestamp();
+        if now < effective_unlock_time(&env)? {
+            return Err(VaultError::StillLocked);
+        }
+
+        let depositors = read_depositors(&env);
+        for depositor in depositors.iter() {
+            let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+            if balance > 0 {
+                let key = DataKeyExt::Snapshot(depositor);
+                env.storage().persistent().set(&key, &balance);
+                env.storage().persistent().extend_ttl(&key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+            }
+        }
+        env.storage().instance().set(&DataKeyExt::SnapshotTime, &now);
+        extend_instance_ttl(&env);
+
+        env.events().publish((symbol_short!("snapshot"),), (depositors.len(), now));
+        Ok(())
+    }
+
+    /// Returns the balance `depositor` held when the snapshot was taken, or None if no snapshot has been taken yet.
+    /// Addresses that held nothing at the time read as 0.
+    pub fn get_snapshot(env: Env, depositor: Address) -> Option<i128> {
+        if !env.storage().instance().has(&DataKeyExt::SnapshotTime) {
+            return None;
+        }
+        Some(env.storage().persistent().get(&DataKeyExt::Snapshot(depositor)).unwrap_or(0))
+    }
+
+    /// Returns every current depositor's balance in one call, keyed by address.
+    /// Depositors who have withdrawn everything are left out.
+    pub fn get_all_balances(env: Env) -> Map<Address, i128> {
+        let mut balances = Map::new(&env);
+        for depositor in read_depositors(&env).iter() {
+            let balance: i128 = env.storage().persistent().get(&DataKey::Balance(depositor.clone())).unwrap_or(0);
+            if balance > 0 {
+                balances.set(depositor, balance);
+            }
+        }
+        balances
+    }
+
+    /// Returns the personal unlock time a depositor set with deposit_with_unlock, if any.
+    pub fn get_deposit_unlock(env: Env, depositor: Address) -> Option<u64> {
+        env.storage().persistent().get(&DataKey::DepositUnlock(depositor))
+    }
+
+    /// Returns the per-window withdrawal limit (0 if unlimited).
+    pub fn get_withdrawal_limit(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::WithdrawalLimit).unwrap_or(0)
+    }
+
+    /// Returns the only address owner-side withdrawals may pay out to, if one was fixed at initialize.
+    pub fn get_withdrawal_destination(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::WithdrawalDestination)
+    }
+
+    /// Returns the beneficiary who can withdraw if the owner goes inactive, if one is set.
+    pub fn get_beneficiary(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Beneficiary)
+    }
+
+    /// Returns the recovery address that can withdraw if the owner key is lost, if one was set at initialize.
+    pub fn get_recovery_address(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKeyExt::RecoveryAddress)
     }
 
     /// Returns the token ID managed by the vault.
-    pub fn get_token_id(env: Env) -> Address {
-        env.storage().instance().get(&DataKey::TokenId).expect("Token ID not set")
+    pub fn get_token_id(env: Env) -> Result<Address, VaultError> {
+        env.storage().instance().get(&DataKey::TokenId).ok_or(VaultError::NotInitialized)
     }
-}
\ No newline at end of file
+}
+
+mod test;
\ No newline at end of file
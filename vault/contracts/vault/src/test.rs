@@ -1,47 +1,4144 @@
 #![cfg(test)]
+extern crate std;
 
 use super::*;
-use soroban_sdk::{testutils::{Address as _, Env as _}, Address, Env};
+use soroban_sdk::{contract, contractimpl, TryFromVal, testutils::{storage::{Instance as _, Persistent as _}, Address as _, AuthorizedFunction, AuthorizedInvocation, Events as _, Ledger as _, MockAuth, MockAuthInvoke}, token::{StellarAssetClient, TokenClient}, map, vec, Address, Env, IntoVal, Val, Vec};
+
+// A minimal "v2" vault that reads the same storage and adds a function, used to exercise upgrade.
+// Its source lives in contracts/vault_v2_fixture.
+mod vault_v2 {
+    soroban_sdk::contractimport!(file = "testdata/vault_v2_fixture.wasm");
+}
+
+// A malicious token that tries to re-enter the vault's withdraw from inside its own transfer.
+// It records whether the re-entrant call failed so tests can inspect it afterwards.
+#[contracttype]
+enum ReentrantTokenKey {
+    Vault,
+    ReentryFailed,
+    Balance(Address),
+}
+
+#[contract]
+pub struct ReentrantToken;
+
+#[contractimpl]
+impl ReentrantToken {
+    pub fn arm(env: Env, vault: Address) {
+        env.storage().instance().set(&ReentrantTokenKey::Vault, &vault);
+    }
+
+    // Balances are only tracked so the vault sees its deposits arrive; anyone may overdraw.
+    pub fn balance(env: Env, id: Address) -> i128 {
+        env.storage().instance().get(&ReentrantTokenKey::Balance(id)).unwrap_or(0)
+    }
+
+    pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+        let from_balance = Self::balance(env.clone(), from.clone());
+        env.storage().instance().set(&ReentrantTokenKey::Balance(from), &(from_balance - amount));
+        let to_balance = Self::balance(env.clone(), to.clone());
+        env.storage().instance().set(&ReentrantTokenKey::Balance(to.clone()), &(to_balance + amount));
+        if let Some(vault) = env.storage().instance().get::<_, Address>(&ReentrantTokenKey::Vault) {
+            let result = VaultContractClient::new(&env, &vault).try_withdraw(&env.current_contract_address(), &to, &amount);
+            env.storage().instance().set(&ReentrantTokenKey::ReentryFailed, &result.is_err());
+        }
+    }
+
+    pub fn reentry_failed(env: Env) -> Option<bool> {
+        env.storage().instance().get(&ReentrantTokenKey::ReentryFailed)
+    }
+}
+
+// A minimal token that burns 1% of every transfer, so the recipient gets less than was sent.
+// It lives in its own module because its function names clash with ReentrantToken's.
+mod fee_token {
+    use soroban_sdk::{contract, contractimpl, contracttype, Address, Env};
+
+    #[contracttype]
+    enum FeeTokenKey {
+        Balance(Address),
+    }
+
+    #[contract]
+    pub struct FeeOnTransferToken;
+
+    #[contractimpl]
+    impl FeeOnTransferToken {
+        pub fn mint(env: Env, to: Address, amount: i128) {
+            let balance = Self::balance(env.clone(), to.clone());
+            env.storage().persistent().set(&FeeTokenKey::Balance(to), &(balance + amount));
+        }
+
+        pub fn balance(env: Env, id: Address) -> i128 {
+            env.storage().persistent().get(&FeeTokenKey::Balance(id)).unwrap_or(0)
+        }
+
+        pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+            from.require_auth();
+            let from_balance = Self::balance(env.clone(), from.clone());
+            env.storage().persistent().set(&FeeTokenKey::Balance(from), &(from_balance - amount));
+            Self::mint(env, to, amount - amount / 100);
+        }
+    }
+}
+use fee_token::{FeeOnTransferToken, FeeOnTransferTokenClient};
+
+// A minimal NFT contract: each token id has one owner, who alone can transfer it.
+// Like fee_token, it gets a module of its own because its transfer clashes with the token mocks.
+mod nft {
+    use soroban_sdk::{contract, contractimpl, contracttype, Address, Env};
+
+    #[contracttype]
+    enum NftKey {
+        Owner(u128),
+    }
+
+    #[contract]
+    pub struct MockNft;
+
+    #[contractimpl]
+    impl MockNft {
+        pub fn mint(env: Env, to: Address, token_id: u128) {
+            env.storage().persistent().set(&NftKey::Owner(token_id), &to);
+        }
+
+        pub fn owner_of(env: Env, token_id: u128) -> Address {
+            env.storage().persistent().get(&NftKey::Owner(token_id)).unwrap()
+        }
+
+        pub fn transfer(env: Env, from: Address, to: Address, token_id: u128) {
+            from.require_auth();
+            assert_eq!(Self::owner_of(env.clone(), token_id), from, "not the owner");
+            env.storage().persistent().set(&NftKey::Owner(token_id), &to);
+        }
+    }
+}
+use nft::{MockNft, MockNftClient};
+
+// A stand-in for a token migration contract: it holds a stock of the new token and pays out one new token
+// for every old token it has been sent.
+mod token_swap {
+    use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env};
+
+    #[contracttype]
+    enum SwapKey {
+        NewToken,
+    }
+
+    #[contract]
+    pub struct TokenSwap;
+
+    #[contractimpl]
+    impl TokenSwap {
+        pub fn set_new_token(env: Env, new_token: Address) {
+            env.storage().instance().set(&SwapKey::NewToken, &new_token);
+        }
+
+        pub fn swap(env: Env, to: Address, amount: i128) {
+            let new_token: Address = env.storage().instance().get(&SwapKey::NewToken).unwrap();
+            token::Client::new(&env, &new_token).transfer(&env.current_contract_address(), &to, &amount);
+        }
+    }
+}
+use token_swap::{TokenSwap, TokenSwapClient};
+
+// A stand-in for an accounting contract that records the last withdrawal it was told about,
+// or fails every callback once told to.
+#[contracttype]
+enum WithdrawReceiverKey {
+    Last,
+    Fail,
+}
+
+#[contract]
+pub struct WithdrawReceiver;
+
+#[contractimpl]
+impl WithdrawReceiver {
+    pub fn set_fail(env: Env, fail: bool) {
+        env.storage().instance().set(&WithdrawReceiverKey::Fail, &fail);
+    }
+
+    pub fn on_withdraw(env: Env, vault: Address, to: Address, amount: i128) {
+        if env.storage().instance().get(&WithdrawReceiverKey::Fail).unwrap_or(false) {
+            panic!("receiver failed");
+        }
+        env.storage().instance().set(&WithdrawReceiverKey::Last, &(vault, to, amount));
+    }
+
+    pub fn last(env: Env) -> Option<(Address, Address, i128)> {
+        env.storage().instance().get(&WithdrawReceiverKey::Last)
+    }
+}
+
+// Deploys a Stellar Asset Contract to act as the vault's token and returns its address.
+fn create_token(env: &Env) -> Address {
+    let token_admin = Address::generate(env);
+    env.register_stellar_asset_contract_v2(token_admin).address()
+}
+
+// Returns options with every optional feature turned off, matching a plain time-locked vault.
+fn default_options(env: &Env) -> VaultOptions {
+    VaultOptions {
+        vesting_start: 0,
+        vesting_duration: 0,
+        cliff_timestamp: 0,
+        penalty_bps: 0,
+        penalty_recipient: Address::generate(env),
+        fee_bps: 0,
+        fee_collector: Address::generate(env),
+        max_total_deposit: 0,
+        min_deposit: 0,
+        inactivity_window: 0,
+        reward_rate: 0,
+        withdrawal_destination: None,
+        grace_period: 0,
+        min_withdrawal: 0,
+        admin: None,
+        notify_contract: None,
+        notify_strict: false,
+        deposit_cooldown: 0,
+        credit_received: false,
+        payout_split: Vec::new(env),
+        allow_force_unlock: false,
+        recovery_address: None,
+        recovery_delay: 0,
+        auto_extend_seconds: 0,
+        action_delay: 0,
+        deposit_open_from: 0,
+        deposit_open_until: 0,
+        min_hold_seconds: 0,
+        balance_events: false,
+        oracle: None,
+        deposit_fee_bps: 0,
+        deposit_fee_retained: false,
+        label: None,
+        signers: Vec::new(env),
+        signer_threshold: 0,
+    }
+}
+
+// A vault wired to a freshly deployed Stellar Asset Contract, with every auth mocked.
+// Tests that need nothing unusual can start from this instead of repeating the setup.
+struct VaultFixture {
+    env: Env,
+    contract_id: Address,
+    client: VaultContractClient<'static>,
+    owner: Address,
+    token_id: Address,
+    token: TokenClient<'static>,
+}
+
+impl VaultFixture {
+    // Sets up a plain time-locked vault that unlocks at `unlock_timestamp`.
+    fn new(unlock_timestamp: u64) -> Self {
+        Self::with_options(unlock_timestamp, default_options)
+    }
+
+    // Sets up a vault that unlocks at `unlock_timestamp` with the options built by `options`.
+    fn with_options(unlock_timestamp: u64, options: impl FnOnce(&Env) -> VaultOptions) -> Self {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register(VaultContract, ());
+        let client = VaultContractClient::new(&env, &contract_id);
+        let owner = Address::generate(&env);
+        let token_id = create_token(&env);
+        let token = TokenClient::new(&env, &token_id);
+        client.initialize(&owner, &token_id, &unlock_timestamp, &options(&env));
+        VaultFixture { env, contract_id, client, owner, token_id, token }
+    }
+
+    // Moves the ledger clock to `timestamp`, for exercising the time lock.
+    fn warp_to(&self, timestamp: u64) {
+        self.env.ledger().set_timestamp(timestamp);
+    }
+
+    // Generates a new account holding `amount` of the vault's token.
+    fn funded_user(&self, amount: i128) -> Address {
+        let user = Address::generate(&self.env);
+        StellarAssetClient::new(&self.env, &self.token_id).mint(&user, &amount);
+        user
+    }
+
+    // Mints `amount` of the vault's token to the owner, for tests where the owner funds the vault and withdraws from it.
+    // Other depositors' balances are theirs alone, so only what the owner put in is the owner's to withdraw.
+    fn funded_owner(&self, amount: i128) -> Address {
+        StellarAssetClient::new(&self.env, &self.token_id).mint(&self.owner, &amount);
+        self.owner.clone()
+    }
+}
+
+// Returns only the events published by the vault itself, skipping the token contract's transfer events.
+fn vault_events(env: &Env, contract_id: &Address) -> Vec<(Address, Vec<Val>, Val)> {
+    let mut events = Vec::new(env);
+    for event in env.events().all().iter() {
+        if &event.0 == contract_id {
+            events.push_back(event);
+        }
+    }
+    events
+}
 
 #[test]
 fn test_initialize_and_deposit() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, VaultContract);
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
     let owner = Address::generate(&env);
     let token_id = Address::generate(&env);
 
     // Call initialize
-    VaultContract::initialize(env.clone(), owner.clone(), token_id.clone(), 12345);
+    client.initialize(&owner, &token_id, &12345, &default_options(&env));
 
     // Check owner
-    assert_eq!(VaultContract::get_owner(env.clone()), owner);
+    assert_eq!(client.get_owner(), owner);
 
     // Check token id
-    assert_eq!(VaultContract::get_token_id(env.clone()), token_id);
+    assert_eq!(client.get_token_id(), token_id);
 
     // Check unlock time
-    assert_eq!(VaultContract::get_unlock_time(env.clone()), 12345);
+    assert_eq!(client.get_unlock_time(), 12345);
 
     // Locked amount should be zero
-    assert_eq!(VaultContract::get_locked_amount(env.clone()), 0);
+    assert_eq!(client.get_locked_amount(), 0);
 }
 
+// The canonical end-to-end usage example: initialize, deposit, wait for unlock, withdraw.
 #[test]
 fn test_vault() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, VaultContract);
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(client.get_locked_amount(), 0);
+
+    // Test deposit; it takes the depositor and the amount, and returns the new locked amount.
+    assert_eq!(client.deposit(&token_id, &owner, &1000), 1000);
+    assert_eq!(client.get_locked_amount(), 1000);
+    assert_eq!(token.balance(&owner), 0);
+    assert_eq!(token.balance(&contract_id), 1000);
+
+    // Test withdraw; it takes the recipient and the amount, and returns what remains locked.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.withdraw(&token_id, &owner, &500), 500);
+    assert_eq!(client.get_locked_amount(), 500);
+    assert_eq!(token.balance(&owner), 500);
+    assert_eq!(token.balance(&contract_id), 500);
+}
+
+#[test]
+fn test_initialize_twice_fails() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = Address::generate(&env);
+
+    client.initialize(&owner, &token_id, &12345, &default_options(&env));
+    assert_eq!(client.try_initialize(&owner, &token_id, &12345, &default_options(&env)), Err(Ok(VaultError::AlreadyInitialized)));
+}
+
+#[test]
+fn test_uninitialized_vault_returns_not_initialized() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
     let client = VaultContractClient::new(&env, &contract_id);
     let user = Address::generate(&env);
+    let token_id = Address::generate(&env);
 
-    // Test deposit
-    env.as_contract(&contract_id, || {
-        client.deposit(&1000);
-        assert_eq!(client.balance(), 1000);
-    });
+    assert_eq!(client.try_deposit(&token_id, &user, &100), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(client.try_withdraw(&token_id, &user, &100), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(client.try_get_owner(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(client.try_get_token_id(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(client.try_get_unlock_time(), Err(Ok(VaultError::NotInitialized)));
+}
 
-    // Test withdraw
-    env.as_contract(&contract_id, || {
-        client.withdraw(&500);
-        assert_eq!(client.balance(), 500);
-    });
+#[test]
+fn test_non_positive_amounts_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+
+    assert_eq!(client.try_deposit(&token_id, &owner, &0), Err(Ok(VaultError::NonPositiveAmount)));
+    assert_eq!(client.try_deposit(&token_id, &owner, &-5), Err(Ok(VaultError::NonPositiveAmount)));
+    assert_eq!(client.try_withdraw(&token_id, &owner, &0), Err(Ok(VaultError::NonPositiveAmount)));
+}
+
+#[test]
+fn test_withdraw_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    // Before the unlock timestamp the funds stay locked.
+    env.ledger().set_timestamp(99);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &100), Err(Ok(VaultError::StillLocked)));
+
+    // After unlock the vault still refuses to pay out more than it holds.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1001), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(client.get_locked_amount(), 1000);
+}
+
+#[test]
+fn test_deposit_emits_event() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    assert_eq!(client.deposit(&token_id, &user, &300), 300);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&env), (300i128, 300i128, 1u64).into_val(&env))]
+    );
+
+    // The data payload carries the running total, not just the latest deposit.
+    assert_eq!(client.deposit(&token_id, &user, &200), 500);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&env), (200i128, 500i128, 2u64).into_val(&env))]
+    );
+}
+
+#[test]
+fn test_withdraw_emits_event() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+    env.ledger().set_timestamp(100);
+
+    // Exactly one withdraw event with the amount sent and the remaining locked amount.
+    assert_eq!(client.withdraw(&token_id, &recipient, &400), 600);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("withdraw"), recipient.clone()).into_val(&env), (400i128, 600i128, 1u64).into_val(&env))]
+    );
+
+    // A reverted withdrawal must not leave an event behind.
+    assert_eq!(client.try_withdraw(&token_id, &recipient, &601), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(vault_events(&env, &contract_id).len(), 0);
+}
+
+#[test]
+fn test_two_step_ownership_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let new_owner = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &100, &default_options(&env));
+
+    assert_eq!(client.get_pending_owner(), None);
+    assert_eq!(client.try_accept_ownership(), Err(Ok(VaultError::NoPendingOwner)));
+
+    // Nominating does not hand over control yet.
+    client.transfer_ownership(&new_owner);
+    assert_eq!(client.get_pending_owner(), Some(new_owner.clone()));
+    assert_eq!(client.get_owner(), owner);
+
+    client.accept_ownership();
+    assert_eq!(env.auths()[0].0, new_owner);
+    assert_eq!(client.get_owner(), new_owner);
+    assert_eq!(client.get_pending_owner(), None);
+}
+
+#[test]
+fn test_accept_ownership_requires_pending_owner() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let new_owner = Address::generate(&env);
+    let intruder = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &100, &default_options(&env));
+
+    env.mock_all_auths();
+    client.transfer_ownership(&new_owner);
+
+    // Only the intruder signs, so the pending owner's auth check fails.
+    env.mock_auths(&[MockAuth {
+        address: &intruder,
+        invoke: &MockAuthInvoke { contract: &contract_id, fn_name: "accept_ownership", args: ().into_val(&env), sub_invokes: &[] },
+    }]);
+    assert!(client.try_accept_ownership().is_err());
+    assert_eq!(client.get_owner(), owner);
+    assert_eq!(client.get_pending_owner(), Some(new_owner));
+}
+
+#[test]
+fn test_transfer_ownership_overwrites_pending() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let first = Address::generate(&env);
+    let second = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &100, &default_options(&env));
+
+    client.transfer_ownership(&first);
+    client.transfer_ownership(&second);
+    assert_eq!(client.get_pending_owner(), Some(second.clone()));
+
+    // The replaced nominee can no longer accept.
+    env.mock_auths(&[MockAuth {
+        address: &first,
+        invoke: &MockAuthInvoke { contract: &contract_id, fn_name: "accept_ownership", args: ().into_val(&env), sub_invokes: &[] },
+    }]);
+    assert!(client.try_accept_ownership().is_err());
+
+    env.mock_all_auths();
+    client.accept_ownership();
+    assert_eq!(client.get_owner(), second);
+}
+
+#[test]
+fn test_per_depositor_balances() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&alice, &1000);
+    token_admin.mint(&bob, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    client.deposit(&token_id, &alice, &300);
+    client.deposit(&token_id, &bob, &450);
+    client.deposit(&token_id, &alice, &200);
+
+    assert_eq!(client.get_balance(&alice), 500);
+    assert_eq!(client.get_balance(&bob), 450);
+    assert_eq!(client.get_balance(&owner), 0);
+    // The aggregate still equals the sum of everyone's contributions.
+    assert_eq!(client.get_locked_amount(), client.get_balance(&alice) + client.get_balance(&bob));
+}
+
+#[test]
+fn test_withdraw_own_leaves_other_depositors_untouched() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&alice, &1000);
+    token_admin.mint(&bob, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &alice, &600);
+    client.deposit(&token_id, &bob, &400);
+
+    // Depositors are bound by the same time lock.
+    assert_eq!(client.try_withdraw_own(&alice, &100), Err(Ok(VaultError::StillLocked)));
+
+    env.ledger().set_timestamp(100);
+    client.withdraw_own(&alice, &250);
+
+    assert_eq!(client.get_balance(&alice), 350);
+    assert_eq!(client.get_balance(&bob), 400);
+    assert_eq!(client.get_locked_amount(), 750);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&alice), 650);
+}
+
+#[test]
+fn test_withdraw_own_cannot_exceed_balance() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&alice, &1000);
+    token_admin.mint(&bob, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &alice, &100);
+    client.deposit(&token_id, &bob, &900);
+
+    // Alice can't dip into Bob's share even though the vault holds enough in total.
+    assert_eq!(client.try_withdraw_own(&alice, &101), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(client.get_balance(&alice), 100);
+    assert_eq!(client.get_locked_amount(), 1000);
+}
+
+#[test]
+fn test_owner_withdrawals_leave_depositor_balances() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { penalty_bps: 100, ..default_options(env) });
+    let alice = f.funded_user(600);
+    let owner = f.funded_owner(400);
+    f.client.deposit(&f.token_id, &alice, &600);
+    f.client.deposit(&f.token_id, &owner, &400);
+
+    // Not even an early exit reaches beyond the owner's own share.
+    assert_eq!(f.client.try_emergency_withdraw(&owner, &401), Err(Ok(VaultError::InsufficientFunds)));
+
+    f.warp_to(100);
+    assert_eq!(f.client.withdrawable_now(), 400);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &owner, &401), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(f.client.try_withdraw_batch(&vec![&f.env, (owner.clone(), 200i128), (owner.clone(), 201i128)]), Err(Ok(VaultError::InsufficientFunds)));
+
+    // What the owner withdraws comes off their own balance, so it can't be taken out a second time with withdraw_own.
+    f.client.withdraw(&f.token_id, &owner, &300);
+    assert_eq!(f.client.get_balance(&owner), 100);
+    assert_eq!(f.client.get_balance(&alice), 600);
+    assert_eq!(f.client.try_withdraw_own(&owner, &101), Err(Ok(VaultError::InsufficientFunds)));
+    f.client.withdraw_own(&owner, &100);
+    f.client.withdraw_own(&alice, &600);
+    assert_eq!(f.token.balance(&alice), 600);
+    assert_eq!(f.client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_pause_blocks_deposits_and_withdrawals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &100);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &user, &500);
+
+    assert!(!client.is_paused());
+    client.pause();
+    assert!(client.is_paused());
+
+    assert_eq!(client.try_deposit(&token_id, &user, &100), Err(Ok(VaultError::Paused)));
+    assert_eq!(client.try_withdraw(&token_id, &owner, &100), Err(Ok(VaultError::Paused)));
+    assert_eq!(client.try_withdraw_own(&user, &100), Err(Ok(VaultError::Paused)));
+    assert_eq!(client.get_locked_amount(), 500);
+
+    // Everything works again once the owner lifts the pause.
+    client.unpause();
+    assert!(!client.is_paused());
+    client.deposit(&token_id, &user, &100);
+    client.deposit(&token_id, &owner, &100);
+    client.withdraw(&token_id, &owner, &100);
+    client.withdraw_own(&user, &100);
+    assert_eq!(client.get_locked_amount(), 500);
+}
+
+#[test]
+fn test_pause_requires_owner() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &0, &default_options(&env));
+
+    // Without the owner's signature the pause is rejected.
+    assert!(client.try_pause().is_err());
+    assert!(!client.is_paused());
+}
+
+#[test]
+fn test_extend_lock() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &100, &default_options(&env));
+
+    client.extend_lock(&500);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("extend"),).into_val(&env), (100u64, 500u64).into_val(&env))]
+    );
+    assert_eq!(client.get_unlock_time(), 500);
+}
+
+#[test]
+fn test_extend_lock_cannot_shorten() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    client.initialize(&owner, &Address::generate(&env), &100, &default_options(&env));
+
+    // Both an earlier and an unchanged timestamp are rejected.
+    assert_eq!(client.try_extend_lock(&99), Err(Ok(VaultError::LockNotExtended)));
+    assert_eq!(client.try_extend_lock(&100), Err(Ok(VaultError::LockNotExtended)));
+    assert_eq!(client.get_unlock_time(), 100);
+}
+
+#[test]
+fn test_linear_vesting() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    // Vest 1000 tokens linearly between t=1000 and t=2000.
+    client.initialize(&owner, &token_id, &0, &VaultOptions { vesting_start: 1000, vesting_duration: 1000, ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    // 0%: nothing can be withdrawn at the start of the window.
+    env.ledger().set_timestamp(1000);
+    assert_eq!(client.vested_amount(), 0);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::NotYetVested)));
+
+    // 50%: half has vested.
+    env.ledger().set_timestamp(1500);
+    assert_eq!(client.vested_amount(), 500);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &501), Err(Ok(VaultError::NotYetVested)));
+    client.withdraw(&token_id, &owner, &500);
+    // Already-withdrawn tokens still count towards the vested total.
+    assert_eq!(client.vested_amount(), 500);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::NotYetVested)));
+
+    // 100%: the remainder unlocks at the end of the window.
+    env.ledger().set_timestamp(2000);
+    assert_eq!(client.vested_amount(), 1000);
+    client.withdraw(&token_id, &owner, &500);
+    assert_eq!(client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_vesting_cliff() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    // Vesting runs from t=1000 to t=2000 with a cliff at t=1400.
+    client.initialize(&owner, &token_id, &0, &VaultOptions { vesting_start: 1000, vesting_duration: 1000, cliff_timestamp: 1400, ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    // Before the cliff nothing is vested even though the window has started.
+    env.ledger().set_timestamp(1399);
+    assert_eq!(client.vested_amount(), 0);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::NotYetVested)));
+
+    // Exactly at the cliff the linear amount accrued since vesting_start becomes available.
+    env.ledger().set_timestamp(1400);
+    assert_eq!(client.vested_amount(), 400);
+
+    // After the cliff vesting continues linearly.
+    env.ledger().set_timestamp(1750);
+    assert_eq!(client.vested_amount(), 750);
+    client.withdraw(&token_id, &owner, &750);
+}
+
+#[test]
+fn test_cliff_at_vesting_end_unlocks_everything() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &0, &VaultOptions { vesting_start: 1000, vesting_duration: 1000, cliff_timestamp: 2000, ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    env.ledger().set_timestamp(1999);
+    assert_eq!(client.vested_amount(), 0);
+    env.ledger().set_timestamp(2000);
+    assert_eq!(client.vested_amount(), 1000);
+}
+
+#[test]
+fn test_cliff_after_vesting_end_rejected() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+
+    assert_eq!(
+        client.try_initialize(&owner, &Address::generate(&env), &0, &VaultOptions { vesting_start: 1000, vesting_duration: 1000, cliff_timestamp: 2001, ..default_options(&env) }),
+        Err(Ok(VaultError::InvalidCliff))
+    );
+}
+
+#[test]
+fn test_tranche_schedule() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize_with_schedule(&owner, &token_id, &vec![&env, (100u64, 250i128), (200u64, 250i128), (300u64, 500i128)]);
+    client.deposit(&token_id, &owner, &1000);
+    assert_eq!(client.get_unlock_time(), 100);
+
+    // Before the first tranche everything is locked.
+    env.ledger().set_timestamp(99);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::StillLocked)));
+
+    // First tranche: 250 available.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.vested_amount(), 250);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &251), Err(Ok(VaultError::NotYetVested)));
+    client.withdraw(&token_id, &owner, &250);
+
+    // Second tranche: another 250, minus nothing more since the first was fully taken.
+    env.ledger().set_timestamp(250);
+    assert_eq!(client.vested_amount(), 500);
+    client.withdraw(&token_id, &owner, &100);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &151), Err(Ok(VaultError::NotYetVested)));
+
+    // Third tranche: the rest of the vault.
+    env.ledger().set_timestamp(300);
+    assert_eq!(client.vested_amount(), 1000);
+    client.withdraw(&token_id, &owner, &650);
+    assert_eq!(client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_get_tranches_claimable_flags() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    client.initialize_with_schedule(&owner, &token_id, &vec![&env, (100u64, 250i128), (200u64, 250i128), (300u64, 500i128)]);
+
+    env.ledger().set_timestamp(99);
+    assert_eq!(client.get_tranches(), vec![&env, (100u64, 250i128, false), (200u64, 250i128, false), (300u64, 500i128, false)]);
+
+    // Each tranche flips exactly at its own timestamp.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.get_tranches(), vec![&env, (100u64, 250i128, true), (200u64, 250i128, false), (300u64, 500i128, false)]);
+    env.ledger().set_timestamp(299);
+    assert_eq!(client.get_tranches(), vec![&env, (100u64, 250i128, true), (200u64, 250i128, true), (300u64, 500i128, false)]);
+    env.ledger().set_timestamp(300);
+    assert_eq!(client.get_tranches(), vec![&env, (100u64, 250i128, true), (200u64, 250i128, true), (300u64, 500i128, true)]);
+
+    // A vault without a schedule has no tranches.
+    let f = VaultFixture::new(100);
+    assert_eq!(f.client.get_tranches().len(), 0);
+}
+
+#[test]
+fn test_invalid_schedules_rejected() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = Address::generate(&env);
+
+    let unsorted = vec![&env, (200u64, 100i128), (100u64, 100i128)];
+    assert_eq!(client.try_initialize_with_schedule(&owner, &token_id, &unsorted), Err(Ok(VaultError::InvalidSchedule)));
+    let empty: Vec<(u64, i128)> = Vec::new(&env);
+    assert_eq!(client.try_initialize_with_schedule(&owner, &token_id, &empty), Err(Ok(VaultError::InvalidSchedule)));
+    let zero_amount = vec![&env, (100u64, 0i128)];
+    assert_eq!(client.try_initialize_with_schedule(&owner, &token_id, &zero_amount), Err(Ok(VaultError::InvalidSchedule)));
+}
+
+#[test]
+fn test_emergency_withdraw_applies_penalty() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let penalty_recipient = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    // 10% penalty for leaving before t=100.
+    client.initialize(&owner, &token_id, &100, &VaultOptions { penalty_bps: 1000, penalty_recipient: penalty_recipient.clone(), ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    env.ledger().set_timestamp(50);
+    client.emergency_withdraw(&recipient, &505);
+    // 505 * 10% = 50.5, rounded down to 50 in the recipient's favour.
+    assert_eq!(token.balance(&recipient), 455);
+    assert_eq!(token.balance(&penalty_recipient), 50);
+    assert_eq!(client.get_locked_amount(), 495);
+
+    // After unlock the emergency path is closed and the normal withdraw is unpenalized.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.try_emergency_withdraw(&recipient, &100), Err(Ok(VaultError::AlreadyUnlocked)));
+    client.withdraw(&token_id, &recipient, &495);
+    assert_eq!(token.balance(&recipient), 950);
+    assert_eq!(token.balance(&penalty_recipient), 50);
+}
+
+#[test]
+fn test_emergency_withdraw_disabled_without_penalty() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    assert_eq!(client.try_emergency_withdraw(&owner, &100), Err(Ok(VaultError::EmergencyWithdrawDisabled)));
+    assert_eq!(client.get_locked_amount(), 1000);
+}
+
+#[test]
+fn test_penalty_above_100_percent_rejected() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+
+    assert_eq!(
+        client.try_initialize(&owner, &Address::generate(&env), &100, &VaultOptions { penalty_bps: 10_001, ..default_options(&env) }),
+        Err(Ok(VaultError::InvalidBasisPoints))
+    );
+}
+
+#[test]
+fn test_deposit_without_approve() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    env.mock_all_auths();
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    // The user signs only the deposit, with the token transfer as a sub-invocation. No approve ever happens.
+    env.mock_auths(&[MockAuth {
+        address: &user,
+        invoke: &MockAuthInvoke {
+            contract: &contract_id,
+            fn_name: "deposit",
+            args: (&token_id, &user, 400i128).into_val(&env),
+            sub_invokes: &[MockAuthInvoke {
+                contract: &token_id,
+                fn_name: "transfer",
+                args: (&user, &contract_id, 400i128).into_val(&env),
+                sub_invokes: &[],
+            }],
+        },
+    }]);
+    client.deposit(&token_id, &user, &400);
+
+    // One authorization tree covered both the deposit and the token movement.
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            user.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((contract_id.clone(), symbol_short!("deposit"), (&token_id, &user, 400i128).into_val(&env))),
+                sub_invocations: std::vec![AuthorizedInvocation {
+                    function: AuthorizedFunction::Contract((token_id.clone(), symbol_short!("transfer"), (&user, &contract_id, 400i128).into_val(&env))),
+                    sub_invocations: std::vec![],
+                }],
+            }
+        )]
+    );
+    assert_eq!(token.allowance(&user, &contract_id), 0);
+    assert_eq!(token.balance(&user), 600);
+    assert_eq!(token.balance(&contract_id), 400);
+    assert_eq!(client.get_locked_amount(), 400);
+}
+
+#[test]
+fn test_withdrawal_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let fee_collector = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    // 2.5% withdrawal fee.
+    client.initialize(&owner, &token_id, &0, &VaultOptions { fee_bps: 250, fee_collector: fee_collector.clone(), ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    client.withdraw(&token_id, &recipient, &400);
+    assert_eq!(token.balance(&recipient), 390);
+    // The fee stays in the vault for the collector, outside the locked total, which the full gross amount leaves.
+    assert_eq!(client.get_accrued_fees(), 10);
+    assert_eq!(client.get_locked_amount(), 600);
+    assert_eq!(token.balance(&contract_id), 610);
+
+    client.withdraw(&token_id, &recipient, &200);
+    assert_eq!(client.get_accrued_fees(), 15);
+    assert_eq!(client.collect_fees(&fee_collector), 15);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("fees"), fee_collector.clone()).into_val(&env), 15i128.into_val(&env))]
+    );
+    assert_eq!(token.balance(&fee_collector), 15);
+    assert_eq!(client.get_accrued_fees(), 0);
+    assert_eq!(client.get_locked_amount(), 400);
+    assert_eq!(token.balance(&contract_id), 400);
+    // There is nothing left to collect.
+    assert_eq!(client.collect_fees(&fee_collector), 0);
+}
+
+#[test]
+fn test_collect_fees_requires_fee_collector() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { fee_bps: 250, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.client.withdraw(&f.token_id, &f.owner, &400);
+
+    let fee_collector = f.client.get_config().fee_collector;
+    let auth = MockAuth {
+        address: &f.owner,
+        invoke: &MockAuthInvoke { contract: &f.contract_id, fn_name: "collect_fees", args: (f.owner.clone(),).into_val(&f.env), sub_invokes: &[] },
+    };
+    assert!(f.client.mock_auths(&[auth]).try_collect_fees(&f.owner).is_err());
+    assert_eq!(f.client.collect_fees(&fee_collector), 10);
+    assert_eq!(f.token.balance(&fee_collector), 10);
+}
+
+#[test]
+fn test_zero_withdrawal_fee_pays_full_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let fee_collector = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &0, &VaultOptions { fee_collector: fee_collector.clone(), ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+
+    client.withdraw(&token_id, &recipient, &400);
+    assert_eq!(token.balance(&recipient), 400);
+    assert_eq!(token.balance(&fee_collector), 0);
+    assert_eq!(client.get_locked_amount(), 600);
+}
+
+#[test]
+fn test_fee_above_100_percent_rejected() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+
+    assert_eq!(
+        client.try_initialize(&owner, &Address::generate(&env), &0, &VaultOptions { fee_bps: 10_001, ..default_options(&env) }),
+        Err(Ok(VaultError::InvalidBasisPoints))
+    );
+}
+
+#[test]
+fn test_deposit_cap() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &2000);
+    client.initialize(&owner, &token_id, &0, &VaultOptions { max_total_deposit: 1000, ..default_options(&env) });
+    assert_eq!(client.get_deposit_cap(), 1000);
+
+    // A deposit that fits.
+    client.deposit(&token_id, &user, &600);
+    // One that lands exactly on the cap.
+    client.deposit(&token_id, &user, &400);
+    assert_eq!(client.get_locked_amount(), 1000);
+    // And one that would go over it.
+    assert_eq!(client.try_deposit(&token_id, &user, &1), Err(Ok(VaultError::CapExceeded)));
+    assert_eq!(client.get_locked_amount(), 1000);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&user), 1000);
+}
+
+#[test]
+fn test_zero_deposit_cap_is_unlimited() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1_000_000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+
+    assert_eq!(client.get_deposit_cap(), 0);
+    client.deposit(&token_id, &user, &1_000_000);
+    assert_eq!(client.get_locked_amount(), 1_000_000);
+}
+
+#[test]
+fn test_minimum_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &0, &VaultOptions { min_deposit: 100, ..default_options(&env) });
+    assert_eq!(client.get_min_deposit(), 100);
+
+    assert_eq!(client.try_deposit(&token_id, &user, &99), Err(Ok(VaultError::BelowMinimum)));
+    client.deposit(&token_id, &user, &100);
+    client.deposit(&token_id, &user, &101);
+    assert_eq!(client.get_locked_amount(), 201);
+
+    // The non-positive check still comes first.
+    assert_eq!(client.try_deposit(&token_id, &user, &0), Err(Ok(VaultError::NonPositiveAmount)));
+}
+
+#[test]
+fn test_get_state_matches_getters() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &500, &default_options(&env));
+    client.deposit(&token_id, &owner, &750);
+
+    let state = client.get_state();
+    assert_eq!(state.owner, Some(client.get_owner()));
+    assert_eq!(state.token_id, client.get_token_id());
+    assert_eq!(state.unlock_timestamp, client.get_unlock_time());
+    assert_eq!(state.locked_amount, client.get_locked_amount());
+    assert_eq!(state, VaultState { owner: Some(owner), token_id, unlock_timestamp: 500, locked_amount: 750 });
+}
+
+#[test]
+fn test_get_state_uninitialized() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.try_get_state(), Err(Ok(VaultError::NotInitialized)));
+}
+
+#[test]
+fn test_is_unlocked() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    assert_eq!(client.try_is_unlocked(), Err(Ok(VaultError::NotInitialized)));
+
+    client.initialize(&Address::generate(&env), &Address::generate(&env), &1000, &default_options(&env));
+
+    env.ledger().set_timestamp(999);
+    assert!(!client.is_unlocked());
+    env.ledger().set_timestamp(1000);
+    assert!(client.is_unlocked());
+    env.ledger().set_timestamp(5000);
+    assert!(client.is_unlocked());
+}
+
+#[test]
+fn test_time_until_unlock() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    client.initialize(&Address::generate(&env), &Address::generate(&env), &1000, &default_options(&env));
+
+    env.ledger().set_timestamp(0);
+    assert_eq!(client.time_until_unlock(), 1000);
+    env.ledger().set_timestamp(400);
+    assert_eq!(client.time_until_unlock(), 600);
+    env.ledger().set_timestamp(999);
+    assert_eq!(client.time_until_unlock(), 1);
+    // Exactly at the boundary and beyond, nothing remains.
+    env.ledger().set_timestamp(1000);
+    assert_eq!(client.time_until_unlock(), 0);
+    env.ledger().set_timestamp(u64::MAX);
+    assert_eq!(client.time_until_unlock(), 0);
+}
+
+#[test]
+fn test_upgrade_preserves_storage() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &500);
+    assert_eq!(client.get_version(), VERSION);
+    assert_eq!(client.get_stored_version(), VERSION);
+    // Nothing to migrate before an upgrade.
+    assert_eq!(client.try_migrate(), Err(Ok(VaultError::AlreadyMigrated)));
+
+    let new_wasm_hash = env.deployer().upload_contract_wasm(vault_v2::WASM);
+    client.upgrade(&new_wasm_hash);
+
+    // The same contract address now runs the v2 code, on top of the v1 storage.
+    let v2 = vault_v2::Client::new(&env, &contract_id);
+    assert_eq!(v2.v2_only(), symbol_short!("v2"));
+    assert_eq!(v2.get_owner(), Some(owner));
+    assert_eq!(v2.get_locked_amount(), 500);
+    // The code reports its new version straight away, but storage only catches up once migrated.
+    assert_eq!(v2.get_version(), 2);
+    assert_eq!(v2.get_stored_version(), 1);
+    v2.migrate();
+    assert_eq!(v2.get_stored_version(), 2);
+}
+
+#[test]
+fn test_upgrade_requires_owner() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    client.initialize(&Address::generate(&env), &Address::generate(&env), &100, &default_options(&env));
+
+    let new_wasm_hash = env.deployer().upload_contract_wasm(vault_v2::WASM);
+    assert!(client.try_upgrade(&new_wasm_hash).is_err());
+    assert_eq!(client.get_version(), VERSION);
+    assert_eq!(client.get_stored_version(), VERSION);
+}
+
+#[test]
+fn test_deposit_extends_instance_ttl() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    assert_eq!(env.as_contract(&contract_id, || env.storage().instance().get_ttl()), INSTANCE_BUMP_AMOUNT);
+
+    // Advance until the remaining TTL falls under the threshold, then deposit.
+    let sequence = env.ledger().sequence() + DAY_IN_LEDGERS + 1;
+    env.ledger().set_sequence_number(sequence);
+    client.deposit(&token_id, &owner, &100);
+    assert_eq!(env.as_contract(&contract_id, || env.storage().instance().get_ttl()), INSTANCE_BUMP_AMOUNT);
+
+    // Past where the entry would have expired without the bump, the vault is still live and readable.
+    env.ledger().set_sequence_number(sequence + INSTANCE_BUMP_AMOUNT - 1);
+    assert_eq!(client.get_locked_amount(), 100);
+}
+
+#[test]
+fn test_balances_live_in_persistent_storage() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &user, &100);
+
+    let balance_ttl = || env.as_contract(&contract_id, || env.storage().persistent().get_ttl(&DataKey::Balance(user.clone())));
+    let locked_ttl = || env.as_contract(&contract_id, || env.storage().persistent().get_ttl(&DataKey::LockedAmount));
+    assert_eq!(balance_ttl(), PERSISTENT_BUMP_AMOUNT);
+    assert_eq!(locked_ttl(), PERSISTENT_BUMP_AMOUNT);
+
+    // Once the remaining TTL drops under the threshold, the next deposit pushes both entries back out.
+    let sequence = env.ledger().sequence() + DAY_IN_LEDGERS + 1;
+    env.ledger().set_sequence_number(sequence);
+    assert_eq!(balance_ttl(), PERSISTENT_LIFETIME_THRESHOLD - 1);
+    client.deposit(&token_id, &user, &50);
+    assert_eq!(balance_ttl(), PERSISTENT_BUMP_AMOUNT);
+    assert_eq!(locked_ttl(), PERSISTENT_BUMP_AMOUNT);
+
+    // Much later the entries are still live and readable.
+    env.ledger().set_sequence_number(sequence + INSTANCE_BUMP_AMOUNT - 1);
+    assert_eq!(client.get_balance(&user), 150);
+    assert_eq!(client.get_locked_amount(), 150);
+}
+
+#[test]
+fn test_malicious_token_cannot_reenter_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths_allowing_non_root_auth();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = env.register(ReentrantToken, ());
+    let token = ReentrantTokenClient::new(&env, &token_id);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    token.arm(&contract_id);
+    client.withdraw(&token_id, &owner, &400);
+
+    // The nested withdraw was refused, so the vault only paid out once.
+    assert_eq!(token.reentry_failed(), Some(true));
+    assert_eq!(client.get_locked_amount(), 600);
+}
+
+#[test]
+fn test_reentrancy_guard_rejects_nested_calls() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &owner, &500);
+
+    // The host aborts real re-entry before the vault runs, so simulate a call that is still in progress.
+    env.as_contract(&contract_id, || env.storage().instance().set(&DataKey::Locked, &true));
+    assert_eq!(client.try_deposit(&token_id, &owner, &100), Err(Ok(VaultError::Reentrancy)));
+    assert_eq!(client.try_withdraw(&token_id, &owner, &100), Err(Ok(VaultError::Reentrancy)));
+    assert_eq!(client.try_withdraw_own(&owner, &100), Err(Ok(VaultError::Reentrancy)));
+
+    // A completed call always leaves the guard cleared.
+    env.as_contract(&contract_id, || env.storage().instance().remove(&DataKey::Locked));
+    client.withdraw(&token_id, &owner, &100);
+    assert!(!env.as_contract(&contract_id, || env.storage().instance().has(&DataKey::Locked)));
+}
+
+#[test]
+fn test_withdraw_batch_three_way_split() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let (a, b, c) = (Address::generate(&env), Address::generate(&env), Address::generate(&env));
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    let batch = vec![&env, (a.clone(), 100i128), (b.clone(), 200i128), (c.clone(), 300i128)];
+    env.ledger().set_timestamp(99);
+    assert_eq!(client.try_withdraw_batch(&batch), Err(Ok(VaultError::StillLocked)));
+
+    env.ledger().set_timestamp(100);
+    client.withdraw_batch(&batch);
+    assert_eq!(vault_events(&env, &contract_id).len(), 3);
+    assert_eq!(token.balance(&a), 100);
+    assert_eq!(token.balance(&b), 200);
+    assert_eq!(token.balance(&c), 300);
+    assert_eq!(client.get_locked_amount(), 400);
+}
+
+#[test]
+fn test_withdraw_batch_over_locked_amount_reverts() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let (a, b) = (Address::generate(&env), Address::generate(&env));
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    // Each amount fits on its own, but together they exceed the vault, so nobody gets paid.
+    let batch = vec![&env, (a.clone(), 600i128), (b.clone(), 600i128)];
+    assert_eq!(client.try_withdraw_batch(&batch), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(token.balance(&a), 0);
+    assert_eq!(token.balance(&b), 0);
+    assert_eq!(client.get_locked_amount(), 1000);
+
+    let with_zero = vec![&env, (a.clone(), 100i128), (b.clone(), 0i128)];
+    assert_eq!(client.try_withdraw_batch(&with_zero), Err(Ok(VaultError::NonPositiveAmount)));
+}
+
+#[test]
+fn test_deposit_batch_three_depositors() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let (a, b, c) = (Address::generate(&env), Address::generate(&env), Address::generate(&env));
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&a, &1000);
+    token_admin.mint(&b, &1000);
+    token_admin.mint(&c, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+
+    client.deposit_batch(&vec![&env, (a.clone(), 100i128), (b.clone(), 200i128), (c.clone(), 300i128)]);
+    assert_eq!(vault_events(&env, &contract_id).len(), 3);
+    // Every depositor signed for their own entry.
+    let signers: std::vec::Vec<Address> = env.auths().into_iter().map(|(address, _)| address).collect();
+    assert_eq!(signers, std::vec![a.clone(), b.clone(), c.clone()]);
+
+    assert_eq!(client.get_balance(&a), 100);
+    assert_eq!(client.get_balance(&b), 200);
+    assert_eq!(client.get_balance(&c), 300);
+    assert_eq!(client.get_locked_amount(), 600);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&contract_id), 600);
+}
+
+#[test]
+fn test_deposit_batch_with_non_positive_amount_reverts() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let (a, b) = (Address::generate(&env), Address::generate(&env));
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&a, &1000);
+    token_admin.mint(&b, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+
+    assert_eq!(
+        client.try_deposit_batch(&vec![&env, (a.clone(), 100i128), (b.clone(), -1i128)]),
+        Err(Ok(VaultError::NonPositiveAmount))
+    );
+    assert_eq!(client.get_balance(&a), 0);
+    assert_eq!(client.get_locked_amount(), 0);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&a), 1000);
+}
+
+#[test]
+fn test_beneficiary_withdraw_after_owner_inactivity() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let beneficiary = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &VaultOptions { inactivity_window: 1000, ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+    assert_eq!(client.try_beneficiary_withdraw(&beneficiary, &100), Err(Ok(VaultError::NoBeneficiary)));
+
+    // The owner's last action is at t=50.
+    env.ledger().set_timestamp(50);
+    client.set_beneficiary(&beneficiary);
+    assert_eq!(client.get_beneficiary(), Some(beneficiary.clone()));
+
+    // Unlocked, but the owner acted less than 1000 seconds ago.
+    env.ledger().set_timestamp(1049);
+    assert_eq!(client.try_beneficiary_withdraw(&beneficiary, &100), Err(Ok(VaultError::OwnerStillActive)));
+
+    // Any owner call resets the window.
+    client.pause();
+    client.unpause();
+    env.ledger().set_timestamp(2048);
+    assert_eq!(client.try_beneficiary_withdraw(&beneficiary, &100), Err(Ok(VaultError::OwnerStillActive)));
+
+    env.ledger().set_timestamp(2049);
+    client.beneficiary_withdraw(&beneficiary, &400);
+    assert_eq!(env.auths()[0].0, beneficiary);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&beneficiary), 400);
+    assert_eq!(client.get_locked_amount(), 600);
+}
+
+#[test]
+fn test_beneficiary_withdraw_respects_unlock_time() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let beneficiary = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &5000, &VaultOptions { inactivity_window: 10, ..default_options(&env) });
+    client.deposit(&token_id, &owner, &1000);
+    client.set_beneficiary(&beneficiary);
+
+    // The owner has been inactive long enough, but the vault itself is still locked.
+    env.ledger().set_timestamp(4999);
+    assert_eq!(client.try_beneficiary_withdraw(&beneficiary, &100), Err(Ok(VaultError::StillLocked)));
+}
+
+#[test]
+fn test_last_activity_tracks_deposits_and_withdrawals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    assert_eq!(client.get_last_activity(), 0);
+
+    env.ledger().set_timestamp(10);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(client.get_last_activity(), 10);
+
+    env.ledger().set_timestamp(20);
+    client.deposit(&token_id, &owner, &1000);
+    assert_eq!(client.get_last_activity(), 20);
+
+    // Owner-only configuration calls are not deposits or withdrawals and leave it untouched.
+    env.ledger().set_timestamp(50);
+    client.pause();
+    client.unpause();
+    assert_eq!(client.get_last_activity(), 20);
+
+    env.ledger().set_timestamp(150);
+    client.withdraw(&token_id, &owner, &300);
+    assert_eq!(client.get_last_activity(), 150);
+}
+
+#[test]
+fn test_deposit_with_unlock_delays_withdraw_own() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&alice, &1000);
+    token_admin.mint(&bob, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    // A personal unlock can't be earlier than the vault's.
+    assert_eq!(client.try_deposit_with_unlock(&alice, &100, &99), Err(Ok(VaultError::UnlockBeforeVault)));
+
+    client.deposit_with_unlock(&alice, &400, &500);
+    client.deposit(&token_id, &bob, &400);
+    assert_eq!(client.get_balance(&alice), 400);
+    assert_eq!(client.get_locked_amount(), 800);
+    assert_eq!(client.get_deposit_unlock(&alice), Some(500));
+    assert_eq!(client.get_deposit_unlock(&bob), None);
+
+    // A later deposit with an earlier time doesn't shorten Alice's lock.
+    client.deposit_with_unlock(&alice, &100, &200);
+    assert_eq!(client.get_deposit_unlock(&alice), Some(500));
+
+    // The vault is unlocked, so Bob can leave, but Alice's own lock still holds.
+    env.ledger().set_timestamp(100);
+    client.withdraw_own(&bob, &400);
+    assert_eq!(client.try_withdraw_own(&alice, &500), Err(Ok(VaultError::StillLocked)));
+
+    env.ledger().set_timestamp(500);
+    client.withdraw_own(&alice, &500);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&alice), 1000);
+    assert_eq!(client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_get_depositors() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&alice, &1000);
+    token_admin.mint(&bob, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(client.get_depositors(), vec![&env]);
+
+    client.deposit(&token_id, &alice, &100);
+    client.deposit(&token_id, &bob, &100);
+    assert_eq!(client.get_depositors(), vec![&env, alice.clone(), bob.clone()]);
+
+    // Repeat deposits, single or batched, don't add duplicates.
+    client.deposit(&token_id, &alice, &100);
+    client.deposit_batch(&vec![&env, (alice.clone(), 50), (bob.clone(), 50)]);
+    assert_eq!(client.get_depositors(), vec![&env, alice.clone(), bob.clone()]);
+
+    // A partial withdrawal keeps Alice listed; a full one removes her.
+    env.ledger().set_timestamp(100);
+    client.withdraw_own(&alice, &200);
+    assert_eq!(client.get_depositors(), vec![&env, alice.clone(), bob.clone()]);
+    client.withdraw_own(&alice, &50);
+    assert_eq!(client.get_depositors(), vec![&env, bob.clone()]);
+
+    // Coming back after leaving lists her again.
+    client.deposit(&token_id, &alice, &10);
+    assert_eq!(client.get_depositors(), vec![&env, bob.clone(), alice.clone()]);
+}
+
+#[test]
+fn test_deposit_and_withdraw_multiple_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    let other_token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &600);
+    StellarAssetClient::new(&env, &other_token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    // Only the primary token is accepted until the owner whitelists another.
+    assert_eq!(client.try_deposit(&other_token_id, &user, &100), Err(Ok(VaultError::TokenNotAllowed)));
+    client.add_allowed_token(&other_token_id);
+
+    assert_eq!(client.deposit(&token_id, &owner, &600), 600);
+    assert_eq!(client.deposit(&other_token_id, &user, &300), 300);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("deposit"), user.clone(), other_token_id.clone()).into_val(&env), (300i128, 300i128, 2u64).into_val(&env))]
+    );
+    assert_eq!(client.get_token_balance(&token_id), 600);
+    assert_eq!(client.get_token_balance(&other_token_id), 300);
+    // Other tokens are tracked separately from the primary locked amount.
+    assert_eq!(client.get_locked_amount(), 600);
+
+    // Both tokens share the vault's time lock.
+    assert_eq!(client.try_withdraw(&other_token_id, &owner, &100), Err(Ok(VaultError::StillLocked)));
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.try_withdraw(&other_token_id, &owner, &301), Err(Ok(VaultError::InsufficientFunds)));
+
+    // Each token is withdrawn independently of the other.
+    assert_eq!(client.withdraw(&other_token_id, &owner, &300), 0);
+    assert_eq!(TokenClient::new(&env, &other_token_id).balance(&owner), 300);
+    assert_eq!(client.get_token_balance(&other_token_id), 0);
+    assert_eq!(client.get_token_balance(&token_id), 600);
+
+    assert_eq!(client.withdraw(&token_id, &owner, &250), 350);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&owner), 250);
+    assert_eq!(client.get_token_balance(&token_id), 350);
+    assert_eq!(client.get_token_balance(&other_token_id), 0);
+
+    // A token the vault never received has nothing to withdraw.
+    let stranger_token_id = create_token(&env);
+    assert_eq!(client.try_withdraw(&stranger_token_id, &owner, &1), Err(Ok(VaultError::InsufficientFunds)));
+}
+
+#[test]
+fn test_token_whitelist() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    let other_token_id = create_token(&env);
+    StellarAssetClient::new(&env, &other_token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+
+    // The primary token is always allowed and can't be delisted.
+    assert!(client.is_token_allowed(&token_id));
+    client.remove_allowed_token(&token_id);
+    assert!(client.is_token_allowed(&token_id));
+
+    assert!(!client.is_token_allowed(&other_token_id));
+    assert_eq!(client.try_deposit(&other_token_id, &user, &100), Err(Ok(VaultError::TokenNotAllowed)));
+
+    client.add_allowed_token(&other_token_id);
+    assert!(client.is_token_allowed(&other_token_id));
+    client.deposit(&other_token_id, &user, &100);
+
+    // Once removed, new deposits are rejected but the existing balance can still come out.
+    client.remove_allowed_token(&other_token_id);
+    assert!(!client.is_token_allowed(&other_token_id));
+    assert_eq!(client.try_deposit(&other_token_id, &user, &100), Err(Ok(VaultError::TokenNotAllowed)));
+    client.withdraw(&other_token_id, &owner, &100);
+    assert_eq!(TokenClient::new(&env, &other_token_id).balance(&owner), 100);
+}
+
+#[test]
+fn test_renounce_ownership() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &user, &500);
+    client.transfer_ownership(&user);
+
+    client.renounce_ownership();
+    assert_eq!(env.auths()[0].0, owner);
+    assert_eq!(client.try_get_owner(), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.get_state().owner, None);
+    assert_eq!(client.get_pending_owner(), None);
+
+    // Every owner-only function now reverts, and the cancelled nominee can't step in.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &100), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.try_pause(), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.try_extend_lock(&200), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.try_transfer_ownership(&owner), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.try_renounce_ownership(), Err(Ok(VaultError::OwnershipRenounced)));
+    assert_eq!(client.try_accept_ownership(), Err(Ok(VaultError::NoPendingOwner)));
+    assert_eq!(client.try_initialize(&user, &token_id, &0, &default_options(&env)), Err(Ok(VaultError::AlreadyInitialized)));
+
+    // Depositors can still take out their own funds.
+    client.withdraw_own(&user, &500);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&user), 1000);
+}
+
+#[test]
+fn test_initialized_flag_survives_renouncement() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let attacker = Address::generate(&env);
+    let token_id = create_token(&env);
+    assert!(!client.is_initialized());
+
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert!(client.is_initialized());
+
+    // Clearing the owner doesn't make the vault look uninitialized, so nobody can re-initialize it.
+    client.renounce_ownership();
+    assert!(client.is_initialized());
+    assert_eq!(client.try_initialize(&attacker, &token_id, &0, &default_options(&env)), Err(Ok(VaultError::AlreadyInitialized)));
+    assert_eq!(client.try_get_owner(), Err(Ok(VaultError::OwnershipRenounced)));
+}
+
+#[test]
+fn test_relock_holds_back_a_portion() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+
+    // Nothing to relock before the vault itself has unlocked.
+    assert_eq!(client.try_relock(&400, &500), Err(Ok(VaultError::StillLocked)));
+
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.try_relock(&400, &100), Err(Ok(VaultError::LockNotExtended)));
+    assert_eq!(client.try_relock(&1001, &500), Err(Ok(VaultError::InsufficientFunds)));
+    client.relock(&400, &500);
+    assert_eq!(client.get_relock(), (400, 500));
+    // No tokens moved.
+    assert_eq!(token.balance(&contract_id), 1000);
+    assert_eq!(client.get_locked_amount(), 1000);
+    assert_eq!(client.try_relock(&601, &600), Err(Ok(VaultError::InsufficientFunds)));
+
+    // The rest is still withdrawable, but the relocked portion isn't.
+    client.withdraw(&token_id, &owner, &600);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::StillLocked)));
+
+    env.ledger().set_timestamp(500);
+    assert_eq!(client.get_relock(), (0, 0));
+    client.withdraw(&token_id, &owner, &400);
+    assert_eq!(token.balance(&owner), 1000);
+}
+
+#[test]
+fn test_rewards_accrue_for_a_depositor() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&owner, &100);
+    token_admin.mint(&user, &1000);
+    // 1% of the balance per second.
+    let reward_rate = (REWARD_RATE_SCALE / 100) as u64;
+    client.initialize(&owner, &token_id, &1000, &VaultOptions { reward_rate, ..default_options(&env) });
+    client.fund_rewards(&60);
+    assert_eq!(client.get_reward_pool(), 60);
+    assert_eq!(client.get_locked_amount(), 0);
+
+    env.ledger().set_timestamp(10);
+    client.deposit(&token_id, &user, &500);
+    assert_eq!(client.get_pending_rewards(&user), 0);
+
+    // 500 * 1% * 4s = 20.
+    env.ledger().set_timestamp(14);
+    assert_eq!(client.get_pending_rewards(&user), 20);
+    assert_eq!(client.claim_rewards(&user), 20);
+    assert_eq!(token.balance(&user), 520);
+    assert_eq!(client.get_reward_pool(), 40);
+    assert_eq!(client.get_pending_rewards(&user), 0);
+
+    // A larger balance earns faster from the moment it changes: 20 at 500, then 1000 * 1% * 2s = 20.
+    env.ledger().set_timestamp(18);
+    client.deposit(&token_id, &user, &500);
+    env.ledger().set_timestamp(20);
+    assert_eq!(client.get_pending_rewards(&user), 40);
+    assert_eq!(client.claim_rewards(&user), 40);
+    assert_eq!(client.get_reward_pool(), 0);
+
+    // The pool is empty, so nothing more can be paid and nothing is lost.
+    env.ledger().set_timestamp(21);
+    assert_eq!(client.try_claim_rewards(&user), Err(Ok(VaultError::InsufficientRewards)));
+    assert_eq!(client.get_pending_rewards(&user), 10);
+    assert_eq!(client.get_locked_amount(), 1000);
+}
+
+#[test]
+fn test_reconcile_picks_up_direct_transfers() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token_admin = StellarAssetClient::new(&env, &token_id);
+    token_admin.mint(&owner, &100);
+    token_admin.mint(&user, &1000);
+    client.initialize(&owner, &token_id, &0, &default_options(&env));
+    client.deposit(&token_id, &user, &500);
+    client.fund_rewards(&100);
+
+    // The books match, even with the reward pool sitting in the same token.
+    assert_eq!(client.actual_token_balance(), 600);
+    assert_eq!(client.reconcile(), 0);
+    assert_eq!(client.get_locked_amount(), 500);
+
+    // Tokens sent straight to the vault bypass deposit.
+    TokenClient::new(&env, &token_id).transfer(&user, &contract_id, &250);
+    assert_eq!(client.actual_token_balance(), 850);
+    assert_eq!(client.get_locked_amount(), 500);
+
+    assert_eq!(client.reconcile(), 250);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("reconcile"),).into_val(&env), (250i128, 750i128).into_val(&env))]
+    );
+    assert_eq!(client.get_locked_amount(), 750);
+    assert_eq!(client.get_balance(&user), 500);
+    // Nobody is credited with the surplus, so it is the owner's; the user's 500 stays theirs.
+    assert_eq!(client.try_withdraw(&token_id, &owner, &251), Err(Ok(VaultError::InsufficientFunds)));
+    client.withdraw(&token_id, &owner, &250);
+    assert_eq!(client.get_locked_amount(), 500);
+    assert_eq!(client.get_reward_pool(), 100);
+}
+
+#[test]
+fn test_initialize_rejects_self_referential_addresses() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+
+    assert_eq!(client.try_initialize(&token_id, &token_id, &100, &default_options(&env)), Err(Ok(VaultError::InvalidAddress)));
+    assert_eq!(client.try_initialize(&owner, &contract_id, &100, &default_options(&env)), Err(Ok(VaultError::InvalidAddress)));
+    assert!(!client.is_initialized());
+
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(client.get_owner(), owner);
+    assert_eq!(client.get_token_id(), token_id);
+}
+
+#[test]
+fn test_initialize_rejects_past_unlock() {
+    let env = Env::default();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    env.ledger().set_timestamp(1000);
+
+    assert_eq!(client.try_initialize(&owner, &token_id, &999, &default_options(&env)), Err(Ok(VaultError::UnlockInPast)));
+    client.initialize(&owner, &token_id, &1001, &default_options(&env));
+    assert_eq!(client.get_unlock_time(), 1001);
+}
+
+#[test]
+fn test_max_lock_duration() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    env.ledger().set_timestamp(1000);
+
+    assert_eq!(
+        client.try_initialize(&owner, &token_id, &(1000 + MAX_LOCK_DURATION + 1), &default_options(&env)),
+        Err(Ok(VaultError::LockTooLong))
+    );
+    client.initialize(&owner, &token_id, &(1000 + MAX_LOCK_DURATION), &default_options(&env));
+
+    // The cap is measured from the current ledger time, so extending later allows a later unlock.
+    env.ledger().set_timestamp(2000);
+    assert_eq!(client.try_extend_lock(&(2000 + MAX_LOCK_DURATION + 1)), Err(Ok(VaultError::LockTooLong)));
+    client.extend_lock(&(2000 + MAX_LOCK_DURATION));
+    assert_eq!(client.get_unlock_time(), 2000 + MAX_LOCK_DURATION);
+}
+
+#[test]
+fn test_withdrawable_now_matches_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    let options = VaultOptions { vesting_start: 100, vesting_duration: 1000, ..default_options(&env) };
+    client.initialize(&owner, &token_id, &100, &options);
+    client.deposit(&token_id, &owner, &1000);
+
+    // Before unlock nothing is withdrawable, and withdraw agrees.
+    assert_eq!(client.withdrawable_now(), 0);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::StillLocked)));
+
+    // At each point, exactly the reported amount can come out and not a token more.
+    for (timestamp, expected) in [(350u64, 250i128), (600, 250), (1100, 500)] {
+        env.ledger().set_timestamp(timestamp);
+        let withdrawable = client.withdrawable_now();
+        assert_eq!(withdrawable, expected);
+        assert!(client.try_withdraw(&token_id, &owner, &(withdrawable + 1)).is_err());
+        client.withdraw(&token_id, &owner, &withdrawable);
+        assert_eq!(client.withdrawable_now(), 0);
+    }
+    assert_eq!(client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_deposit_with_memo_emits_memo() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    let memo = BytesN::from_array(&env, &[7u8; 32]);
+    assert_eq!(client.deposit_with_memo(&user, &300, &memo), 300);
+    let events = vault_events(&env, &contract_id);
+    assert_eq!(
+        events,
+        vec![&env, (contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&env), (300i128, 300i128, 1u64, memo.clone()).into_val(&env))]
+    );
+    // The memo round-trips byte for byte.
+    let (_, _, _, emitted_memo): (i128, i128, u64, BytesN<32>) = events.get(0).unwrap().2.into_val(&env);
+    assert_eq!(emitted_memo.to_array(), [7u8; 32]);
+    assert_eq!(client.get_balance(&user), 300);
+}
+
+#[test]
+fn test_withdrawal_destination_lock() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    let options = VaultOptions { withdrawal_destination: Some(treasury.clone()), ..default_options(&env) };
+    client.initialize(&owner, &token_id, &0, &options);
+    client.deposit(&token_id, &owner, &1000);
+    assert_eq!(client.get_withdrawal_destination(), Some(treasury.clone()));
+
+    // Even the owner can't send funds anywhere but the approved destination.
+    assert_eq!(client.try_withdraw(&token_id, &owner, &100), Err(Ok(VaultError::DestinationNotAllowed)));
+    assert_eq!(
+        client.try_withdraw_batch(&vec![&env, (treasury.clone(), 100), (owner.clone(), 100)]),
+        Err(Ok(VaultError::DestinationNotAllowed))
+    );
+
+    client.withdraw(&token_id, &treasury, &400);
+    client.withdraw_batch(&vec![&env, (treasury.clone(), 100)]);
+    assert_eq!(TokenClient::new(&env, &token_id).balance(&treasury), 500);
+    assert_eq!(client.get_locked_amount(), 500);
+}
+
+#[test]
+fn test_withdrawal_limit_resets_each_day() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    client.deposit(&token_id, &owner, &1000);
+    assert_eq!(client.try_set_withdrawal_limit(&-1), Err(Ok(VaultError::InvalidLimit)));
+    client.set_withdrawal_limit(&300);
+    assert_eq!(client.get_withdrawal_limit(), 300);
+
+    // Exhaust the limit across several withdrawals in the same window.
+    env.ledger().set_timestamp(100);
+    client.withdraw(&token_id, &owner, &200);
+    client.withdraw_batch(&vec![&env, (owner.clone(), 100)]);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::LimitExceeded)));
+
+    // Just before the window rolls over, still nothing.
+    env.ledger().set_timestamp(100 + WITHDRAWAL_WINDOW - 1);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &1), Err(Ok(VaultError::LimitExceeded)));
+
+    // A day later the limit resets, but a single withdrawal still can't exceed it.
+    env.ledger().set_timestamp(100 + WITHDRAWAL_WINDOW);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &301), Err(Ok(VaultError::LimitExceeded)));
+    client.withdraw(&token_id, &owner, &300);
+    assert_eq!(client.get_locked_amount(), 400);
+
+    // Removing the limit lets the rest out at once.
+    client.set_withdrawal_limit(&0);
+    client.withdraw(&token_id, &owner, &400);
+    assert_eq!(client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_deposit_partial_leaves_free_portion_withdrawable() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+    let token = TokenClient::new(&env, &token_id);
+    StellarAssetClient::new(&env, &token_id).mint(&owner, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    assert_eq!(client.try_deposit_partial(&owner, &1000, &10_001), Err(Ok(VaultError::InvalidBasisPoints)));
+    // Half is time-locked, half is free.
+    assert_eq!(client.deposit_partial(&owner, &1000, &5_000), 500);
+    assert_eq!(client.get_locked_amount(), 500);
+    assert_eq!(client.get_free_amount(), 500);
+    assert_eq!(client.get_balance(&owner), 1000);
+    assert_eq!(client.withdrawable_now(), 500);
+
+    // Before unlock only the free half can come out.
+    assert_eq!(client.try_withdraw(&token_id, &owner, &501), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(client.withdraw(&token_id, &owner, &300), 500);
+    assert_eq!(client.get_free_amount(), 200);
+    assert_eq!(client.try_withdraw(&token_id, &owner, &201), Err(Ok(VaultError::StillLocked)));
+
+    // After unlock the rest of the free portion goes first, then the locked half.
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.withdrawable_now(), 700);
+    assert_eq!(client.withdraw(&token_id, &owner, &400), 300);
+    assert_eq!(client.get_free_amount(), 0);
+    assert_eq!(client.withdraw(&token_id, &owner, &300), 0);
+    assert_eq!(token.balance(&owner), 1000);
+}
+
+#[test]
+fn test_deposit_partial_is_owner_only() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    let other = f.funded_user(1000);
+    let owner = f.funded_owner(1000);
+
+    // Someone else's free portion would be the owner's to take, so only the owner may deposit partially.
+    assert_eq!(f.client.try_deposit_partial(&user, &1000, &5_000), Err(Ok(VaultError::Unauthorized)));
+    f.client.deposit(&f.token_id, &user, &1000);
+    f.client.deposit(&f.token_id, &other, &1000);
+    f.client.deposit_partial(&owner, &1000, &5_000);
+
+    // The owner's free half comes out before unlock and off the owner's balance, and nobody else's.
+    assert_eq!(f.client.try_withdraw(&f.token_id, &owner, &501), Err(Ok(VaultError::StillLocked)));
+    f.client.withdraw(&f.token_id, &owner, &500);
+    assert_eq!(f.client.get_balance(&owner), 500);
+    assert_eq!((f.client.get_balance(&user), f.client.get_balance(&other)), (1000, 1000));
+
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &owner, &501), Err(Ok(VaultError::InsufficientFunds)));
+    f.client.withdraw_own(&user, &1000);
+    f.client.withdraw_own(&other, &1000);
+    f.client.withdraw_own(&owner, &500);
+    assert_eq!(f.client.get_locked_amount(), 0);
+    assert_eq!(f.token.balance(&other), 1000);
+}
+
+#[test]
+fn test_fixture_deposit_withdraw_round_trip() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+
+    // Depositing moves real tokens from the owner into the vault.
+    f.client.deposit(&f.token_id, &owner, &600);
+    assert_eq!(f.token.balance(&owner), 400);
+    assert_eq!(f.token.balance(&f.contract_id), 600);
+    assert_eq!(f.client.get_locked_amount(), 600);
+
+    // Withdrawing after unlock moves them back out to the owner.
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &600);
+    assert_eq!(f.token.balance(&f.owner), 1000);
+    assert_eq!(f.token.balance(&f.contract_id), 0);
+    assert_eq!(f.client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_unlock_boundary() {
+    let f = VaultFixture::new(1_000);
+    let owner = f.funded_owner(300);
+    f.client.deposit(&f.token_id, &owner, &300);
+
+    // One second before unlock, the time lock still holds.
+    f.warp_to(999);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &100), Err(Ok(VaultError::StillLocked)));
+    assert!(!f.client.is_unlocked());
+
+    // Exactly at the unlock timestamp, withdrawal is allowed.
+    f.warp_to(1_000);
+    assert!(f.client.is_unlocked());
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+
+    // And any time after.
+    f.warp_to(1_000_000);
+    f.client.withdraw(&f.token_id, &f.owner, &200);
+    assert_eq!(f.token.balance(&f.owner), 300);
+}
+
+#[test]
+fn test_get_all_balances() {
+    let f = VaultFixture::new(100);
+    let alice = f.funded_user(1000);
+    let bob = f.funded_user(1000);
+    let carol = f.funded_user(1000);
+    assert_eq!(f.client.get_all_balances(), map![&f.env]);
+
+    f.client.deposit(&f.token_id, &alice, &100);
+    f.client.deposit(&f.token_id, &bob, &200);
+    f.client.deposit(&f.token_id, &carol, &300);
+    f.client.deposit(&f.token_id, &alice, &50);
+    assert_eq!(f.client.get_all_balances(), map![&f.env, (alice.clone(), 150), (bob.clone(), 200), (carol.clone(), 300)]);
+
+    // Bob leaves entirely and drops out of the map.
+    f.warp_to(100);
+    f.client.withdraw_own(&bob, &200);
+    assert_eq!(f.client.get_all_balances(), map![&f.env, (alice.clone(), 150), (carol.clone(), 300)]);
+}
+
+#[test]
+fn test_deposit_and_withdraw_sequence_numbers() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    let other = f.funded_user(1000);
+    assert_eq!((f.client.get_deposit_seq(), f.client.get_withdraw_seq()), (0, 0));
+
+    f.client.deposit(&f.token_id, &owner, &100);
+    f.client.deposit(&f.token_id, &owner, &100);
+    assert_eq!(f.client.get_deposit_seq(), 2);
+
+    // Each entry of a batch gets its own number, continuing the same sequence.
+    f.client.deposit_batch(&vec![&f.env, (owner.clone(), 100), (other.clone(), 100)]);
+    let events = vault_events(&f.env, &f.contract_id);
+    let seqs: std::vec::Vec<u64> = events.iter().map(|event| <(i128, i128, u64)>::try_from_val(&f.env, &event.2).unwrap().2).collect();
+    assert_eq!(seqs, [3, 4]);
+    assert_eq!(f.client.get_deposit_seq(), 4);
+
+    // Withdrawals count separately.
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &50);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("withdraw"), f.owner.clone()).into_val(&f.env), (50i128, 350i128, 1u64).into_val(&f.env))]
+    );
+    f.client.withdraw_batch(&vec![&f.env, (f.owner.clone(), 50), (owner.clone(), 50)]);
+    assert_eq!(f.client.get_withdraw_seq(), 3);
+    assert_eq!(f.client.get_deposit_seq(), 4);
+}
+
+#[test]
+fn test_grace_period_delays_withdrawals() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { grace_period: 50, ..default_options(env) });
+    let user = f.funded_user(900);
+    let owner = f.funded_owner(100);
+    f.client.deposit(&f.token_id, &user, &900);
+    f.client.deposit(&f.token_id, &owner, &100);
+    assert_eq!(f.client.get_unlock_time(), 100);
+    assert_eq!(f.client.effective_unlock_time(), 150);
+
+    // At the raw unlock time the grace period is still running.
+    f.warp_to(100);
+    assert!(!f.client.is_unlocked());
+    assert_eq!(f.client.time_until_unlock(), 50);
+    assert_eq!(f.client.withdrawable_now(), 0);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &100), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.try_withdraw_own(&user, &100), Err(Ok(VaultError::StillLocked)));
+
+    f.warp_to(150);
+    assert!(f.client.is_unlocked());
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+    f.client.withdraw_own(&user, &100);
+    assert_eq!(f.client.get_locked_amount(), 800);
+}
+
+#[test]
+fn test_deposit_up_to_cap() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { max_total_deposit: 1000, ..default_options(env) });
+    let user = f.funded_user(2000);
+
+    // Fits entirely.
+    assert_eq!(f.client.deposit_up_to_cap(&user, &600), 600);
+    assert_eq!(f.client.get_locked_amount(), 600);
+
+    // Only the room left under the cap is taken, and the event reports that amount.
+    assert_eq!(f.client.deposit_up_to_cap(&user, &700), 400);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&f.env), (400i128, 1000i128, 2u64).into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_locked_amount(), 1000);
+    assert_eq!(f.token.balance(&user), 1000);
+
+    // A full vault takes nothing.
+    assert_eq!(f.client.try_deposit_up_to_cap(&user, &1), Err(Ok(VaultError::CapExceeded)));
+}
+
+#[test]
+fn test_min_withdrawal_with_final_sweep() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { min_withdrawal: 100, ..default_options(env) });
+    let owner = f.funded_owner(250);
+    f.client.deposit(&f.token_id, &owner, &250);
+    assert_eq!(f.client.get_min_withdrawal(), 100);
+
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &99), Err(Ok(VaultError::BelowMinimumWithdrawal)));
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+
+    // Taking another 100 would leave 50, below the minimum, so the rest comes along with it.
+    assert_eq!(f.client.withdraw(&f.token_id, &f.owner, &100), 0);
+    assert_eq!(f.token.balance(&f.owner), 250);
+
+    // Anything less than the minimum that arrives later can still be swept out, and again nothing is left behind.
+    f.funded_owner(50);
+    f.client.deposit(&f.token_id, &owner, &50);
+    assert_eq!(f.client.withdraw(&f.token_id, &f.owner, &30), 0);
+    assert_eq!(f.token.balance(&f.owner), 300);
+}
+
+#[test]
+fn test_final_withdrawal_absorbs_fee_rounding_residue() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { fee_bps: 333, min_withdrawal: 10, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+
+    // To net 962 after the 3.33% fee the owner needs 962 / 0.9667 = 995.13 gross, rounded down to 995.
+    // That would strand 5 below the minimum, so the withdrawal takes the exact remaining balance instead.
+    assert_eq!(f.client.withdraw(&f.token_id, &f.owner, &995), 0);
+    assert_eq!(f.client.get_locked_amount(), 0);
+    assert_eq!(f.client.get_accrued_fees(), 33);
+    assert_eq!(f.token.balance(&f.owner), 967);
+    assert_eq!(f.token.balance(&f.contract_id), 33);
+}
+
+#[test]
+fn test_sweep_clears_residual_dust() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { fee_bps: 333, ..default_options(env) });
+    let owner = f.funded_owner(1007);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::StillLocked)));
+
+    // Withdraw everything the books show, with the fee taking an odd cut.
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &1000);
+    assert_eq!(f.client.get_locked_amount(), 0);
+
+    // Dust that the books don't know about, like a stray direct transfer, is stuck.
+    f.token.transfer(&owner, &f.contract_id, &7);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &7), Err(Ok(VaultError::InsufficientFunds)));
+
+    assert_eq!(f.client.sweep(&f.owner), 7);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("swept"), f.owner.clone()).into_val(&f.env), 7i128.into_val(&f.env))]
+    );
+    // The 33 fee still waits for the fee collector, and the owner got the rest plus the dust.
+    assert_eq!(f.token.balance(&f.contract_id), 33);
+    assert_eq!(f.client.get_accrued_fees(), 33);
+    assert_eq!(f.client.get_locked_amount(), 0);
+    assert_eq!(f.token.balance(&f.owner), 967 + 7);
+}
+
+#[test]
+fn test_sweep_only_takes_dust() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { vesting_start: 0, vesting_duration: 1_000_000, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+
+    // 0.1% into vesting, a single token can't be withdrawn, so nothing can be swept either.
+    f.warp_to(1000);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &2), Err(Ok(VaultError::NotYetVested)));
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::NotYetVested)));
+
+    // Fully vested, the books still track far more than dust.
+    f.warp_to(1_000_000);
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::VaultNotEmpty)));
+    assert_eq!(f.token.balance(&f.contract_id), 1000);
+}
+
+#[test]
+fn test_sweep_respects_limit_and_requests() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { min_withdrawal: 10, ..default_options(env) });
+    let owner = f.funded_owner(10);
+    f.client.deposit(&f.token_id, &owner, &5);
+    f.warp_to(100);
+
+    // 5 is below the minimum withdrawal, so it counts as dust, but a pending request still has a claim on it.
+    let request_id = f.client.request_withdrawal(&f.owner, &5);
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::VaultNotEmpty)));
+    f.client.fulfill_withdrawal(&request_id);
+    f.client.deposit(&f.token_id, &owner, &5);
+
+    // The sweep counts against the withdrawal limit like any other withdrawal.
+    f.client.set_withdrawal_limit(&4);
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::LimitExceeded)));
+    f.client.set_withdrawal_limit(&0);
+    assert_eq!(f.client.sweep(&f.owner), 5);
+    assert_eq!(f.token.balance(&f.owner), 10);
+}
+
+#[test]
+fn test_depositor_allowlist() {
+    let f = VaultFixture::new(100);
+    let invited = f.funded_user(1000);
+    let stranger = f.funded_user(1000);
+
+    // Off by default: anyone can deposit.
+    assert!(!f.client.is_depositor_allowlist_enabled());
+    f.client.deposit(&f.token_id, &stranger, &100);
+
+    f.client.set_depositor_allowlist(&true);
+    f.client.add_depositor(&invited);
+    assert!(f.client.is_depositor_allowed(&invited));
+    assert!(!f.client.is_depositor_allowed(&stranger));
+
+    // Allowed.
+    assert_eq!(f.client.deposit(&f.token_id, &invited, &100), 200);
+    // Disallowed, on every deposit path.
+    assert_eq!(f.client.try_deposit(&f.token_id, &stranger, &100), Err(Ok(VaultError::DepositorNotAllowed)));
+    assert_eq!(f.client.try_deposit_up_to_cap(&stranger, &100), Err(Ok(VaultError::DepositorNotAllowed)));
+    assert_eq!(f.client.try_deposit_batch(&vec![&f.env, (stranger.clone(), 100i128)]), Err(Ok(VaultError::DepositorNotAllowed)));
+
+    // Removing an address shuts it out again but leaves its balance alone.
+    f.client.remove_depositor(&invited);
+    assert_eq!(f.client.try_deposit(&f.token_id, &invited, &100), Err(Ok(VaultError::DepositorNotAllowed)));
+    assert_eq!(f.client.get_balance(&invited), 100);
+
+    // Turning the allowlist off restores open deposits.
+    f.client.set_depositor_allowlist(&false);
+    assert_eq!(f.client.deposit(&f.token_id, &stranger, &100), 300);
+    assert_eq!(f.client.deposit(&f.token_id, &invited, &100), 400);
+}
+
+#[test]
+fn test_blocklist() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    let sanctioned = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &owner, &500);
+
+    f.client.block_address(&sanctioned);
+    assert!(f.client.is_blocked(&sanctioned));
+    assert!(!f.client.is_blocked(&owner));
+
+    // A blocked address can't deposit.
+    assert_eq!(f.client.try_deposit(&f.token_id, &sanctioned, &100), Err(Ok(VaultError::AddressBlocked)));
+    assert_eq!(f.client.try_deposit_batch(&vec![&f.env, (sanctioned.clone(), 100i128)]), Err(Ok(VaultError::AddressBlocked)));
+
+    // Nor can it receive a withdrawal.
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &sanctioned, &100), Err(Ok(VaultError::AddressBlocked)));
+    assert_eq!(f.client.try_withdraw_batch(&vec![&f.env, (sanctioned.clone(), 100i128)]), Err(Ok(VaultError::AddressBlocked)));
+    assert_eq!(f.token.balance(&sanctioned), 1000);
+
+    // Unblocking restores both.
+    f.client.unblock_address(&sanctioned);
+    assert!(!f.client.is_blocked(&sanctioned));
+    f.client.deposit(&f.token_id, &sanctioned, &100);
+    assert_eq!(f.client.withdraw(&f.token_id, &sanctioned, &200), 400);
+    assert_eq!(f.token.balance(&sanctioned), 1100);
+}
+
+#[test]
+fn test_separate_admin() {
+    let f = VaultFixture::new(100);
+    let admin = Address::generate(&f.env);
+    // Without a separate admin, the owner administers the vault.
+    assert_eq!(f.client.get_admin(), f.owner);
+    f.client.set_admin(&admin);
+    assert_eq!(f.client.get_admin(), admin);
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &500);
+    f.warp_to(100);
+
+    // Only the owner signs: admin-only functions fail.
+    let owner_only = |fn_name: &'static str, args: Vec<Val>| {
+        f.env.mock_auths(&[MockAuth {
+            address: &f.owner,
+            invoke: &MockAuthInvoke { contract: &f.contract_id, fn_name, args, sub_invokes: &[] },
+        }]);
+    };
+    owner_only("pause", vec![&f.env]);
+    assert!(f.client.try_pause().is_err());
+    owner_only("block_address", vec![&f.env, user.to_val()]);
+    assert!(f.client.try_block_address(&user).is_err());
+    owner_only("set_admin", vec![&f.env, f.owner.to_val()]);
+    assert!(f.client.try_set_admin(&f.owner).is_err());
+
+    // Only the admin signs: owner-only functions fail.
+    f.env.mock_auths(&[MockAuth {
+        address: &admin,
+        invoke: &MockAuthInvoke {
+            contract: &f.contract_id,
+            fn_name: "withdraw",
+            args: (&f.token_id, &admin, 100i128).into_val(&f.env),
+            sub_invokes: &[],
+        },
+    }]);
+    assert!(f.client.try_withdraw(&f.token_id, &admin, &100).is_err());
+    assert_eq!(f.client.get_locked_amount(), 500);
+
+    // The admin signing for its own functions works.
+    f.env.mock_auths(&[MockAuth {
+        address: &admin,
+        invoke: &MockAuthInvoke { contract: &f.contract_id, fn_name: "pause", args: ().into_val(&f.env), sub_invokes: &[] },
+    }]);
+    f.client.pause();
+    assert!(f.client.is_paused());
+
+    // A separate admin survives the owner renouncing.
+    f.env.mock_all_auths();
+    f.client.unpause();
+    f.client.renounce_ownership();
+    assert_eq!(f.client.get_admin(), admin);
+    f.client.pause();
+}
+
+#[test]
+fn test_role_change_events() {
+    let f = VaultFixture::new(100);
+    let new_owner = Address::generate(&f.env);
+    let admin = Address::generate(&f.env);
+    let next_admin = Address::generate(&f.env);
+
+    f.client.transfer_ownership(&new_owner);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("own_nom"), f.owner.clone(), new_owner.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+
+    f.client.accept_ownership();
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("own_xfer"), f.owner.clone(), new_owner.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_owner(), new_owner);
+
+    // The first admin change hands the role over from the (new) owner.
+    f.client.set_admin(&admin);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("adm_xfer"), new_owner.clone(), admin.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+    f.client.set_admin(&next_admin);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("adm_xfer"), admin.clone(), next_admin.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+}
+
+#[test]
+fn test_migrate_moves_locked_amount_to_persistent() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+
+    // Write a vault the way the code did before versioning: the locked amount in instance storage,
+    // and no Version or Initialized entries.
+    env.as_contract(&contract_id, || {
+        env.storage().instance().set(&DataKey::Owner, &owner);
+        env.storage().instance().set(&DataKey::TokenId, &token_id);
+        env.storage().instance().set(&DataKey::UnlockTimestamp, &100u64);
+        env.storage().instance().set(&DataKey::LockedAmount, &700i128);
+    });
+    assert_eq!(client.get_locked_amount(), 0);
+    assert!(!client.is_initialized());
+
+    client.migrate();
+    assert_eq!(client.get_locked_amount(), 700);
+    assert_eq!(client.get_stored_version(), VERSION);
+    assert!(client.is_initialized());
+    assert!(!env.as_contract(&contract_id, || env.storage().instance().has(&DataKey::LockedAmount)));
+    assert_eq!(client.try_initialize(&owner, &token_id, &100, &default_options(&env)), Err(Ok(VaultError::AlreadyInitialized)));
+
+    // A second run finds nothing to do.
+    assert_eq!(client.try_migrate(), Err(Ok(VaultError::AlreadyMigrated)));
+}
+
+// Deploys the Stellar Asset Contract for native XLM and creates a classic account holding `balance` stroops.
+// Native XLM has no issuer to mint from, so the account entry is written straight into the test ledger.
+fn native_xlm_account(env: &Env, balance: i64) -> (Address, Address) {
+    use soroban_sdk::xdr::{
+        AccountEntry, AccountEntryExt, AccountId, LedgerEntry, LedgerEntryData, LedgerEntryExt, LedgerKey, LedgerKeyAccount, PublicKey,
+        ScAddress, SequenceNumber, Thresholds, Uint256,
+    };
+    use std::rc::Rc;
+
+    // Asset::Native serializes to its bare XDR discriminant.
+    let native_id = env.deployer().with_stellar_asset(soroban_sdk::Bytes::from_array(env, &[0, 0, 0, 0])).deploy();
+
+    let account_id = AccountId(PublicKey::PublicKeyTypeEd25519(Uint256([7; 32])));
+    let key = LedgerKey::Account(LedgerKeyAccount { account_id: account_id.clone() });
+    let entry = LedgerEntry {
+        last_modified_ledger_seq: 0,
+        data: LedgerEntryData::Account(AccountEntry {
+            account_id: account_id.clone(),
+            balance,
+            seq_num: SequenceNumber(0),
+            num_sub_entries: 0,
+            inflation_dest: None,
+            flags: 0,
+            home_domain: Default::default(),
+            thresholds: Thresholds([1, 0, 0, 0]),
+            signers: Default::default(),
+            ext: AccountEntryExt::V0,
+        }),
+        ext: LedgerEntryExt::V0,
+    };
+    env.host()
+        .with_mut_storage(|storage| storage.put(&Rc::new(key), &Rc::new(entry), None, &env.host().budget_cloned()))
+        .unwrap();
+    let account = Address::try_from_val(env, &ScAddress::Account(account_id)).unwrap();
+    (native_id, account)
+}
+
+#[test]
+fn test_native_xlm_round_trip() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (native_id, owner) = native_xlm_account(&env, 1_000_000_000);
+    let xlm = TokenClient::new(&env, &native_id);
+    assert_eq!(xlm.symbol(), soroban_sdk::String::from_str(&env, "native"));
+
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    client.initialize(&owner, &native_id, &100, &default_options(&env));
+
+    // No approval step: the depositor's auth covers the SAC transfer, exactly as for any other token.
+    assert_eq!(client.deposit(&native_id, &owner, &250_000_000), 250_000_000);
+    assert_eq!(xlm.balance(&owner), 750_000_000);
+    assert_eq!(xlm.balance(&contract_id), 250_000_000);
+
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.withdraw(&native_id, &owner, &250_000_000), 0);
+    assert_eq!(xlm.balance(&owner), 1_000_000_000);
+    assert_eq!(xlm.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_unlock_curve() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { vesting_start: 1000, vesting_duration: 1000, ..default_options(env) });
+    let owner = f.funded_owner(10_000);
+    f.client.deposit(&f.token_id, &owner, &10_000);
+
+    // Half the vault unlocks a quarter of the way in, then another tenth by the halfway point.
+    let curve = vec![&f.env, (2500u32, 5000u32), (5000, 6000)];
+    f.client.set_unlock_curve(&curve);
+    assert_eq!(f.client.get_unlock_curve(), curve);
+
+    // At the defined breakpoints.
+    f.warp_to(1250);
+    assert_eq!(f.client.withdrawable_now(), 5000);
+    f.warp_to(1500);
+    assert_eq!(f.client.withdrawable_now(), 6000);
+    // Interpolated between the two points, from the implicit origin, and towards the implicit end.
+    f.warp_to(1375);
+    assert_eq!(f.client.withdrawable_now(), 5500);
+    f.warp_to(1100);
+    assert_eq!(f.client.withdrawable_now(), 2000);
+    f.warp_to(1750);
+    assert_eq!(f.client.withdrawable_now(), 8000);
+    f.warp_to(2000);
+    assert_eq!(f.client.withdrawable_now(), 10_000);
+
+    // Once vesting has begun the curve can't change.
+    assert_eq!(f.client.try_set_unlock_curve(&vec![&f.env, (0u32, 10_000u32)]), Err(Ok(VaultError::VestingStarted)));
+}
+
+#[test]
+fn test_unlock_curve_validation() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { vesting_start: 1000, vesting_duration: 1000, ..default_options(env) });
+    let invalid = [
+        vec![&f.env],
+        vec![&f.env, (10_001u32, 5000u32)],
+        vec![&f.env, (5000u32, 10_001u32)],
+        vec![&f.env, (5000u32, 5000u32), (5000, 6000)],
+        vec![&f.env, (2500u32, 6000u32), (5000, 5000)],
+    ];
+    for points in invalid.iter() {
+        assert_eq!(f.client.try_set_unlock_curve(points), Err(Ok(VaultError::InvalidUnlockCurve)));
+    }
+    assert_eq!(f.client.get_unlock_curve(), vec![&f.env]);
+
+    // A vault without a vesting window has nothing for a curve to shape.
+    let g = VaultFixture::new(100);
+    assert_eq!(g.client.try_set_unlock_curve(&vec![&g.env, (5000u32, 5000u32)]), Err(Ok(VaultError::InvalidUnlockCurve)));
+}
+
+#[test]
+fn test_withdraw_notifies_receiver() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { notify_contract: Some(env.register(WithdrawReceiver, ())), ..default_options(env) });
+    let receiver = WithdrawReceiverClient::new(&f.env, &f.client.get_notify_contract().unwrap());
+    let owner = f.funded_owner(1000);
+    let to = Address::generate(&f.env);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+
+    f.client.withdraw(&f.token_id, &to, &300);
+    assert_eq!(receiver.last(), Some((f.contract_id.clone(), to.clone(), 300)));
+
+    // A failing callback doesn't block the withdrawal of a lenient vault.
+    receiver.set_fail(&true);
+    assert_eq!(f.client.withdraw(&f.token_id, &to, &200), 500);
+    assert_eq!(f.token.balance(&to), 500);
+    assert_eq!(receiver.last(), Some((f.contract_id.clone(), to.clone(), 300)));
+}
+
+#[test]
+fn test_strict_notify_reverts_withdrawal() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions {
+        notify_contract: Some(env.register(WithdrawReceiver, ())),
+        notify_strict: true,
+        ..default_options(env)
+    });
+    let receiver = WithdrawReceiverClient::new(&f.env, &f.client.get_notify_contract().unwrap());
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+
+    receiver.set_fail(&true);
+    assert!(f.client.try_withdraw(&f.token_id, &f.owner, &200).is_err());
+    assert_eq!(f.client.get_locked_amount(), 1000);
+    assert_eq!(f.token.balance(&f.owner), 0);
+
+    receiver.set_fail(&false);
+    f.client.withdraw(&f.token_id, &f.owner, &200);
+    assert_eq!(receiver.last(), Some((f.contract_id.clone(), f.owner.clone(), 200)));
+}
+
+#[test]
+fn test_deposit_cooldown() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { deposit_cooldown: 60, ..default_options(env) });
+    assert_eq!(f.client.get_deposit_cooldown(), 60);
+    let user = f.funded_user(1000);
+    let other = f.funded_user(1000);
+
+    f.warp_to(10);
+    f.client.deposit(&f.token_id, &user, &100);
+    // An immediate second deposit is refused, through any deposit path.
+    assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::DepositTooSoon)));
+    assert_eq!(f.client.try_deposit_batch(&vec![&f.env, (user.clone(), 100i128)]), Err(Ok(VaultError::DepositTooSoon)));
+    // The cooldown is per depositor.
+    f.client.deposit(&f.token_id, &other, &100);
+
+    f.warp_to(69);
+    assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::DepositTooSoon)));
+    f.warp_to(70);
+    assert_eq!(f.client.deposit(&f.token_id, &user, &100), 300);
+    assert_eq!(f.client.get_balance(&user), 200);
+}
+
+#[test]
+fn test_can_withdraw_owner() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert!(!f.client.can_withdraw(&f.owner, &500));
+
+    f.warp_to(100);
+    assert!(f.client.can_withdraw(&f.owner, &1000));
+    assert!(!f.client.can_withdraw(&f.owner, &1001));
+    assert!(!f.client.can_withdraw(&f.owner, &0));
+    f.client.set_withdrawal_limit(&400);
+    assert!(f.client.can_withdraw(&f.owner, &400));
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &401), Err(Ok(VaultError::LimitExceeded)));
+    // The limit doesn't cover withdraw_own, through which the owner can still take out their own balance.
+    assert!(f.client.can_withdraw(&f.owner, &401));
+    // An address with no role can't withdraw anything.
+    assert!(!f.client.can_withdraw(&Address::generate(&f.env), &1));
+    // Asking records nothing.
+    f.client.withdraw(&f.token_id, &f.owner, &400);
+}
+
+#[test]
+fn test_can_withdraw_depositor_with_own_unlock() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    f.client.deposit_with_unlock(&user, &600, &200);
+
+    f.warp_to(150);
+    assert!(!f.client.can_withdraw(&user, &600));
+    f.warp_to(200);
+    assert!(f.client.can_withdraw(&user, &600));
+    assert!(!f.client.can_withdraw(&user, &601));
+    f.client.block_address(&user);
+    assert!(!f.client.can_withdraw(&user, &600));
+}
+
+#[test]
+fn test_can_withdraw_beneficiary() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { inactivity_window: 1000, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    let beneficiary = Address::generate(&f.env);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+    f.client.set_beneficiary(&beneficiary);
+
+    // Within the owner's inactivity window the beneficiary must wait.
+    f.warp_to(1099);
+    assert!(!f.client.can_withdraw(&beneficiary, &500));
+    f.warp_to(1100);
+    assert!(f.client.can_withdraw(&beneficiary, &500));
+    assert!(!f.client.can_withdraw(&beneficiary, &1001));
+    f.client.beneficiary_withdraw(&beneficiary, &500);
+}
+
+#[test]
+fn test_initialize_emits_init_event() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("init"), owner.clone(), token_id.clone()).into_val(&env), 100u64.into_val(&env))]
+    );
+
+    // A rejected re-initialization emits nothing.
+    assert_eq!(client.try_initialize(&owner, &token_id, &200, &default_options(&env)), Err(Ok(VaultError::AlreadyInitialized)));
+    assert_eq!(vault_events(&env, &contract_id), vec![&env]);
+}
+
+#[test]
+fn test_deposit_and_extend_pushes_unlock_out() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+
+    // A late deposit, with less than the minimum term left, pushes the unlock out.
+    f.warp_to(80);
+    assert_eq!(f.client.deposit_and_extend(&owner, &400, &50), 400);
+    assert_eq!(f.client.get_unlock_time(), 130);
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &400), Err(Ok(VaultError::StillLocked)));
+    f.warp_to(130);
+    f.client.withdraw(&f.token_id, &f.owner, &400);
+}
+
+#[test]
+fn test_deposit_and_extend_never_shortens() {
+    let f = VaultFixture::new(1000);
+    let owner = f.funded_owner(1000);
+
+    // An early deposit leaves a longer existing unlock alone.
+    f.warp_to(10);
+    assert_eq!(f.client.deposit_and_extend(&owner, &400, &50), 400);
+    assert_eq!(f.client.get_unlock_time(), 1000);
+
+    assert_eq!(f.client.try_deposit_and_extend(&owner, &100, &(MAX_LOCK_DURATION + 1)), Err(Ok(VaultError::LockTooLong)));
+}
+
+#[test]
+fn test_deposit_and_extend_only_locks_own_share() {
+    let f = VaultFixture::new(100);
+    let alice = f.funded_user(1000);
+    let mallory = f.funded_user(1);
+    f.client.deposit(&f.token_id, &alice, &500);
+
+    // A third party's term only binds their own deposit, not the vault or anyone else's share.
+    assert_eq!(f.client.deposit_and_extend(&mallory, &1, &(MAX_LOCK_DURATION - 100)), 501);
+    assert_eq!(f.client.get_unlock_time(), 100);
+    assert_eq!(f.client.get_deposit_unlock(&mallory), Some(MAX_LOCK_DURATION - 100));
+    assert_eq!(f.client.get_deposit_unlock(&alice), None);
+
+    f.warp_to(100);
+    f.client.withdraw_own(&alice, &500);
+    assert_eq!(f.client.try_withdraw_own(&mallory, &1), Err(Ok(VaultError::StillLocked)));
+}
+
+#[test]
+fn test_fee_on_transfer_deposit_is_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let token_id = env.register(FeeOnTransferToken, ());
+    let token = FeeOnTransferTokenClient::new(&env, &token_id);
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    token.mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+
+    // The vault would only receive 990, so crediting 1000 is refused and nothing changes.
+    assert_eq!(client.try_deposit(&token_id, &user, &1000), Err(Ok(VaultError::TransferAmountMismatch)));
+    assert_eq!(client.get_locked_amount(), 0);
+    assert_eq!(token.balance(&user), 1000);
+    // Amounts too small to be charged a fee still go through.
+    assert_eq!(client.deposit(&token_id, &user, &99), 99);
+}
+
+#[test]
+fn test_credit_received_books_actual_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let token_id = env.register(FeeOnTransferToken, ());
+    let token = FeeOnTransferTokenClient::new(&env, &token_id);
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let user = Address::generate(&env);
+    token.mint(&user, &1000);
+    client.initialize(&owner, &token_id, &100, &VaultOptions { credit_received: true, ..default_options(&env) });
+
+    // 1% of the 1000 sent is burned on the way, so the depositor is credited the 990 that arrived.
+    assert_eq!(client.deposit(&token_id, &user, &1000), 990);
+    assert_eq!(
+        vault_events(&env, &contract_id),
+        vec![&env, (contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&env), (990i128, 990i128, 1u64).into_val(&env))]
+    );
+    assert_eq!(client.get_balance(&user), 990);
+    assert_eq!(token.balance(&contract_id), 990);
+}
+
+#[test]
+fn test_close_requires_empty_vault() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &400);
+    assert_eq!(f.client.try_close(), Err(Ok(VaultError::VaultNotEmpty)));
+
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &300);
+    assert_eq!(f.client.try_close(), Err(Ok(VaultError::VaultNotEmpty)));
+    assert_eq!(f.client.get_locked_amount(), 100);
+
+    // A balance of another whitelisted token keeps the vault open too, until it's withdrawn.
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+    let other_token_id = create_token(&f.env);
+    StellarAssetClient::new(&f.env, &other_token_id).mint(&f.owner, &500);
+    f.client.add_allowed_token(&other_token_id);
+    f.client.deposit(&other_token_id, &f.owner, &500);
+    assert_eq!(f.client.try_close(), Err(Ok(VaultError::VaultNotEmpty)));
+    f.client.withdraw(&other_token_id, &f.owner, &200);
+    assert_eq!(f.client.try_close(), Err(Ok(VaultError::VaultNotEmpty)));
+    f.client.withdraw(&other_token_id, &f.owner, &300);
+    assert!(f.client.close());
+}
+
+#[test]
+fn test_close_in_batches() {
+    let f = VaultFixture::new(100);
+    let depositors = CLOSE_BATCH_SIZE + 5;
+    for _ in 0..depositors {
+        let user = f.funded_user(10);
+        f.client.deposit(&f.token_id, &user, &10);
+        f.warp_to(100);
+        f.client.withdraw_own(&user, &10);
+    }
+
+    // The first call only gets through a batch of depositor records and leaves the vault open.
+    assert!(!f.client.close());
+    assert!(f.client.is_initialized());
+    f.env.as_contract(&f.contract_id, || {
+        let exited: Vec<Address> = f.env.storage().persistent().get(&DataKeyExt::ExitedDepositors).unwrap();
+        assert_eq!(exited.len(), 5);
+    });
+    assert!(f.client.close());
+    assert!(!f.client.is_initialized());
+}
+
+#[test]
+fn test_close_clears_storage() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &400);
+    // A depositor who leaves before the close still has records the close must find.
+    let leaver = f.funded_user(300);
+    f.client.deposit(&f.token_id, &leaver, &300);
+    f.warp_to(100);
+    f.client.snapshot();
+    f.client.withdraw_own(&leaver, &300);
+    assert_eq!(f.client.get_depositors(), vec![&f.env, user.clone()]);
+    f.client.withdraw_own(&user, &400);
+
+    f.client.close();
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("closed"), f.owner.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+    assert_eq!(f.client.try_get_state(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(f.client.try_get_owner(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(f.client.try_get_token_id(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(f.client.try_get_unlock_time(), Err(Ok(VaultError::NotInitialized)));
+    assert_eq!(f.client.try_get_stored_version(), Err(Ok(VaultError::NotInitialized)));
+    assert!(!f.client.is_initialized());
+    assert_eq!(f.client.get_balance(&user), 0);
+    assert_eq!(f.client.get_depositors(), vec![&f.env]);
+    assert_eq!(f.client.get_snapshot(&leaver), None);
+    f.env.as_contract(&f.contract_id, || {
+        for depositor in [&user, &leaver] {
+            assert!(!f.env.storage().persistent().has(&DataKey::Balance(depositor.clone())));
+            assert!(!f.env.storage().persistent().has(&DataKey::RewardCheckpoint(depositor.clone())));
+        }
+        assert!(!f.env.storage().persistent().has(&DataKeyExt::ExitedDepositors));
+    });
+    assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::NotInitialized)));
+
+    // A closed vault stays closed.
+    assert_eq!(
+        f.client.try_initialize(&user, &f.token_id, &200, &default_options(&f.env)),
+        Err(Ok(VaultError::AlreadyInitialized))
+    );
+}
+
+#[test]
+fn test_distribute_pays_split_with_remainder() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions {
+        payout_split: vec![env, (Address::generate(env), 3333u32), (Address::generate(env), 3333), (Address::generate(env), 3334)],
+        ..default_options(env)
+    });
+    let split = f.client.get_payout_split();
+    let (a, b, c) = (split.get(0).unwrap().0, split.get(1).unwrap().0, split.get(2).unwrap().0);
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert_eq!(f.client.try_distribute(&100), Err(Ok(VaultError::StillLocked)));
+
+    f.warp_to(100);
+    // Each share rounds down to 33; the last recipient picks up the leftover unit.
+    f.client.distribute(&100);
+    assert_eq!((f.token.balance(&a), f.token.balance(&b), f.token.balance(&c)), (33, 33, 34));
+    assert_eq!(f.client.get_locked_amount(), 900);
+
+    // Shares that round down to nothing are skipped.
+    f.client.distribute(&2);
+    assert_eq!((f.token.balance(&a), f.token.balance(&b), f.token.balance(&c)), (33, 33, 36));
+    assert_eq!(f.client.get_locked_amount(), 898);
+}
+
+#[test]
+fn test_payout_split_validation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let owner = Address::generate(&env);
+    let token_id = create_token(&env);
+
+    let short = vec![&env, (Address::generate(&env), 5000u32), (Address::generate(&env), 4999)];
+    let over = vec![&env, (Address::generate(&env), 5000u32), (Address::generate(&env), 5001)];
+    for split in [short, over] {
+        assert_eq!(
+            client.try_initialize(&owner, &token_id, &100, &VaultOptions { payout_split: split, ..default_options(&env) }),
+            Err(Ok(VaultError::InvalidBasisPoints))
+        );
+    }
+
+    // Without a split there is nothing to distribute to.
+    client.initialize(&owner, &token_id, &100, &default_options(&env));
+    assert_eq!(client.try_distribute(&100), Err(Ok(VaultError::NoPayoutSplit)));
+}
+
+#[test]
+fn test_lifetime_totals() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    let other = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &owner, &300);
+    f.client.deposit_batch(&vec![&f.env, (owner.clone(), 100i128), (other.clone(), 200i128)]);
+    assert_eq!(f.client.get_total_deposited(), 600);
+    assert_eq!(f.client.get_total_withdrawn(), 0);
+
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &250);
+    f.client.withdraw_own(&other, &200);
+    f.client.withdraw(&f.token_id, &f.owner, &150);
+    assert_eq!(f.client.get_locked_amount(), 0);
+
+    // The totals keep their history once the vault is empty, and grow again with new activity.
+    assert_eq!(f.client.get_total_deposited(), 600);
+    assert_eq!(f.client.get_total_withdrawn(), 600);
+    f.client.deposit(&f.token_id, &owner, &50);
+    assert_eq!(f.client.get_total_deposited(), 650);
+}
+
+#[test]
+fn test_deposit_for_credits_beneficiary() {
+    let f = VaultFixture::new(100);
+    let sponsor = f.funded_user(1000);
+    let beneficiary = Address::generate(&f.env);
+
+    assert_eq!(f.client.deposit_for(&sponsor, &beneficiary, &400), 400);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![
+            &f.env,
+            (f.contract_id.clone(), (symbol_short!("dep_for"), sponsor.clone(), beneficiary.clone()).into_val(&f.env), (400i128, 400i128, 1u64).into_val(&f.env))
+        ]
+    );
+    // The sponsor paid, but the balance is the beneficiary's.
+    assert_eq!(f.token.balance(&sponsor), 600);
+    assert_eq!(f.client.get_balance(&beneficiary), 400);
+    assert_eq!(f.client.get_balance(&sponsor), 0);
+    assert_eq!(f.client.get_depositors(), vec![&f.env, beneficiary.clone()]);
+
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw_own(&sponsor, &400), Err(Ok(VaultError::InsufficientFunds)));
+    f.client.withdraw_own(&beneficiary, &400);
+    assert_eq!(f.token.balance(&beneficiary), 400);
+}
+
+#[test]
+fn test_force_unlock_when_enabled() {
+    let f = VaultFixture::with_options(1000, |env| VaultOptions { allow_force_unlock: true, ..default_options(env) });
+    let owner = f.funded_owner(500);
+    f.client.deposit(&f.token_id, &owner, &500);
+    f.warp_to(200);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &500), Err(Ok(VaultError::StillLocked)));
+
+    f.client.force_unlock();
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("f_unlock"), f.owner.clone()).into_val(&f.env), (1000u64, 200u64).into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_unlock_time(), 200);
+    f.client.withdraw(&f.token_id, &f.owner, &500);
+    assert_eq!(f.token.balance(&f.owner), 500);
+
+    // Once open there is nothing left to force.
+    assert_eq!(f.client.try_force_unlock(), Err(Ok(VaultError::AlreadyUnlocked)));
+}
+
+#[test]
+fn test_force_unlock_behind_action_delay() {
+    let f = VaultFixture::with_options(10_000, |env| VaultOptions { allow_force_unlock: true, action_delay: 3600, ..default_options(env) });
+    let action_id: BytesN<32> = f.env.crypto().sha256(&Bytes::from_slice(&f.env, b"force_unlock")).to_bytes();
+
+    // Without a queued action the force unlock can't happen at all, and then only once the delay has run.
+    assert_eq!(f.client.try_force_unlock(), Err(Ok(VaultError::ActionNotQueued)));
+    f.client.queue_action(&action_id, &3600);
+    f.warp_to(3599);
+    assert_eq!(f.client.try_force_unlock(), Err(Ok(VaultError::ActionNotReady)));
+    assert_eq!(f.client.get_unlock_time(), 10_000);
+
+    f.warp_to(3600);
+    f.client.force_unlock();
+    assert_eq!(f.client.get_unlock_time(), 3600);
+    assert_eq!(f.client.get_queued_action(&action_id), None);
+}
+
+#[test]
+fn test_force_unlock_disabled_by_default() {
+    let f = VaultFixture::new(1000);
+    f.warp_to(200);
+    assert_eq!(f.client.try_force_unlock(), Err(Ok(VaultError::ForceUnlockDisabled)));
+    assert_eq!(f.client.get_unlock_time(), 1000);
+}
+
+#[test]
+fn test_withdraw_own_emits_w_own_event() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(500);
+    f.client.deposit(&f.token_id, &user, &500);
+    f.warp_to(100);
+
+    f.client.withdraw_own(&user, &200);
+    // The per-depositor flow has its own event, carrying the depositor's remaining balance rather than the vault total.
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("w_own"), user.clone()).into_val(&f.env), (200i128, 300i128).into_val(&f.env))]
+    );
+}
+
+#[test]
+fn test_withdrawable_for() {
+    let f = VaultFixture::new(100);
+    let alice = f.funded_user(500);
+    let bob = f.funded_user(300);
+    f.client.deposit(&f.token_id, &alice, &500);
+    f.client.deposit(&f.token_id, &bob, &300);
+
+    // Nothing is withdrawable before the unlock.
+    assert_eq!(f.client.withdrawable_for(&alice), 0);
+
+    // After it, each depositor can take their whole balance.
+    f.warp_to(100);
+    assert_eq!(f.client.withdrawable_for(&alice), 500);
+    assert_eq!(f.client.withdrawable_for(&bob), 300);
+    assert!(f.client.can_withdraw(&alice, &500));
+
+    // The owner can't draw the vault down below what depositors are owed, so their balances stay withdrawable.
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &400), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(f.client.withdrawable_for(&alice), 500);
+    assert_eq!(f.client.try_withdraw_own(&alice, &501), Err(Ok(VaultError::InsufficientFunds)));
+    f.client.withdraw_own(&alice, &500);
+    assert_eq!(f.client.withdrawable_for(&alice), 0);
+    assert_eq!(f.client.withdrawable_for(&bob), 300);
+}
+
+#[test]
+fn test_withdrawable_for_partially_vested() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { vesting_start: 100, vesting_duration: 1000, ..default_options(env) });
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &1000);
+
+    // A quarter of the way through vesting, a quarter of the depositor's balance can come out.
+    f.warp_to(350);
+    assert_eq!(f.client.withdrawable_for(&user), 250);
+    assert!(f.client.can_withdraw(&user, &250));
+    assert_eq!(f.client.try_withdraw_own(&user, &251), Err(Ok(VaultError::NotYetVested)));
+    f.client.withdraw_own(&user, &250);
+    assert_eq!(f.client.withdrawable_for(&user), 0);
+
+    // What they've taken counts against what vests later.
+    f.warp_to(600);
+    assert_eq!(f.client.withdrawable_for(&user), 250);
+    f.warp_to(1100);
+    assert_eq!(f.client.withdrawable_for(&user), 750);
+    f.client.withdraw_own(&user, &750);
+    assert_eq!(f.token.balance(&user), 1000);
+}
+
+#[test]
+fn test_withdrawable_for_respects_personal_unlock() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(500);
+    f.client.deposit_with_unlock(&user, &500, &300);
+
+    f.warp_to(100);
+    assert_eq!(f.client.withdrawable_for(&user), 0);
+    f.warp_to(300);
+    assert_eq!(f.client.withdrawable_for(&user), 500);
+}
+
+#[test]
+fn test_recovery_withdraw_after_owner_inactive() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { recovery_address: Some(Address::generate(env)), recovery_delay: 1000, ..default_options(env) });
+    let recovery = f.client.get_recovery_address().unwrap();
+    let owner = f.funded_owner(500);
+    f.client.deposit(&f.token_id, &owner, &500);
+
+    // The owner's last action was initialize at time 0, so recovery must wait until 1000.
+    f.warp_to(999);
+    assert_eq!(f.client.try_recovery_withdraw(&recovery, &500), Err(Ok(VaultError::OwnerStillActive)));
+    assert!(!f.client.can_withdraw(&recovery, &500));
+
+    f.warp_to(1000);
+    assert!(f.client.can_withdraw(&recovery, &500));
+    f.client.recovery_withdraw(&recovery, &500);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("recov_wd"), recovery.clone()).into_val(&f.env), (500i128, 0i128).into_val(&f.env))]
+    );
+    assert_eq!(f.token.balance(&recovery), 500);
+    assert_eq!(f.client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_recovery_withdraw_waits_for_owner_silence() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { recovery_address: Some(Address::generate(env)), recovery_delay: 1000, ..default_options(env) });
+    let recovery = f.client.get_recovery_address().unwrap();
+    let owner = f.funded_owner(500);
+    f.client.deposit(&f.token_id, &owner, &500);
+
+    // Any authorized owner call restarts the delay.
+    f.warp_to(900);
+    f.client.set_beneficiary(&Address::generate(&f.env));
+    f.warp_to(1500);
+    assert_eq!(f.client.try_recovery_withdraw(&recovery, &500), Err(Ok(VaultError::OwnerStillActive)));
+    f.warp_to(1900);
+    f.client.recovery_withdraw(&recovery, &200);
+    assert_eq!(f.token.balance(&recovery), 200);
+}
+
+#[test]
+fn test_recovery_withdraw_requires_recovery_address() {
+    let f = VaultFixture::new(100);
+    f.warp_to(10_000);
+    assert_eq!(f.client.try_recovery_withdraw(&f.owner, &1), Err(Ok(VaultError::NoRecoveryAddress)));
+}
+
+#[test]
+fn test_deposit_overflow_moves_no_tokens() {
+    for credit_received in [false, true] {
+        let f = VaultFixture::with_options(100, |env| VaultOptions { credit_received, ..default_options(env) });
+        let whale = f.funded_user(i128::MAX - 10);
+        let user = f.funded_user(100);
+        f.client.deposit(&f.token_id, &whale, &(i128::MAX - 10));
+
+        // The locked total can't take another 100, so the deposit is refused before the token is ever called.
+        assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::Overflow)));
+        assert_eq!(f.token.balance(&user), 100);
+        assert_eq!(f.token.balance(&f.contract_id), i128::MAX - 10);
+        assert_eq!(f.client.get_locked_amount(), i128::MAX - 10);
+        assert_eq!(f.client.get_balance(&user), 0);
+        assert_eq!(f.client.get_depositors(), vec![&f.env, whale.clone()]);
+    }
+}
+
+#[test]
+fn test_get_config_matches_initialize() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register(VaultContract, ());
+    let client = VaultContractClient::new(&env, &contract_id);
+    let token_id = create_token(&env);
+    let options = VaultOptions {
+        vesting_start: 1000,
+        vesting_duration: 500,
+        cliff_timestamp: 1200,
+        penalty_bps: 300,
+        fee_bps: 50,
+        deposit_fee_bps: 125,
+        deposit_fee_retained: true,
+        max_total_deposit: 1_000_000,
+        min_deposit: 10,
+        min_withdrawal: 5,
+        deposit_cooldown: 60,
+        inactivity_window: 7200,
+        recovery_delay: 86_400,
+        recovery_address: Some(Address::generate(&env)),
+        reward_rate: 7,
+        grace_period: 30,
+        withdrawal_destination: Some(Address::generate(&env)),
+        credit_received: true,
+        allow_force_unlock: true,
+        balance_events: true,
+        ..default_options(&env)
+    };
+    client.initialize(&Address::generate(&env), &token_id, &1000, &options);
+
+    let config = client.get_config();
+    assert_eq!(config.token_id, token_id);
+    assert_eq!(config.unlock_timestamp, 1000);
+    assert_eq!(config.grace_period, 30);
+    assert_eq!(config.vesting_start, 1000);
+    assert_eq!(config.vesting_duration, 500);
+    assert_eq!(config.cliff_timestamp, 1200);
+    assert_eq!(config.penalty_bps, 300);
+    assert_eq!(config.penalty_recipient, options.penalty_recipient);
+    assert_eq!(config.fee_bps, 50);
+    assert_eq!(config.fee_collector, options.fee_collector);
+    assert_eq!(config.deposit_fee_bps, 125);
+    assert!(config.deposit_fee_retained);
+    assert_eq!(config.max_total_deposit, 1_000_000);
+    assert_eq!(config.min_deposit, 10);
+    assert_eq!(config.min_withdrawal, 5);
+    assert_eq!(config.deposit_cooldown, 60);
+    assert_eq!(config.inactivity_window, 7200);
+    assert_eq!(config.recovery_delay, 86_400);
+    assert_eq!(config.reward_rate, 7);
+    assert_eq!(config.withdrawal_destination, options.withdrawal_destination);
+    assert!(config.credit_received);
+    assert!(config.allow_force_unlock);
+    assert!(config.balance_events);
+
+    // The defaults read back as well.
+    let defaults = VaultFixture::new(100).client.get_config();
+    assert_eq!(defaults.fee_bps, 0);
+    assert_eq!(defaults.deposit_fee_bps, 0);
+    assert!(!defaults.deposit_fee_retained);
+    assert!(!defaults.balance_events);
+}
+
+#[test]
+fn test_nft_lock_and_withdraw() {
+    let f = VaultFixture::new(100);
+    let nft_id = f.env.register(MockNft, ());
+    let nft = MockNftClient::new(&f.env, &nft_id);
+    let user = Address::generate(&f.env);
+    nft.mint(&user, &7);
+    nft.mint(&user, &8);
+
+    f.client.deposit_nft(&user, &nft_id, &7);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("nft_dep"), user.clone(), nft_id.clone()).into_val(&f.env), 7u128.into_val(&f.env))]
+    );
+    assert_eq!(nft.owner_of(&7), f.contract_id);
+    assert!(f.client.is_nft_locked(&nft_id, &7));
+    assert!(!f.client.is_nft_locked(&nft_id, &8));
+
+    // The NFT is held to the same unlock timestamp as fungible deposits.
+    assert_eq!(f.client.try_withdraw_nft(&f.owner, &nft_id, &7), Err(Ok(VaultError::StillLocked)));
+    // A vault holding an NFT isn't empty.
+    assert_eq!(f.client.try_close(), Err(Ok(VaultError::VaultNotEmpty)));
+
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw_nft(&f.owner, &nft_id, &8), Err(Ok(VaultError::NftNotLocked)));
+    // The NFT is the depositor's, so the owner signing alone can't take it.
+    let owner_only = MockAuth {
+        address: &f.owner,
+        invoke: &MockAuthInvoke {
+            contract: &f.contract_id,
+            fn_name: "withdraw_nft",
+            args: (f.owner.clone(), nft_id.clone(), 7u128).into_val(&f.env),
+            sub_invokes: &[],
+        },
+    };
+    assert!(f.client.mock_auths(&[owner_only]).try_withdraw_nft(&f.owner, &nft_id, &7).is_err());
+    f.client.withdraw_nft(&user, &nft_id, &7);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("nft_wd"), user.clone(), nft_id.clone()).into_val(&f.env), 7u128.into_val(&f.env))]
+    );
+    assert_eq!(nft.owner_of(&7), user);
+    assert!(!f.client.is_nft_locked(&nft_id, &7));
+    assert_eq!(f.client.try_withdraw_nft(&f.owner, &nft_id, &7), Err(Ok(VaultError::NftNotLocked)));
+
+    // An NFT the owner deposited comes out through the owner.
+    nft.mint(&f.owner, &9);
+    f.client.deposit_nft(&f.owner, &nft_id, &9);
+    f.client.withdraw_nft(&f.owner, &nft_id, &9);
+    assert_eq!(nft.owner_of(&9), f.owner);
+}
+
+#[test]
+fn test_auto_extend_on_deposit() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { auto_extend_seconds: 1000, ..default_options(env) });
+    let user = f.funded_user(1000);
+
+    // Each deposit renews the rolling term from its own time.
+    f.warp_to(50);
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.get_unlock_time(), 1050);
+    f.warp_to(400);
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.get_unlock_time(), 1400);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &200), Err(Ok(VaultError::StillLocked)));
+
+    // A later unlock set by the owner is never shortened.
+    f.client.extend_lock(&5000);
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.get_unlock_time(), 5000);
+    assert_eq!(f.client.get_config().auto_extend_seconds, 1000);
+}
+
+#[test]
+fn test_auto_extend_disabled_by_default() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    f.warp_to(50);
+    f.client.deposit(&f.token_id, &owner, &100);
+    assert_eq!(f.client.get_unlock_time(), 100);
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+}
+
+#[test]
+fn test_ttl_remaining() {
+    let f = VaultFixture::new(100);
+    let instance_ttl = || f.env.as_contract(&f.contract_id, || f.env.storage().instance().get_ttl());
+    assert_eq!(f.client.ttl_remaining(), INSTANCE_BUMP_AMOUNT);
+    assert_eq!(f.client.ttl_remaining(), instance_ttl());
+
+    // The figure counts down with the ledger sequence while the vault sits idle.
+    let sequence = f.env.ledger().sequence();
+    f.env.ledger().set_sequence_number(sequence + 1000);
+    assert_eq!(f.client.ttl_remaining(), INSTANCE_BUMP_AMOUNT - 1000);
+    assert_eq!(f.client.ttl_remaining(), instance_ttl());
+
+    // Once under the threshold, the next state change tops it back up.
+    f.env.ledger().set_sequence_number(sequence + DAY_IN_LEDGERS + 1);
+    let user = f.funded_user(100);
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.ttl_remaining(), INSTANCE_BUMP_AMOUNT);
+    assert_eq!(f.client.ttl_remaining(), instance_ttl());
+}
+
+// Sets up a funded, unlocked vault whose withdrawals need 2 of 3 signers, returning the fixture and the signers.
+fn multisig_vault() -> (VaultFixture, Vec<Address>) {
+    let f = VaultFixture::with_options(100, |env| VaultOptions {
+        signers: vec![env, Address::generate(env), Address::generate(env), Address::generate(env)],
+        signer_threshold: 2,
+        ..default_options(env)
+    });
+    let signers = f.client.get_signers().0;
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+    (f, signers)
+}
+
+#[test]
+fn test_multisig_withdrawal_executes_at_threshold() {
+    let (f, signers) = multisig_vault();
+    let to = Address::generate(&f.env);
+
+    let proposal_id = f.client.propose_withdrawal(&signers.get(0).unwrap(), &to, &400);
+    assert_eq!(proposal_id, 1);
+    assert!(f.client.approve_withdrawal(&signers.get(2).unwrap(), &proposal_id));
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![
+            &f.env,
+            (f.contract_id.clone(), (symbol_short!("ms_appr"), signers.get(2).unwrap()).into_val(&f.env), 1u64.into_val(&f.env)),
+            (f.contract_id.clone(), (symbol_short!("ms_exec"), to.clone()).into_val(&f.env), (400i128, 600i128, 1u64).into_val(&f.env))
+        ]
+    );
+    assert_eq!(f.token.balance(&to), 400);
+    assert_eq!(f.client.get_locked_amount(), 600);
+    // An executed proposal is gone and can't run twice.
+    assert_eq!(f.client.get_proposal(&proposal_id), None);
+    assert_eq!(f.client.try_approve_withdrawal(&signers.get(1).unwrap(), &proposal_id), Err(Ok(VaultError::ProposalNotFound)));
+    f.env.as_contract(&f.contract_id, || {
+        let open: Vec<u64> = f.env.storage().persistent().get(&DataKeyExt::OpenProposals).unwrap();
+        assert_eq!(open.len(), 0);
+    });
+}
+
+#[test]
+fn test_multisig_withdrawal_pending_below_threshold() {
+    let (f, signers) = multisig_vault();
+    let to = Address::generate(&f.env);
+
+    let proposal_id = f.client.propose_withdrawal(&signers.get(0).unwrap(), &to, &400);
+    let proposal = f.client.get_proposal(&proposal_id).unwrap();
+    assert_eq!(proposal.approvals, vec![&f.env, signers.get(0).unwrap()]);
+    assert_eq!(f.token.balance(&to), 0);
+    assert_eq!(f.client.get_locked_amount(), 1000);
+
+    // Neither a non-signer nor the owner acting alone can move funds.
+    assert_eq!(f.client.try_approve_withdrawal(&f.owner, &proposal_id), Err(Ok(VaultError::Unauthorized)));
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &400), Err(Ok(VaultError::Unauthorized)));
+    assert_eq!(f.client.try_withdraw_own(&f.owner, &400), Err(Ok(VaultError::Unauthorized)));
+    assert!(!f.client.can_withdraw(&f.owner, &400));
+
+    // Closing the emptied vault clears out the proposal that never executed.
+    let drain_id = f.client.propose_withdrawal(&signers.get(1).unwrap(), &to, &1000);
+    f.client.approve_withdrawal(&signers.get(2).unwrap(), &drain_id);
+    assert!(f.client.close());
+    assert_eq!(f.client.get_proposal(&proposal_id), None);
+}
+
+#[test]
+fn test_multisig_duplicate_approval_counts_once() {
+    let (f, signers) = multisig_vault();
+    let to = Address::generate(&f.env);
+    let proposer = signers.get(0).unwrap();
+
+    let proposal_id = f.client.propose_withdrawal(&proposer, &to, &400);
+    // The proposer already approved, so approving again neither counts nor executes.
+    assert!(!f.client.approve_withdrawal(&proposer, &proposal_id));
+    assert!(!f.client.approve_withdrawal(&proposer, &proposal_id));
+    assert_eq!(f.client.get_proposal(&proposal_id).unwrap().approvals.len(), 1);
+    assert_eq!(f.token.balance(&to), 0);
+
+    assert!(f.client.approve_withdrawal(&signers.get(1).unwrap(), &proposal_id));
+    assert_eq!(f.token.balance(&to), 400);
+}
+
+#[test]
+fn test_signers_validation() {
+    let env = Env::default();
+    let client = VaultContractClient::new(&env, &env.register(VaultContract, ()));
+    let owner = Address::generate(&env);
+    let token_id = Address::generate(&env);
+    let a = Address::generate(&env);
+    let b = Address::generate(&env);
+    for (signers, threshold) in [
+        (vec![&env, a.clone(), b.clone()], 3u32),
+        (vec![&env, a.clone(), b.clone()], 0),
+        (vec![&env, a.clone(), a.clone()], 2),
+        (Vec::new(&env), 1),
+    ] {
+        assert_eq!(
+            client.try_initialize(&owner, &token_id, &0, &VaultOptions { signers, signer_threshold: threshold, ..default_options(&env) }),
+            Err(Ok(VaultError::InvalidSigners))
+        );
+    }
+
+    client.initialize(&owner, &token_id, &0, &VaultOptions { signers: vec![&env, a.clone(), b.clone()], signer_threshold: 2, ..default_options(&env) });
+    assert_eq!(client.get_signers(), (vec![&env, a, b], 2));
+    assert_eq!(VaultFixture::new(100).client.get_signers(), (Vec::new(&env), 0));
+}
+
+#[test]
+fn test_multisig_blocks_owner_side_exits() {
+    let (f, _) = multisig_vault();
+    let nft_id = f.env.register(MockNft, ());
+    let nft = MockNftClient::new(&f.env, &nft_id);
+    nft.mint(&f.owner, &7);
+    f.client.deposit_nft(&f.owner, &nft_id, &7);
+
+    // The owner can't get tokens or NFTs out alone by any other route either.
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::Unauthorized)));
+    assert_eq!(f.client.try_withdraw_nft(&f.owner, &nft_id, &7), Err(Ok(VaultError::Unauthorized)));
+    let new_token_id = create_token(&f.env);
+    let swap_id = f.env.register(TokenSwap, ());
+    assert_eq!(f.client.try_migrate_token(&new_token_id, &Some(swap_id)), Err(Ok(VaultError::Unauthorized)));
+    assert_eq!(f.token.balance(&f.contract_id), 1000);
+    assert!(f.client.is_nft_locked(&nft_id, &7));
+}
+
+#[test]
+fn test_multisig_blocks_beneficiary_and_recovery() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions {
+        signers: vec![env, Address::generate(env), Address::generate(env)],
+        signer_threshold: 2,
+        recovery_address: Some(Address::generate(env)),
+        ..default_options(env)
+    });
+    let recovery = f.client.get_recovery_address().unwrap();
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+
+    // The owner can't name a beneficiary to withdraw for them without an approval, and recovery is closed too.
+    let alt = Address::generate(&f.env);
+    assert_eq!(f.client.try_set_beneficiary(&alt), Err(Ok(VaultError::Unauthorized)));
+    assert_eq!(f.client.try_beneficiary_withdraw(&alt, &1000), Err(Ok(VaultError::NoBeneficiary)));
+    assert_eq!(f.client.try_recovery_withdraw(&recovery, &1000), Err(Ok(VaultError::Unauthorized)));
+    assert!(!f.client.can_withdraw(&recovery, &1000));
+    assert_eq!(f.token.balance(&f.contract_id), 1000);
+}
+
+#[test]
+fn test_queue_and_execute_action() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { action_delay: 3600, ..default_options(env) });
+    let action_id = BytesN::from_array(&f.env, &[7; 32]);
+
+    // An action can't be queued for sooner than the delay allows, nor executed before it is queued.
+    assert_eq!(f.client.try_queue_action(&action_id, &3599), Err(Ok(VaultError::ActionNotReady)));
+    assert_eq!(f.client.try_execute_action(&action_id), Err(Ok(VaultError::ActionNotQueued)));
+
+    f.client.queue_action(&action_id, &3600);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("act_queue"), action_id.clone()).into_val(&f.env), 3600u64.into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_queued_action(&action_id), Some(3600));
+
+    f.warp_to(3599);
+    assert_eq!(f.client.try_execute_action(&action_id), Err(Ok(VaultError::ActionNotReady)));
+
+    f.warp_to(3600);
+    f.client.execute_action(&action_id);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("act_exec"), action_id.clone()).into_val(&f.env), ().into_val(&f.env))]
+    );
+    // Each queued action runs once.
+    assert_eq!(f.client.get_queued_action(&action_id), None);
+    assert_eq!(f.client.try_execute_action(&action_id), Err(Ok(VaultError::ActionNotQueued)));
+}
+
+#[test]
+fn test_upgrade_waits_for_timelock() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { action_delay: 3600, ..default_options(env) });
+    let new_wasm_hash = f.env.deployer().upload_contract_wasm(vault_v2::WASM);
+
+    assert_eq!(f.client.try_upgrade(&new_wasm_hash), Err(Ok(VaultError::ActionNotQueued)));
+    f.client.queue_action(&new_wasm_hash, &3600);
+    assert_eq!(f.client.try_upgrade(&new_wasm_hash), Err(Ok(VaultError::ActionNotReady)));
+
+    f.warp_to(3600);
+    f.client.upgrade(&new_wasm_hash);
+    let v2 = vault_v2::Client::new(&f.env, &f.contract_id);
+    assert_eq!(v2.v2_only(), symbol_short!("v2"));
+}
+
+#[test]
+fn test_deposit_idempotent_moves_tokens_once() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    let other = f.funded_user(1000);
+    let request_id = BytesN::from_array(&f.env, &[1; 32]);
+
+    assert_eq!(f.client.deposit_idempotent(&user, &400, &request_id), 400);
+    assert_eq!(f.token.balance(&user), 600);
+
+    // A retry returns the first result without touching anything.
+    assert_eq!(f.client.deposit_idempotent(&user, &400, &request_id), 400);
+    assert_eq!(vault_events(&f.env, &f.contract_id), Vec::new(&f.env));
+    assert_eq!(f.token.balance(&user), 600);
+    assert_eq!(f.client.get_balance(&user), 400);
+    assert_eq!(f.client.get_locked_amount(), 400);
+    assert_eq!(f.client.get_deposit_seq(), 1);
+
+    // A fresh id deposits again, and another depositor's ids are their own.
+    assert_eq!(f.client.deposit_idempotent(&user, &100, &BytesN::from_array(&f.env, &[2; 32])), 500);
+    assert_eq!(f.client.deposit_idempotent(&other, &100, &request_id), 600);
+    assert_eq!(f.token.balance(&other), 900);
+}
+
+#[test]
+fn test_deposit_window() {
+    let f = VaultFixture::with_options(1000, |env| VaultOptions { deposit_open_from: 100, deposit_open_until: 200, ..default_options(env) });
+    let user = f.funded_user(1000);
+
+    f.warp_to(99);
+    assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::DepositWindowClosed)));
+
+    // Both ends of the window are inclusive.
+    f.warp_to(100);
+    f.client.deposit(&f.token_id, &user, &100);
+    f.warp_to(200);
+    f.client.deposit(&f.token_id, &user, &100);
+
+    f.warp_to(201);
+    assert_eq!(f.client.try_deposit(&f.token_id, &user, &100), Err(Ok(VaultError::DepositWindowClosed)));
+    assert_eq!(f.client.try_deposit_batch(&vec![&f.env, (user.clone(), 100i128)]), Err(Ok(VaultError::DepositWindowClosed)));
+    assert_eq!(f.client.get_locked_amount(), 200);
+    assert_eq!(f.token.balance(&user), 800);
+}
+
+#[test]
+fn test_deposit_window_open_indefinitely() {
+    let f = VaultFixture::with_options(1000, |env| VaultOptions { deposit_open_from: 100, ..default_options(env) });
+    let user = f.funded_user(1000);
+    f.warp_to(1_000_000);
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.get_locked_amount(), 100);
+}
+
+#[test]
+fn test_deposit_window_must_open_before_it_closes() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let client = VaultContractClient::new(&env, &env.register(VaultContract, ()));
+    let options = VaultOptions { deposit_open_from: 200, deposit_open_until: 100, ..default_options(&env) };
+    assert_eq!(client.try_initialize(&Address::generate(&env), &create_token(&env), &1000, &options), Err(Ok(VaultError::InvalidSchedule)));
+}
+
+#[test]
+fn test_min_hold_after_deposit() {
+    // The vault itself is open from the start, so only the hold stands in the way.
+    let f = VaultFixture::with_options(0, |env| VaultOptions { min_hold_seconds: 100, ..default_options(env) });
+    let user = f.funded_user(1000);
+
+    f.warp_to(10);
+    f.client.deposit(&f.token_id, &user, &500);
+    f.warp_to(109);
+    assert_eq!(f.client.try_withdraw_own(&user, &500), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.withdrawable_for(&user), 0);
+
+    f.warp_to(110);
+    f.client.withdraw_own(&user, &200);
+    assert_eq!(f.token.balance(&user), 700);
+
+    // A new deposit restarts the hold on the whole balance.
+    f.client.deposit(&f.token_id, &user, &100);
+    assert_eq!(f.client.try_withdraw_own(&user, &100), Err(Ok(VaultError::StillLocked)));
+    f.warp_to(210);
+    f.client.withdraw_own(&user, &400);
+    assert_eq!(f.token.balance(&user), 1000);
+}
+
+#[test]
+fn test_min_hold_ignores_sponsored_deposits() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { min_hold_seconds: 100, ..default_options(env) });
+    let user = f.funded_user(500);
+    let attacker = f.funded_user(10);
+    f.warp_to(910);
+    f.client.deposit(&f.token_id, &user, &500);
+
+    // A token credited by someone else just before the hold ends doesn't restart it.
+    f.warp_to(1009);
+    f.client.deposit_for(&attacker, &user, &1);
+    f.warp_to(1010);
+    f.client.withdraw_own(&user, &501);
+    assert_eq!(f.token.balance(&user), 501);
+}
+
+#[test]
+fn test_healthcheck() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { fee_bps: 100, ..default_options(env) });
+    let owner = f.funded_owner(1100);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert_eq!(f.client.healthcheck(), (true, 0));
+
+    // Accrued fees are part of the books, so withdrawing keeps the vault consistent.
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &500);
+    assert_eq!(f.client.healthcheck(), (true, 0));
+
+    // A direct transfer shows up as a surplus until reconciled.
+    f.token.transfer(&owner, &f.contract_id, &100);
+    assert_eq!(f.client.healthcheck(), (false, 100));
+    f.client.reconcile();
+    assert_eq!(f.client.healthcheck(), (true, 0));
+}
+
+#[test]
+fn test_healthcheck_uninitialized() {
+    let env = Env::default();
+    let client = VaultContractClient::new(&env, &env.register(VaultContract, ()));
+    assert_eq!(client.healthcheck(), (false, 0));
+}
+
+#[test]
+fn test_migrate_token_empty_vault() {
+    let f = VaultFixture::new(100);
+    let new_token_id = create_token(&f.env);
+
+    f.client.migrate_token(&new_token_id, &None);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("tok_mig"), f.token_id.clone(), new_token_id.clone()).into_val(&f.env), 0i128.into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_token_id(), new_token_id);
+
+    // Deposits now go through the new token.
+    let user = Address::generate(&f.env);
+    StellarAssetClient::new(&f.env, &new_token_id).mint(&user, &100);
+    f.client.deposit(&new_token_id, &user, &100);
+    assert_eq!(TokenClient::new(&f.env, &new_token_id).balance(&f.contract_id), 100);
+}
+
+#[test]
+fn test_migrate_token_rejects_locked_vault() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(500);
+    f.client.deposit(&f.token_id, &user, &500);
+    let new_token_id = create_token(&f.env);
+    let swap_id = f.env.register(TokenSwap, ());
+
+    assert_eq!(f.client.try_migrate_token(&new_token_id, &Some(swap_id)), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.get_token_id(), f.token_id);
+    assert_eq!(f.token.balance(&f.contract_id), 500);
+}
+
+#[test]
+fn test_migrate_token_swaps_held_balance() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(500);
+    f.client.deposit(&f.token_id, &user, &500);
+    let new_token_id = create_token(&f.env);
+    let new_token = TokenClient::new(&f.env, &new_token_id);
+    let swap_id = f.env.register(TokenSwap, ());
+    TokenSwapClient::new(&f.env, &swap_id).set_new_token(&new_token_id);
+    StellarAssetClient::new(&f.env, &new_token_id).mint(&swap_id, &500);
+
+    f.warp_to(100);
+    // Holding tokens without a swap contract to exchange them isn't allowed.
+    assert_eq!(f.client.try_migrate_token(&new_token_id, &None), Err(Ok(VaultError::VaultNotEmpty)));
+
+    f.client.migrate_token(&new_token_id, &Some(swap_id.clone()));
+    assert_eq!(f.token.balance(&f.contract_id), 0);
+    assert_eq!(f.token.balance(&swap_id), 500);
+    assert_eq!(new_token.balance(&f.contract_id), 500);
+    assert_eq!(f.client.get_locked_amount(), 500);
+    assert_eq!(f.client.healthcheck(), (true, 0));
+
+    f.client.withdraw_own(&user, &500);
+    assert_eq!(new_token.balance(&user), 500);
+}
+
+#[test]
+fn test_withdrawal_queue_reserves_and_fulfills() {
+    let f = VaultFixture::new(100);
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+
+    let to = Address::generate(&f.env);
+    let request_id = f.client.request_withdrawal(&to, &600);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("wd_req"), to.clone()).into_val(&f.env), (1u64, 600i128).into_val(&f.env))]
+    );
+    assert_eq!(f.client.get_withdrawal_request(&request_id), Some((to.clone(), 600)));
+    assert_eq!(f.client.get_pending_withdrawals(), 600);
+    // Requests reserve their share of what the owner may withdraw.
+    assert_eq!(f.client.try_request_withdrawal(&to, &401), Err(Ok(VaultError::InsufficientFunds)));
+
+    // Nothing has left the vault yet.
+    assert_eq!(f.client.get_locked_amount(), 1000);
+    assert_eq!(f.token.balance(&f.contract_id), 1000);
+
+    // Every token is on hand, so anyone can fulfill the request straight away.
+    f.client.fulfill_withdrawal(&request_id);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("wd_fill"), to.clone()).into_val(&f.env), (600i128, 400i128, 1u64).into_val(&f.env))]
+    );
+    assert_eq!(f.token.balance(&to), 600);
+    assert_eq!(f.client.get_locked_amount(), 400);
+    assert_eq!(f.client.get_pending_withdrawals(), 0);
+    assert_eq!(f.client.get_withdrawal_request(&request_id), None);
+    assert_eq!(f.client.try_fulfill_withdrawal(&request_id), Err(Ok(VaultError::ProposalNotFound)));
+}
+
+#[test]
+fn test_pending_requests_reserve_locked_funds() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { inactivity_window: 1000, penalty_bps: 100, ..default_options(env) });
+    let beneficiary = Address::generate(&f.env);
+    f.client.set_beneficiary(&beneficiary);
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+    let request_id = f.client.request_withdrawal(&f.owner, &600);
+
+    // Nothing else can spend what the request has reserved.
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &401), Err(Ok(VaultError::InsufficientFunds)));
+    let to_many = vec![&f.env, (f.owner.clone(), 200i128), (f.owner.clone(), 201i128)];
+    assert_eq!(f.client.try_withdraw_batch(&to_many), Err(Ok(VaultError::InsufficientFunds)));
+    // Not even an emergency withdrawal after the lock has been pushed out again.
+    f.client.extend_lock(&2000);
+    assert_eq!(f.client.try_emergency_withdraw(&f.owner, &401), Err(Ok(VaultError::InsufficientFunds)));
+    f.warp_to(2000);
+    assert_eq!(f.client.try_beneficiary_withdraw(&beneficiary, &401), Err(Ok(VaultError::InsufficientFunds)));
+    assert_eq!(f.client.preview_withdraw(&400), (400, 0));
+    f.client.withdraw(&f.token_id, &f.owner, &400);
+
+    // So the request can still be paid in full.
+    f.client.fulfill_withdrawal(&request_id);
+    assert_eq!(f.token.balance(&f.owner), 1000);
+    assert_eq!(f.client.get_locked_amount(), 0);
+}
+
+#[test]
+fn test_request_withdrawal_respects_lock() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &1000);
+    assert_eq!(f.client.try_request_withdrawal(&f.owner, &100), Err(Ok(VaultError::StillLocked)));
+}
+
+#[test]
+fn test_snapshot_freezes_balances() {
+    let f = VaultFixture::new(100);
+    let alice = f.funded_user(600);
+    let bob = f.funded_user(400);
+    f.client.deposit(&f.token_id, &alice, &600);
+    f.client.deposit(&f.token_id, &bob, &400);
+
+    assert_eq!(f.client.try_snapshot(), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.get_snapshot(&alice), None);
+
+    f.warp_to(100);
+    f.client.snapshot();
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("snapshot"),).into_val(&f.env), (2u32, 100u64).into_val(&f.env))]
+    );
+
+    // Later withdrawals and deposits change live balances but not the snapshot.
+    f.client.withdraw_own(&alice, &600);
+    let carol = f.funded_user(300);
+    f.client.deposit(&f.token_id, &carol, &300);
+    assert_eq!(f.client.get_balance(&alice), 0);
+    assert_eq!(f.client.get_snapshot(&alice), Some(600));
+    assert_eq!(f.client.get_snapshot(&bob), Some(400));
+    assert_eq!(f.client.get_snapshot(&carol), Some(0));
+
+    f.warp_to(200);
+    assert_eq!(f.client.try_snapshot(), Err(Ok(VaultError::SnapshotTaken)));
+    assert_eq!(f.client.get_snapshot(&alice), Some(600));
+}
+
+#[test]
+fn test_balance_changed_events() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { balance_events: true, ..default_options(env) });
+    let user = f.funded_user(1000);
+
+    f.client.deposit(&f.token_id, &user, &400);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![
+            &f.env,
+            (f.contract_id.clone(), (symbol_short!("bal_chg"), user.clone()).into_val(&f.env), (0i128, 400i128, BALANCE_REASON_DEPOSIT).into_val(&f.env)),
+            (f.contract_id.clone(), (symbol_short!("deposit"), user.clone()).into_val(&f.env), (400i128, 400i128, 1u64).into_val(&f.env))
+        ]
+    );
+    f.client.deposit(&f.token_id, &user, &100);
+    let data: (i128, i128, u32) = TryFromVal::try_from_val(&f.env, &vault_events(&f.env, &f.contract_id).get(0).unwrap().2).unwrap();
+    assert_eq!(data, (400, 500, BALANCE_REASON_DEPOSIT));
+
+    f.warp_to(100);
+    f.client.withdraw_own(&user, &150);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![
+            &f.env,
+            (f.contract_id.clone(), (symbol_short!("bal_chg"), user.clone()).into_val(&f.env), (500i128, 350i128, BALANCE_REASON_WITHDRAW).into_val(&f.env)),
+            (f.contract_id.clone(), (symbol_short!("w_own"), user.clone()).into_val(&f.env), (150i128, 350i128).into_val(&f.env))
+        ]
+    );
+}
+
+#[test]
+fn test_balance_changed_events_off_by_default() {
+    let f = VaultFixture::new(100);
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &400);
+    assert_eq!(vault_events(&f.env, &f.contract_id).len(), 1);
+}
+
+#[test]
+fn test_oracle_unlock_condition() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { oracle: Some(Address::generate(env)), ..default_options(env) });
+    let oracle = f.client.get_config().oracle.unwrap();
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert!(!f.client.is_unlock_condition_met());
+
+    // The time has passed, but the oracle hasn't reported the condition yet.
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &100), Err(Ok(VaultError::StillLocked)));
+
+    f.client.set_unlock_condition(&true);
+    assert_eq!(
+        vault_events(&f.env, &f.contract_id),
+        vec![&f.env, (f.contract_id.clone(), (symbol_short!("unl_cond"), oracle.clone()).into_val(&f.env), true.into_val(&f.env))]
+    );
+    assert_eq!(f.client.withdraw(&f.token_id, &f.owner, &100), 900);
+
+    // The oracle can take the condition back, which closes withdrawals again.
+    f.client.set_unlock_condition(&false);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &100), Err(Ok(VaultError::StillLocked)));
+}
+
+#[test]
+fn test_oracle_condition_gates_every_owner_exit() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { oracle: Some(Address::generate(env)), ..default_options(env) });
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &1000);
+    let other_token_id = create_token(&f.env);
+    StellarAssetClient::new(&f.env, &other_token_id).mint(&user, &300);
+    f.client.add_allowed_token(&other_token_id);
+    f.client.deposit(&other_token_id, &user, &300);
+    let nft_id = f.env.register(MockNft, ());
+    MockNftClient::new(&f.env, &nft_id).mint(&f.owner, &7);
+    f.client.deposit_nft(&f.owner, &nft_id, &7);
+    let new_token_id = create_token(&f.env);
+    let swap_id = f.env.register(TokenSwap, ());
+    TokenSwapClient::new(&f.env, &swap_id).set_new_token(&new_token_id);
+    StellarAssetClient::new(&f.env, &new_token_id).mint(&swap_id, &1000);
+
+    // Past the unlock time but with the condition unset, nothing leaves by any route.
+    f.warp_to(100);
+    assert_eq!(f.client.try_withdraw(&other_token_id, &f.owner, &300), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.try_withdraw_nft(&f.owner, &nft_id, &7), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.try_sweep(&f.owner), Err(Ok(VaultError::StillLocked)));
+    assert_eq!(f.client.try_migrate_token(&new_token_id, &Some(swap_id.clone())), Err(Ok(VaultError::StillLocked)));
+
+    f.client.set_unlock_condition(&true);
+    assert_eq!(f.client.withdraw(&other_token_id, &f.owner, &300), 0);
+    f.client.withdraw_nft(&f.owner, &nft_id, &7);
+    f.client.migrate_token(&new_token_id, &Some(swap_id));
+    assert_eq!(f.client.get_token_id(), new_token_id);
+}
+
+#[test]
+fn test_oracle_condition_needs_time_too() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { oracle: Some(Address::generate(env)), ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+
+    // A met condition doesn't open the vault before its unlock time.
+    f.client.set_unlock_condition(&true);
+    assert_eq!(f.client.try_withdraw(&f.token_id, &f.owner, &100), Err(Ok(VaultError::StillLocked)));
+    f.warp_to(100);
+    f.client.withdraw(&f.token_id, &f.owner, &100);
+
+    // Without an oracle the condition is always met and nobody can report it.
+    let plain = VaultFixture::new(100);
+    assert!(plain.client.is_unlock_condition_met());
+    assert_eq!(plain.client.try_set_unlock_condition(&true), Err(Ok(VaultError::Unauthorized)));
+}
+
+#[test]
+fn test_preview_withdraw_matches_withdraw() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { fee_bps: 333, min_withdrawal: 10, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    assert_eq!(f.client.try_preview_withdraw(&400), Err(Ok(VaultError::StillLocked)));
+
+    f.warp_to(100);
+    assert_eq!(f.client.preview_withdraw(&400), (387, 13));
+    f.client.withdraw(&f.token_id, &f.owner, &400);
+    assert_eq!(f.token.balance(&f.owner), 387);
+    assert_eq!(f.client.get_accrued_fees(), 13);
+
+    // The preview rounds a final withdrawal up the same way withdraw does.
+    assert_eq!(f.client.preview_withdraw(&595), (581, 19));
+    f.client.withdraw(&f.token_id, &f.owner, &595);
+    assert_eq!(f.token.balance(&f.owner), 387 + 581);
+    assert_eq!(f.client.get_accrued_fees(), 13 + 19);
+    assert_eq!(f.client.try_preview_withdraw(&1), Err(Ok(VaultError::InsufficientFunds)));
+}
+
+#[test]
+fn test_preview_withdraw_applies_vesting() {
+    let f = VaultFixture::with_options(0, |env| VaultOptions { vesting_start: 0, vesting_duration: 1000, ..default_options(env) });
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+
+    f.warp_to(250);
+    assert_eq!(f.client.preview_withdraw(&250), (250, 0));
+    assert_eq!(f.client.try_preview_withdraw(&251), Err(Ok(VaultError::NotYetVested)));
+    f.client.withdraw(&f.token_id, &f.owner, &250);
+    assert_eq!(f.token.balance(&f.owner), 250);
+}
+
+#[test]
+fn test_deposit_fee_paid_to_collector() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { deposit_fee_bps: 250, ..default_options(env) });
+    let fee_collector = f.client.get_config().fee_collector;
+    assert_eq!(f.client.get_deposit_fee_bps(), 250);
+    let user = f.funded_user(1000);
+
+    // 2.5% of 1000 goes to the collector and the depositor is credited the rest.
+    assert_eq!(f.client.deposit(&f.token_id, &user, &1000), 975);
+    assert_eq!(f.client.get_balance(&user), 975);
+    assert_eq!(f.token.balance(&fee_collector), 25);
+    assert_eq!(f.token.balance(&f.contract_id), 975);
+    assert_eq!(f.client.healthcheck(), (true, 0));
+}
+
+#[test]
+fn test_deposit_fee_retained_in_vault() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { deposit_fee_bps: 250, deposit_fee_retained: true, ..default_options(env) });
+    let fee_collector = f.client.get_config().fee_collector;
+    let user = f.funded_user(1000);
+
+    // The fee stays locked for the owner instead of being credited to the depositor.
+    assert_eq!(f.client.deposit(&f.token_id, &user, &1000), 1000);
+    assert_eq!(f.client.get_balance(&user), 975);
+    assert_eq!(f.token.balance(&fee_collector), 0);
+    assert_eq!(f.token.balance(&f.contract_id), 1000);
+}
+
+#[test]
+fn test_zero_deposit_fee_credits_in_full() {
+    let f = VaultFixture::new(100);
+    let fee_collector = f.client.get_config().fee_collector;
+    assert_eq!(f.client.get_deposit_fee_bps(), 0);
+    let user = f.funded_user(1000);
+    assert_eq!(f.client.deposit(&f.token_id, &user, &1000), 1000);
+    assert_eq!(f.client.get_balance(&user), 1000);
+    assert_eq!(f.token.balance(&fee_collector), 0);
+
+    // A fee of 100% or more is refused at initialize, since every deposit would credit nothing.
+    let env = Env::default();
+    let client = VaultContractClient::new(&env, &env.register(VaultContract, ()));
+    for deposit_fee_bps in [10_000, 10_001] {
+        assert_eq!(
+            client.try_initialize(&Address::generate(&env), &Address::generate(&env), &0, &VaultOptions { deposit_fee_bps, ..default_options(&env) }),
+            Err(Ok(VaultError::InvalidBasisPoints))
+        );
+    }
+    // Just under 100%, a deposit still credits something, and repeat deposits list the depositor once.
+    let f = VaultFixture::with_options(100, |env| VaultOptions { deposit_fee_bps: 9_999, ..default_options(env) });
+    let user = f.funded_user(2);
+    assert_eq!(f.client.deposit(&f.token_id, &user, &1), 1);
+    assert_eq!(f.client.deposit(&f.token_id, &user, &1), 2);
+    assert_eq!(f.client.get_depositors(), vec![&f.env, user.clone()]);
+}
+
+#[test]
+fn test_current_apr() {
+    // 10 / REWARD_RATE_SCALE per second is 0.31536 tokens per token over a year, or 3153.6 bps.
+    let f = VaultFixture::with_options(100, |env| VaultOptions { reward_rate: 10, ..default_options(env) });
+    assert_eq!(f.client.current_apr(), 0);
+    let user = f.funded_user(1000);
+    f.client.deposit(&f.token_id, &user, &1000);
+    assert_eq!(f.client.current_apr(), 3153);
+
+    // The rate is per locked token, so more tokens locked doesn't change it.
+    let other = f.funded_user(5000);
+    f.client.deposit(&f.token_id, &other, &5000);
+    assert_eq!(f.client.current_apr(), 3153);
+
+    let slow = VaultFixture::with_options(100, |env| VaultOptions { reward_rate: 1, ..default_options(env) });
+    let user = slow.funded_user(1000);
+    slow.client.deposit(&slow.token_id, &user, &1000);
+    assert_eq!(slow.client.current_apr(), 315);
+
+    // A rate too high to express in u32 basis points saturates.
+    let fast = VaultFixture::with_options(100, |env| VaultOptions { reward_rate: u64::MAX, ..default_options(env) });
+    let user = fast.funded_user(1000);
+    fast.client.deposit(&fast.token_id, &user, &1000);
+    assert_eq!(fast.client.current_apr(), u32::MAX);
+
+    // Without rewards the APR is 0.
+    let plain = VaultFixture::new(100);
+    let user = plain.funded_user(1000);
+    plain.client.deposit(&plain.token_id, &user, &1000);
+    assert_eq!(plain.client.current_apr(), 0);
+}
+
+#[test]
+fn test_vault_label() {
+    let f = VaultFixture::with_options(100, |env| VaultOptions { label: Some(Symbol::new(env, "college_fund")), ..default_options(env) });
+    assert_eq!(f.client.get_label(), Symbol::new(&f.env, "college_fund"));
+
+    // The label has no effect on the vault's behaviour.
+    let owner = f.funded_owner(1000);
+    f.client.deposit(&f.token_id, &owner, &1000);
+    f.warp_to(100);
+    assert_eq!(f.client.withdraw(&f.token_id, &f.owner, &1000), 0);
+
+    let unlabelled = VaultFixture::new(100);
+    assert_eq!(unlabelled.client.get_label(), symbol_short!(""));
 }
@@ -0,0 +1,54 @@
+#![no_std] // No standard library for embedded-like environments
+// A stand-in "v2" of the vault used by the vault's upgrade test.
+// It keeps the vault's storage layout and adds one new function, so the test can upgrade to it
+// and check that state written by v1 is still readable and can be migrated. Rebuild the committed wasm with:
+//   stellar contract build --package vault_v2_fixture
+//   cp target/wasm32v1-none/release/vault_v2_fixture.wasm contracts/vault/testdata/
+use soroban_sdk::{contract, contractimpl, contracttype, symbol_short, Address, Env, Symbol};
+
+// Must match the variant names of the vault's DataKey, since that is how the keys are encoded in storage.
+#[contracttype]
+pub enum DataKey {
+    Owner,
+    LockedAmount,
+    Version,
+}
+
+// The version this stand-in reports; one above the vault's own VERSION.
+const VERSION: u32 = 2;
+
+#[contract]
+pub struct VaultV2Fixture;
+
+#[contractimpl]
+impl VaultV2Fixture {
+    /// Returns the owner written by the previous version.
+    pub fn get_owner(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Owner)
+    }
+
+    /// Returns the locked amount written by the previous version.
+    pub fn get_locked_amount(env: Env) -> i128 {
+        env.storage().persistent().get(&DataKey::LockedAmount).unwrap_or(0)
+    }
+
+    /// Returns the version of this code.
+    pub fn get_version(_env: Env) -> u32 {
+        VERSION
+    }
+
+    /// Returns the version written by the previous version's initialize, or by migrate.
+    pub fn get_stored_version(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::Version).unwrap_or(0)
+    }
+
+    /// Records this code's version in storage, as the real vault's migrate does.
+    pub fn migrate(env: Env) {
+        env.storage().instance().set(&DataKey::Version, &VERSION);
+    }
+
+    /// A function that only exists in v2.
+    pub fn v2_only(_env: Env) -> Symbol {
+        symbol_short!("v2")
+    }
+}